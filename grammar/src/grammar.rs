@@ -5,8 +5,8 @@ use std::time::SystemTime;
 use std::{
     collections::HashSet,
     path::{Path, PathBuf},
-    process::Command,
-    sync::mpsc::channel,
+    process::{Command, Output},
+    sync::mpsc::{channel, Receiver},
 };
 use tempfile::TempPath;
 use tree_sitter::Language;
@@ -34,12 +34,18 @@ pub(crate) enum GrammarSelection {
     Except { except: HashSet<String> },
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct GrammarConfiguration {
     #[serde(rename = "name")]
     pub grammar_id: String,
     pub(crate) source: GrammarSource,
+    /// Failing to fetch or build an experimental grammar is only a warning.
+    #[serde(default)]
+    pub(crate) experimental: bool,
+    /// Passed to the C compiler, after the default flags.
+    #[serde(default)]
+    pub(crate) compile_flags: Vec<String>,
 }
 
 impl GrammarConfiguration {
@@ -51,11 +57,38 @@ impl GrammarConfiguration {
                 revision: revision.to_string(),
                 subpath,
             },
+            experimental: false,
+            compile_flags: Vec::new(),
+        }
+    }
+
+    pub fn local(id: &str, path: &Path) -> Self {
+        Self {
+            grammar_id: id.to_string(),
+            source: GrammarSource::Local {
+                path: path.to_string_lossy().to_string(),
+            },
+            experimental: false,
+            compile_flags: Vec::new(),
+        }
+    }
+
+    pub fn experimental(self, experimental: bool) -> Self {
+        Self {
+            experimental,
+            ..self
+        }
+    }
+
+    pub fn compile_flags(self, compile_flags: Vec<String>) -> Self {
+        Self {
+            compile_flags,
+            ..self
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase", untagged)]
 pub enum GrammarSource {
     Local {
@@ -81,9 +114,7 @@ pub fn get_language(name: &str) -> Result<Language> {
 #[cfg(not(target_arch = "wasm32"))]
 pub fn get_language(name: &str) -> Result<Language> {
     use libloading::{Library, Symbol};
-    let mut rel_library_path = PathBuf::new().join("grammars").join(name);
-    rel_library_path.set_extension(DYLIB_EXTENSION);
-    let library_path = crate::runtime_file(&rel_library_path);
+    let library_path = library_path(name);
 
     let library = unsafe { Library::new(&library_path) }.map_err(|err| {
         anyhow::anyhow!(
@@ -116,7 +147,8 @@ pub fn fetch_grammars(mut grammars: Vec<GrammarConfiguration>) -> Result<()> {
     grammars.retain(|grammar| !matches!(grammar.source, GrammarSource::Local { .. }));
 
     println!("Fetching {} grammars", grammars.len());
-    let results = run_parallel(grammars, fetch_grammar);
+    let experimental_grammar_ids = experimental_grammar_ids(&grammars);
+    let results = run_parallel(grammars, |grammar| fetch_grammar(grammar, true));
 
     let mut errors = Vec::new();
     let mut git_updated = Vec::new();
@@ -153,23 +185,16 @@ pub fn fetch_grammars(mut grammars: Vec<GrammarConfiguration>) -> Result<()> {
         }
     }
 
-    if !errors.is_empty() {
-        let len = errors.len();
-        for (i, (grammar, error)) in errors.into_iter().enumerate() {
-            println!("Failure {}/{len}: {grammar} {error}", i + 1);
-        }
-        bail!("{len} grammars failed to fetch");
-    }
-
-    Ok(())
+    report_failures("fetch", errors, &experimental_grammar_ids)
 }
 
 pub fn build_grammars(target: Option<String>, grammars: Vec<GrammarConfiguration>) -> Result<()> {
     ensure_git_is_available()?;
 
     println!("Building {} grammars", grammars.len());
+    let experimental_grammar_ids = experimental_grammar_ids(&grammars);
     let results = run_parallel(grammars, move |grammar| {
-        build_grammar(grammar, target.as_deref())
+        build_grammar(grammar, target.as_deref(), true)
     });
 
     let mut errors = Vec::new();
@@ -195,17 +220,120 @@ pub fn build_grammars(target: Option<String>, grammars: Vec<GrammarConfiguration
         println!("\t{built:?}");
     }
 
+    report_failures("build", errors, &experimental_grammar_ids)
+}
+
+fn experimental_grammar_ids(grammars: &[GrammarConfiguration]) -> HashSet<String> {
+    grammars
+        .iter()
+        .filter(|grammar| grammar.experimental)
+        .map(|grammar| grammar.grammar_id.clone())
+        .collect()
+}
+
+/// Failures of experimental grammars are printed as warnings,
+/// so that they do not fail the fetching or building of the other grammars.
+fn report_failures(
+    action: &str,
+    errors: Vec<(String, anyhow::Error)>,
+    experimental_grammar_ids: &HashSet<String>,
+) -> Result<()> {
+    let (warnings, errors): (Vec<_>, Vec<_>) = errors
+        .into_iter()
+        .partition(|(grammar_id, _)| experimental_grammar_ids.contains(grammar_id));
+
+    for (grammar_id, error) in warnings {
+        println!("Warning: experimental grammar {grammar_id} failed to {action}: {error}");
+    }
+
     if !errors.is_empty() {
         let len = errors.len();
         for (i, (grammar_id, error)) in errors.into_iter().enumerate() {
             println!("Failure {}/{len}: {grammar_id} {error}", i + 1);
         }
-        bail!("{len} grammars failed to build");
+        bail!("{len} grammars failed to {action}");
     }
 
     Ok(())
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrammarProgress {
+    pub grammar_id: String,
+    pub phase: GrammarPhase,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrammarPhase {
+    Fetch,
+    Compile,
+    Done { library_bytes: u64 },
+    Failed { error: String },
+}
+
+/// Fetch and build `grammars` in the background, using at most `max_workers` threads.
+///
+/// Unlike `fetch_grammars` and `build_grammars`, this returns immediately,
+/// and the progress of each grammar is sent to the returned receiver,
+/// which is disconnected once every grammar is either done or failed.
+pub fn fetch_and_build_grammars_with_progress(
+    grammars: Vec<GrammarConfiguration>,
+    target: Option<String>,
+    max_workers: usize,
+) -> Result<Receiver<GrammarProgress>> {
+    ensure_git_is_available()?;
+
+    Ok(run_parallel_with_progress(
+        grammars,
+        max_workers,
+        move |grammar, report| {
+            report(GrammarPhase::Fetch);
+            fetch_grammar(grammar.clone(), false)?;
+            report(GrammarPhase::Compile);
+            build_grammar(grammar.clone(), target.as_deref(), false)?;
+            Ok(fs::metadata(library_path(&grammar.grammar_id))?.len())
+        },
+    ))
+}
+
+/// `job` returns the size of the built library in bytes.
+fn run_parallel_with_progress<F>(
+    grammars: Vec<GrammarConfiguration>,
+    max_workers: usize,
+    job: F,
+) -> Receiver<GrammarProgress>
+where
+    F: Fn(&GrammarConfiguration, &dyn Fn(GrammarPhase)) -> Result<u64> + Send + 'static + Clone,
+{
+    let pool = threadpool::Builder::new()
+        .num_threads(max_workers.max(1))
+        .build();
+    let (tx, rx) = channel();
+
+    for grammar in grammars {
+        let tx = tx.clone();
+        let job = job.clone();
+
+        pool.execute(move || {
+            let report = |phase: GrammarPhase| {
+                // Ignore any SendErrors, the receiver might have lost interest in the progress.
+                let _ = tx.send(GrammarProgress {
+                    grammar_id: grammar.grammar_id.clone(),
+                    phase,
+                });
+            };
+            match job(&grammar, &report) {
+                Ok(library_bytes) => report(GrammarPhase::Done { library_bytes }),
+                Err(error) => report(GrammarPhase::Failed {
+                    error: format!("{error:#}"),
+                }),
+            }
+        });
+    }
+
+    rx
+}
+
 fn run_parallel<F, Res>(grammars: Vec<GrammarConfiguration>, job: F) -> Vec<(String, Result<Res>)>
 where
     F: Fn(GrammarConfiguration) -> Result<Res> + Send + 'static + Clone,
@@ -236,17 +364,27 @@ enum FetchStatus {
     NonGit,
 }
 
-fn fetch_grammar(grammar: GrammarConfiguration) -> Result<FetchStatus> {
+/// Progress messages are printed to stdout for the CLI,
+/// but logged otherwise, because stdout belongs to the TUI while the editor is running.
+fn report_progress(print_progress: bool, message: String) {
+    if print_progress {
+        println!("{message}");
+    } else {
+        log::info!("{message}");
+    }
+}
+
+fn fetch_grammar(grammar: GrammarConfiguration, print_progress: bool) -> Result<FetchStatus> {
     if let GrammarSource::Git {
         remote, revision, ..
     } = grammar.source
     {
-        let grammar_dir = crate::runtime_dir()
-            .join("grammars")
-            .join("sources")
-            .join(&grammar.grammar_id);
+        let grammar_dir = source_dir(&grammar.grammar_id);
 
-        println!("Fetching grammar to = {}", grammar_dir.display());
+        report_progress(
+            print_progress,
+            format!("Fetching grammar to = {}", grammar_dir.display()),
+        );
 
         fs::create_dir_all(&grammar_dir).context(format!(
             "Could not create grammar directory {grammar_dir:?}"
@@ -326,25 +464,30 @@ where
     }
 }
 
+#[derive(Debug, PartialEq, Eq)]
 enum BuildStatus {
     AlreadyBuilt,
     Built,
 }
 
-fn build_grammar(grammar: GrammarConfiguration, target: Option<&str>) -> Result<BuildStatus> {
+fn build_grammar(
+    grammar: GrammarConfiguration,
+    target: Option<&str>,
+    print_progress: bool,
+) -> Result<BuildStatus> {
     let grammar_dir = if let GrammarSource::Local { path } = &grammar.source {
         PathBuf::from(&path)
     } else {
-        crate::runtime_dir()
-            .join("grammars")
-            .join("sources")
-            .join(&grammar.grammar_id)
+        source_dir(&grammar.grammar_id)
     };
 
-    println!(
-        "Building grammar: {} at {}",
-        grammar.grammar_id,
-        grammar_dir.display()
+    report_progress(
+        print_progress,
+        format!(
+            "Building grammar: {} at {}",
+            grammar.grammar_id,
+            grammar_dir.display()
+        ),
     );
     let grammar_dir_entries = grammar_dir.read_dir().with_context(|| {
         format!("Failed to read directory {grammar_dir:?}. Did you use 'hx --grammar fetch'?")
@@ -373,6 +516,35 @@ fn build_tree_sitter_library(
     src_path: &Path,
     grammar: GrammarConfiguration,
     target: Option<&str>,
+) -> Result<BuildStatus> {
+    let library_path = library_path(&grammar.grammar_id);
+    compile_tree_sitter_library(
+        src_path,
+        &grammar.grammar_id,
+        &library_path,
+        target,
+        &grammar.compile_flags,
+        &Command::output,
+    )
+}
+
+fn library_path(grammar_id: &str) -> PathBuf {
+    let mut library_path = crate::grammars_dir().join(grammar_id);
+    library_path.set_extension(DYLIB_EXTENSION);
+    library_path
+}
+
+/// Runs a compiler command and waits for its output, which tests replace to inspect the command.
+type CompilerRunner<'a> = &'a dyn Fn(&mut Command) -> std::io::Result<Output>;
+
+/// `compile_flags` are only passed to the C compiler, not to the C++ compiler of `scanner.cc`.
+fn compile_tree_sitter_library(
+    src_path: &Path,
+    grammar_id: &str,
+    library_path: &Path,
+    target: Option<&str>,
+    compile_flags: &[String],
+    run_compiler: CompilerRunner,
 ) -> Result<BuildStatus> {
     let header_path = src_path;
     let parser_path = src_path.join("parser.c");
@@ -388,9 +560,6 @@ fn build_tree_sitter_library(
             None
         }
     };
-    let parser_lib_path = crate::runtime_dir().join("grammars");
-    let mut library_path = parser_lib_path.join(&grammar.grammar_id);
-    library_path.set_extension(DYLIB_EXTENSION);
 
     // if we are running inside a buildscript emit cargo metadata
     // to detect if we are running from a buildscript check some env variables
@@ -404,13 +573,22 @@ fn build_tree_sitter_library(
         }
     }
 
-    let recompile = needs_recompile(&library_path, &parser_path, &scanner_path)
+    let recompile = needs_recompile(library_path, &parser_path, &scanner_path, compile_flags)
         .context("Failed to compare source and binary timestamps")?;
 
     if !recompile {
         return Ok(BuildStatus::AlreadyBuilt);
     }
 
+    // The library is compiled into a temporary file, which is then renamed to `library_path`,
+    // so that concurrent builds never leave a partially written library behind.
+    let temporary_library_path = tempfile::Builder::new()
+        .prefix(grammar_id)
+        .suffix(&format!(".{DYLIB_EXTENSION}"))
+        .tempfile_in(library_path.parent().unwrap_or(Path::new(".")))
+        .context("Failed to create temporary library file")?
+        .into_temp_path();
+
     let mut config = cc::Build::new();
     config
         .cpp(true)
@@ -428,6 +606,7 @@ fn build_tree_sitter_library(
     command.args(compiler.args());
     // used to delay dropping the temporary object file until after the compilation is complete
     let _path_guard;
+    let mut warnings = String::new();
 
     if compiler.is_like_msvc() {
         command
@@ -435,7 +614,8 @@ fn build_tree_sitter_library(
             .arg(header_path)
             .arg("/Od")
             .arg("/utf-8")
-            .arg("/std:c11");
+            .arg("/std:c11")
+            .args(compile_flags);
         if let Some(scanner_path) = scanner_path.as_ref() {
             if scanner_path.extension() == Some("c".as_ref()) {
                 command.arg(scanner_path);
@@ -446,8 +626,7 @@ fn build_tree_sitter_library(
                     cpp_command.env(key, value);
                 }
                 cpp_command.args(compiler.args());
-                let object_file =
-                    library_path.with_file_name(format!("{}_scanner.obj", &grammar.grammar_id));
+                let object_file = library_path.with_file_name(format!("{grammar_id}_scanner.obj"));
                 cpp_command
                     .args(["/nologo", "/LD", "/I"])
                     .arg(header_path)
@@ -457,9 +636,8 @@ fn build_tree_sitter_library(
                     .arg(format!("/Fo{}", object_file.display()))
                     .arg("/c")
                     .arg(scanner_path);
-                let output = cpp_command
-                    .output()
-                    .context("Failed to execute C++ compiler")?;
+                let output =
+                    run_compiler(&mut cpp_command).context("Failed to execute C++ compiler")?;
 
                 if !output.status.success() {
                     return Err(anyhow!(
@@ -468,6 +646,7 @@ fn build_tree_sitter_library(
                         String::from_utf8_lossy(&output.stderr)
                     ));
                 }
+                warnings.push_str(&String::from_utf8_lossy(&output.stderr));
                 command.arg(&object_file);
                 _path_guard = TempPath::from_path(object_file);
             }
@@ -476,7 +655,7 @@ fn build_tree_sitter_library(
         command
             .arg(parser_path)
             .arg("/link")
-            .arg(format!("/out:{}", library_path.to_str().unwrap()));
+            .arg(format!("/out:{}", temporary_library_path.to_str().unwrap()));
     } else {
         command
             .arg("-shared")
@@ -485,7 +664,8 @@ fn build_tree_sitter_library(
             .arg("-I")
             .arg(header_path)
             .arg("-o")
-            .arg(&library_path);
+            .arg(&temporary_library_path)
+            .args(compile_flags);
 
         if let Some(scanner_path) = scanner_path.as_ref() {
             if scanner_path.extension() == Some("c".as_ref()) {
@@ -497,8 +677,7 @@ fn build_tree_sitter_library(
                     cpp_command.env(key, value);
                 }
                 cpp_command.args(compiler.args());
-                let object_file =
-                    library_path.with_file_name(format!("{}_scanner.o", &grammar.grammar_id));
+                let object_file = library_path.with_file_name(format!("{grammar_id}_scanner.o"));
                 cpp_command
                     .arg("-fPIC")
                     .arg("-fno-exceptions")
@@ -509,9 +688,8 @@ fn build_tree_sitter_library(
                     .arg("-std=c++14")
                     .arg("-c")
                     .arg(scanner_path);
-                let output = cpp_command
-                    .output()
-                    .context("Failed to execute C++ compiler")?;
+                let output =
+                    run_compiler(&mut cpp_command).context("Failed to execute C++ compiler")?;
                 if !output.status.success() {
                     return Err(anyhow!(
                         "Parser compilation failed.\nStdout: {}\nStderr: {}",
@@ -519,6 +697,7 @@ fn build_tree_sitter_library(
                         String::from_utf8_lossy(&output.stderr)
                     ));
                 }
+                warnings.push_str(&String::from_utf8_lossy(&output.stderr));
 
                 command.arg(&object_file);
                 _path_guard = TempPath::from_path(object_file);
@@ -533,28 +712,81 @@ fn build_tree_sitter_library(
         }
     }
 
-    let output = command.output().with_context(|| {
+    let output = run_compiler(&mut command).with_context(|| {
         format!("Failed to execute C/C++ compiler, the command was {command:?}")
     })?;
     if !output.status.success() {
+        let compile_flags_hint = if compile_flags.is_empty() {
+            String::new()
+        } else {
+            format!("\nCheck the configured compile_flags {compile_flags:?}.")
+        };
         return Err(anyhow!(
-            "Parser compilation ({command:?}) failed.\nStdout: {}\nStderr: {}",
+            "Parser compilation ({command:?}) failed.{compile_flags_hint}\nStdout: {}\nStderr: {}",
             String::from_utf8_lossy(&output.stdout),
             String::from_utf8_lossy(&output.stderr)
         ));
     }
+    warnings.push_str(&String::from_utf8_lossy(&output.stderr));
+    temporary_library_path
+        .persist(library_path)
+        .with_context(|| format!("Failed to move the built library to {library_path:?}"))?;
+
+    let compile_flags_path = compile_flags_path(library_path);
+    if compile_flags.is_empty() {
+        if compile_flags_path.exists() {
+            fs::remove_file(&compile_flags_path)?;
+        }
+    } else {
+        fs::write(&compile_flags_path, compile_flags.join("\n"))
+            .with_context(|| format!("Failed to write {compile_flags_path:?}"))?;
+    }
+
+    // Warnings do not fail the build, but they are recorded
+    // so that they can be inspected when a grammar parses oddly.
+    let warnings_path = build_warnings_path(library_path);
+    if warnings.trim().is_empty() {
+        if warnings_path.exists() {
+            fs::remove_file(&warnings_path)?;
+        }
+    } else {
+        fs::write(&warnings_path, warnings)
+            .with_context(|| format!("Failed to write {warnings_path:?}"))?;
+    }
 
     Ok(BuildStatus::Built)
 }
 
+fn build_warnings_path(library_path: &Path) -> PathBuf {
+    library_path.with_extension("warnings")
+}
+
+/// The `compile_flags` that the library was built with, one per line,
+/// which is absent if there were none.
+fn compile_flags_path(library_path: &Path) -> PathBuf {
+    library_path.with_extension("flags")
+}
+
+/// Gives the C/C++ compiler warnings recorded when the grammar was last built,
+/// or `None` if there were no warnings.
+pub fn get_build_warnings(name: &str) -> Option<String> {
+    fs::read_to_string(build_warnings_path(&library_path(name))).ok()
+}
+
+/// The library is also recompiled if `compile_flags` differ from the ones it was built with.
 fn needs_recompile(
     lib_path: &Path,
     parser_c_path: &Path,
     scanner_path: &Option<PathBuf>,
+    compile_flags: &[String],
 ) -> Result<bool> {
     if !lib_path.exists() {
         return Ok(true);
     }
+    let built_compile_flags = fs::read_to_string(compile_flags_path(lib_path)).unwrap_or_default();
+    if built_compile_flags != compile_flags.join("\n") {
+        return Ok(true);
+    }
     let lib_mtime = mtime(lib_path)?;
     if mtime(parser_c_path)? > lib_mtime {
         return Ok(true);
@@ -571,17 +803,196 @@ fn mtime(path: &Path) -> Result<SystemTime> {
     Ok(fs::metadata(path)?.modified()?)
 }
 
-/// Gives the contents of a file from a language's `<runtime>/grammars/sources/<language>/queries/<filename>`
-/// directory
-pub fn load_runtime_file(language: &str, filename: &str) -> Result<String, std::io::Error> {
-    let path = crate::runtime_file(
-        &PathBuf::new()
-            .join("grammars")
-            .join("sources")
-            .join(language)
-            .join("queries")
-            .join(filename),
-    );
-    log::info!("Loading from {}", path.display());
-    std::fs::read_to_string(path)
+/// The `<grammars>/sources/<grammar_id>` directory that a `Git` grammar is fetched into,
+/// see `grammars_dir`
+pub fn source_dir(grammar_id: &str) -> PathBuf {
+    crate::grammars_dir().join("sources").join(grammar_id)
+}
+
+#[cfg(test)]
+mod test_grammar {
+    use super::*;
+
+    #[test]
+    fn grammars_are_built_in_parallel() {
+        let grammars = ["a", "b", "c"]
+            .into_iter()
+            .map(|id| GrammarConfiguration::remote(id, "https://example.com", "main", None))
+            .collect();
+        let job_duration = std::time::Duration::from_millis(300);
+        let start = std::time::Instant::now();
+        let results = run_parallel(grammars, move |_| {
+            std::thread::sleep(job_duration);
+            Ok(())
+        });
+        assert_eq!(results.len(), 3);
+        if std::thread::available_parallelism().is_ok_and(|count| count.get() >= 3) {
+            assert!(start.elapsed() < job_duration * 3, "{:?}", start.elapsed());
+        }
+    }
+
+    #[test]
+    fn progress_is_reported_in_order() {
+        let grammars = vec![
+            GrammarConfiguration::remote("a", "https://example.com/a", "main", None),
+            GrammarConfiguration::remote("b", "https://example.com/b", "main", None),
+        ];
+        let receiver = run_parallel_with_progress(grammars, 2, |grammar, report| {
+            report(GrammarPhase::Fetch);
+            report(GrammarPhase::Compile);
+            if grammar.grammar_id == "a" {
+                Ok(42)
+            } else {
+                Err(anyhow!("no parser.c"))
+            }
+        });
+        let events: Vec<_> = receiver.iter().collect();
+        let phases = |grammar_id: &str| {
+            events
+                .iter()
+                .filter(|event| event.grammar_id == grammar_id)
+                .map(|event| event.phase.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            phases("a"),
+            vec![
+                GrammarPhase::Fetch,
+                GrammarPhase::Compile,
+                GrammarPhase::Done { library_bytes: 42 }
+            ]
+        );
+        assert_eq!(
+            phases("b"),
+            vec![
+                GrammarPhase::Fetch,
+                GrammarPhase::Compile,
+                GrammarPhase::Failed {
+                    error: "no parser.c".to_string()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn build_warnings_are_recorded() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let src_path = tempdir.path().join("src");
+        fs::create_dir_all(&src_path)?;
+        fs::write(
+            src_path.join("parser.c"),
+            "#warning \"this grammar is deprecated\"\nvoid *tree_sitter_test(void) { return 0; }\n",
+        )?;
+        let library_path = tempdir.path().join("test").with_extension(DYLIB_EXTENSION);
+
+        compile_tree_sitter_library(
+            &src_path,
+            "test",
+            &library_path,
+            None,
+            &[],
+            &Command::output,
+        )?;
+
+        assert!(library_path.exists());
+        let warnings = fs::read_to_string(build_warnings_path(&library_path))?;
+        assert!(
+            warnings.contains("this grammar is deprecated"),
+            "{warnings}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn failures_of_experimental_grammars_are_warnings() {
+        let experimental = experimental_grammar_ids(&[
+            GrammarConfiguration::remote("stable", "https://example.com/a", "main", None),
+            GrammarConfiguration::remote("new", "https://example.com/b", "bad-commit", None)
+                .experimental(true),
+        ]);
+        assert!(report_failures(
+            "fetch",
+            vec![("new".to_string(), anyhow!("revision bad-commit not found"))],
+            &experimental
+        )
+        .is_ok());
+        assert!(report_failures(
+            "fetch",
+            vec![
+                ("new".to_string(), anyhow!("revision bad-commit not found")),
+                ("stable".to_string(), anyhow!("revision main not found"))
+            ],
+            &experimental
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn compile_flags_are_passed_to_the_compiler() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let src_path = tempdir.path().join("src");
+        fs::create_dir_all(&src_path)?;
+        fs::write(src_path.join("parser.c"), "")?;
+        let library_path = tempdir.path().join("test").with_extension(DYLIB_EXTENSION);
+
+        let arguments = std::sync::Mutex::new(Vec::new());
+        let error = compile_tree_sitter_library(
+            &src_path,
+            "test",
+            &library_path,
+            None,
+            &["-DKI_TEST_FLAG=1".to_string()],
+            &|command| {
+                arguments.lock().unwrap().extend(
+                    command
+                        .get_args()
+                        .map(|argument| argument.to_string_lossy().to_string()),
+                );
+                // Fail like a compiler rejecting the flag
+                Command::new("sh")
+                    .args(["-c", "echo 'unknown flag' >&2; exit 1"])
+                    .output()
+            },
+        )
+        .unwrap_err();
+
+        assert!(arguments
+            .lock()
+            .unwrap()
+            .contains(&"-DKI_TEST_FLAG=1".to_string()));
+        let error = format!("{error:#}");
+        assert!(error.contains("compile_flags"), "{error}");
+        assert!(error.contains("unknown flag"), "{error}");
+        assert!(!library_path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn changing_compile_flags_recompiles() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let src_path = tempdir.path().join("src");
+        fs::create_dir_all(&src_path)?;
+        fs::write(src_path.join("parser.c"), "")?;
+        let library_path = tempdir.path().join("test").with_extension(DYLIB_EXTENSION);
+        let compile = |compile_flags: &[&str]| {
+            compile_tree_sitter_library(
+                &src_path,
+                "test",
+                &library_path,
+                None,
+                &compile_flags
+                    .iter()
+                    .map(|flag| flag.to_string())
+                    .collect::<Vec<_>>(),
+                &|_| Command::new("true").output(),
+            )
+        };
+
+        assert_eq!(compile(&["-DKI_TEST_FLAG=1"])?, BuildStatus::Built);
+        assert_eq!(compile(&["-DKI_TEST_FLAG=1"])?, BuildStatus::AlreadyBuilt);
+        assert_eq!(compile(&["-DKI_TEST_FLAG=2"])?, BuildStatus::Built);
+        assert_eq!(compile(&[])?, BuildStatus::Built);
+        assert_eq!(compile(&[])?, BuildStatus::AlreadyBuilt);
+        Ok(())
+    }
 }
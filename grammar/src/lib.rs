@@ -10,6 +10,8 @@ static RUNTIME_DIR: once_cell::sync::Lazy<PathBuf> = once_cell::sync::Lazy::new(
 
 static LOG_FILE: once_cell::sync::OnceCell<PathBuf> = once_cell::sync::OnceCell::new();
 
+pub const GRAMMAR_CACHE_DIR_ENV: &str = "KI_GRAMMAR_CACHE_DIR";
+
 // Get the current working directory.
 // This information is managed internally as the call to std::env::current_dir
 // might fail if the cwd has been deleted.
@@ -57,6 +59,20 @@ pub fn runtime_file(rel_path: &Path) -> PathBuf {
     find_runtime_file(rel_path)
 }
 
+/// The directory where grammar sources are fetched to and compiled grammars are stored.
+pub fn grammars_dir() -> PathBuf {
+    resolve_grammars_dir(std::env::var_os(GRAMMAR_CACHE_DIR_ENV))
+}
+
+/// The directory given by the `KI_GRAMMAR_CACHE_DIR` environment variable
+/// overrides `<runtime>/grammars`, unless it is empty.
+fn resolve_grammars_dir(environment_variable: Option<std::ffi::OsString>) -> PathBuf {
+    environment_variable
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| RUNTIME_DIR.join("grammars"))
+}
+
 pub fn config_dir() -> PathBuf {
     // TODO: allow env var override
     let strategy = choose_base_strategy().expect("Unable to find the config directory!");
@@ -80,3 +96,21 @@ pub fn log_file() -> PathBuf {
 pub fn default_log_file() -> PathBuf {
     cache_dir().join("ki.log")
 }
+
+#[cfg(test)]
+mod test_lib {
+    use super::*;
+
+    #[test]
+    fn grammar_cache_dir_env_overrides_default_grammars_dir() {
+        assert_eq!(resolve_grammars_dir(None), runtime_dir().join("grammars"));
+        assert_eq!(
+            resolve_grammars_dir(Some("".into())),
+            runtime_dir().join("grammars")
+        );
+        assert_eq!(
+            resolve_grammars_dir(Some("/tmp/ki-grammars".into())),
+            PathBuf::from("/tmp/ki-grammars")
+        );
+    }
+}
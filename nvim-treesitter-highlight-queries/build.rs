@@ -1,4 +1,5 @@
 const LANGS: &[&str] = &[
+    "asm",
     "bash",
     "c",
     "c_sharp",
@@ -11,6 +12,7 @@ const LANGS: &[&str] = &[
     "dune",
     "ecma",
     "elixir",
+    "embedded_template",
     "fish",
     "fsharp",
     "git_config",
@@ -26,26 +28,32 @@ const LANGS: &[&str] = &[
     "heex",
     "html",
     "html_tags",
+    "htmldjango",
     "idris",
     "javascript",
+    "jsdoc",
     "json",
     "jsx",
     "julia",
     "just",
     "ki_quickfix",
     "lua",
+    "make",
     "markdown",
+    "markdown_inline",
     "nix",
     "ocaml",
     "ocaml_interface",
     "odin",
     "python",
     "racket",
+    "regex",
     "rescript",
     "roc",
     "ruby",
     "rust",
     "scheme",
+    "scss",
     "sql",
     "svelte",
     "swift",
@@ -56,6 +64,7 @@ const LANGS: &[&str] = &[
     "typescript",
     "typst",
     "unison",
+    "vue",
     "xml",
     "yaml",
     "zig",
@@ -0,0 +1,12 @@
+; The assembly code of inline assembly is injected with Assembly,
+; for example, `asm("mov %0, %1" : "=r"(x) : "r"(y))`.
+; The operands and clobbers are left alone, because their strings are constraints instead of code.
+(gnu_asm_expression
+  assembly_code: [
+    (string_literal
+      (string_content) @injection.content)
+    (concatenated_string
+      (string_literal
+        (string_content) @injection.content))
+  ]
+  (#set! injection.language "asm"))
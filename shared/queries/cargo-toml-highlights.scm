@@ -0,0 +1,20 @@
+; Dependency names, e.g. `serde` in `[dependencies]`
+(table
+  (bare_key) @_table
+  (pair
+    (bare_key) @module)
+  (#match? @_table "^(dev-|build-)?dependencies$"))
+
+; Version strings, e.g. `serde = "1.0"`
+(table
+  (bare_key) @_table
+  (pair
+    (bare_key)
+    (string) @string.special)
+  (#match? @_table "^(dev-|build-)?dependencies$"))
+
+; e.g. `serde = { version = "1.0" }` and `version = "0.1.0"` of `[package]`
+(pair
+  (bare_key) @_key
+  (string) @string.special
+  (#eq? @_key "version"))
@@ -0,0 +1,16 @@
+; Only the shell form (e.g. `RUN echo hi`) contains shell,
+; the exec form (e.g. `RUN ["echo", "hi"]`) is a JSON array
+(run_instruction
+  (shell_command) @injection.content
+  (#set! injection.language "bash")
+  (#set! injection.include-children))
+
+(cmd_instruction
+  (shell_command) @injection.content
+  (#set! injection.language "bash")
+  (#set! injection.include-children))
+
+(entrypoint_instruction
+  (shell_command) @injection.content
+  (#set! injection.language "bash")
+  (#set! injection.include-children))
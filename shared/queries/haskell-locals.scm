@@ -0,0 +1,21 @@
+; Haskell bindings are lexically scoped, so a parameter or a local binding
+; shadows the top-level declaration of the same name.
+[
+  (function)
+  (local_binds)
+  (let_in)
+] @local.scope
+
+(function
+  patterns: (patterns
+    (variable) @local.definition))
+
+(local_binds
+  (bind
+    name: (variable) @local.definition))
+
+(local_binds
+  (function
+    name: (variable) @local.definition))
+
+(variable) @local.reference
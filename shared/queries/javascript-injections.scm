@@ -0,0 +1,40 @@
+; CSS-in-JS, only for the tag names of styled-components (and emotion),
+; so that other template literals are not highlighted as CSS.
+;
+; The fragments are injected separately, so that the backticks and `${}` are not part of the CSS.
+
+; styled.div`...`
+(call_expression
+  function: (member_expression
+    object: (identifier) @_tag)
+  arguments: (template_string
+    (string_fragment) @injection.content)
+  (#eq? @_tag "styled")
+  (#set! injection.language "css"))
+
+; styled(Button)`...`
+(call_expression
+  function: (call_expression
+    function: (identifier) @_tag)
+  arguments: (template_string
+    (string_fragment) @injection.content)
+  (#eq? @_tag "styled")
+  (#set! injection.language "css"))
+
+; css`...`
+(call_expression
+  function: (identifier) @_tag
+  arguments: (template_string
+    (string_fragment) @injection.content)
+  (#match? @_tag "^(css|createGlobalStyle|keyframes|injectGlobal)$")
+  (#set! injection.language "css"))
+
+; /.../
+(regex
+  pattern: (regex_pattern) @injection.content
+  (#set! injection.language "regex"))
+
+; /** ... */ documentation comments, but not ordinary comments
+((comment) @injection.content
+  (#match? @injection.content "^/[*][*][^*/]")
+  (#set! injection.language "jsdoc"))
@@ -0,0 +1,2 @@
+(object "{" @open "}" @close)
+(array "[" @open "]" @close)
@@ -0,0 +1,14 @@
+; Fenced code blocks are injected with the language of their info string,
+; for example, ```lua
+; The children are included, because the punctuation of the code is parsed as tokens of Markdown
+(fenced_code_block
+  (info_string
+    (language) @injection.language)
+  (code_fence_content) @injection.content
+  (#set! injection.include-children))
+
+; Inline content (e.g. emphasis and links) is parsed by the separate inline grammar,
+; and its children are included, because its punctuation is parsed as tokens of Markdown
+((inline) @injection.content
+  (#set! injection.language "markdown_inline")
+  (#set! injection.include-children))
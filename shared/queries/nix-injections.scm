@@ -0,0 +1,23 @@
+; The script of `writeShellScript "name" ''...''` and `writeShellScriptBin "name" ''...''`,
+; which may be qualified, for example, `pkgs.writeShellScript`.
+;
+; Only the second argument is bash, the first being the name of the script,
+; and the interpolations (e.g. `${pkgs.hello}`) are left as Nix.
+(apply_expression
+  function: (apply_expression
+    function: [
+      (variable_expression
+        name: (identifier) @_function)
+      (select_expression
+        attrpath: (attrpath
+          attr: (identifier) @_function .))
+    ])
+  argument: [
+    (string_expression
+      (string_fragment) @injection.content)
+    (indented_string_expression
+      (string_fragment) @injection.content)
+  ]
+  (#match? @_function "^writeShellScript(Bin)?$")
+  (#set! injection.language "bash")
+  (#set! injection.combined))
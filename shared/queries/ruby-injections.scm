@@ -0,0 +1,23 @@
+; Heredocs are injected with the language of their tag,
+; for example, <<~SQL contains SQL and <<~BASH contains Bash.
+;
+; The tag comes after the content, so the heredocs are combined injections,
+; because only those are matched completely before they are injected,
+; and each language has its own pattern, so that heredocs of different languages are not combined.
+((heredoc_body
+  (heredoc_content) @injection.content
+  (heredoc_end) @injection.language)
+  (#match? @injection.language "^SQL$")
+  (#set! injection.combined))
+
+((heredoc_body
+  (heredoc_content) @injection.content
+  (heredoc_end) @injection.language)
+  (#match? @injection.language "^(BASH|SH|SHELL|ZSH)$")
+  (#set! injection.combined))
+
+((heredoc_body
+  (heredoc_content) @injection.content
+  (heredoc_end) @injection.language)
+  (#match? @injection.language "^HTML$")
+  (#set! injection.combined))
@@ -0,0 +1,8 @@
+; Brackets are only paired when they are delimiters of the same node,
+; so brackets within strings and comments are never paired.
+(_ "(" @open ")" @close)
+(_ "[" @open "]" @close)
+(_ "{" @open "}" @close)
+(type_arguments "<" @open ">" @close)
+(type_parameters "<" @open ">" @close)
+(closure_parameters "|" @open "|" @close)
@@ -0,0 +1,46 @@
+; Regexes, only for the constructors of the `regex` crate and the macros of `lazy-regex`,
+; so that other strings are not highlighted as regexes.
+;
+; The constructor comes before the string, so the regexes are combined injections,
+; because only those are matched completely before they are injected.
+
+; Regex::new("..."), regex::bytes::RegexBuilder::new(r"...")
+(call_expression
+  function: (scoped_identifier
+    path: (_) @_type
+    name: (identifier) @_function)
+  arguments: (arguments
+    .
+    [
+      (string_literal
+        (string_content) @injection.content)
+      (raw_string_literal
+        (string_content) @injection.content)
+    ])
+  (#match? @_type "(^|::)(Regex|RegexBuilder)$")
+  (#eq? @_function "new")
+  (#set! injection.language "regex")
+  (#set! injection.combined))
+
+; regex!("..."), lazy_regex!(r"...")
+(macro_invocation
+  macro: (identifier) @_macro
+  (token_tree
+    .
+    [
+      (string_literal
+        (string_content) @injection.content)
+      (raw_string_literal
+        (string_content) @injection.content)
+    ])
+  (#match? @_macro "^(regex|lazy_regex|regex_is_match|regex_find|regex_captures)$")
+  (#set! injection.language "regex")
+  (#set! injection.combined))
+
+; Documentation comments are Markdown, whose fenced code blocks are injected in turn.
+; Every doc comment of the file is combined into one Markdown document,
+; so that a fence spanning many `///` lines is parsed as one code block.
+; Ordinary comments have no `doc_comment` node, so they are not injected.
+((doc_comment) @injection.content
+  (#set! injection.language "markdown")
+  (#set! injection.combined))
@@ -0,0 +1,35 @@
+; `<script lang="ts">` and `<style lang="scss">`
+(script_element
+  (start_tag
+    (attribute
+      (attribute_name) @_attribute_name
+      (quoted_attribute_value
+        (attribute_value) @injection.language)))
+  (raw_text) @injection.content
+  (#eq? @_attribute_name "lang"))
+
+(style_element
+  (start_tag
+    (attribute
+      (attribute_name) @_attribute_name
+      (quoted_attribute_value
+        (attribute_value) @injection.language)))
+  (raw_text) @injection.content
+  (#eq? @_attribute_name "lang"))
+
+; Blocks without the `lang` attribute
+((script_element
+  (start_tag) @_start_tag
+  (raw_text) @injection.content)
+  (#not-match? @_start_tag "\\slang\\s*=")
+  (#set! injection.language "javascript"))
+
+((style_element
+  (start_tag) @_start_tag
+  (raw_text) @injection.content)
+  (#not-match? @_start_tag "\\slang\\s*=")
+  (#set! injection.language "css"))
+
+((template_element) @injection.content
+  (#set! injection.language "html")
+  (#set! injection.include-children))
@@ -0,0 +1,24 @@
+; CI configurations (e.g. GitHub Actions, GitLab CI) run shell commands from `run:` keys
+(block_mapping_pair
+  key: (flow_node
+    (plain_scalar
+      (string_scalar) @_key))
+  value: (flow_node
+    [
+      (plain_scalar)
+      (double_quote_scalar)
+      (single_quote_scalar)
+    ] @injection.content)
+  (#eq? @_key "run")
+  (#set! injection.language "bash")
+  (#set! injection.include-children))
+
+(block_mapping_pair
+  key: (flow_node
+    (plain_scalar
+      (string_scalar) @_key))
+  value: (block_node
+    (block_scalar) @injection.content)
+  (#eq? @_key "run")
+  (#set! injection.language "bash")
+  (#set! injection.include-children))
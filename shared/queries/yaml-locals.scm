@@ -0,0 +1,9 @@
+; An alias refers to the last anchor of the same name before it in the same document,
+; for example, `*defaults` refers to `&defaults`.
+(document) @local.scope
+
+(anchor
+  (anchor_name) @local.definition)
+
+(alias
+  (alias_name) @local.reference)
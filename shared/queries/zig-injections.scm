@@ -0,0 +1,13 @@
+; The values of `@cDefine`, which is only valid within `@cImport`, are C,
+; for example, `@cDefine("MAX_LEN", "sizeof(int) * 4")`.
+;
+; The header names of `@cInclude` are not C, so they are left as strings.
+(builtin_function
+  (builtin_identifier) @_builtin
+  (arguments
+    (string)
+    .
+    (string
+      (string_content) @injection.content))
+  (#eq? @_builtin "@cDefine")
+  (#set! injection.language "c"))
@@ -1,34 +1,269 @@
-use std::io::Write;
+use std::{collections::HashSet, io::Write, path::PathBuf};
 
-use crate::language::ProcessCommand;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::language::{IndentUnit, ProcessCommand};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    /// The line ending of every line of `content`, which is `Lf` if there is no line ending,
+    /// or `None` if `content` mixes `\r\n` and `\n`.
+    pub fn detect(content: &str) -> Option<Self> {
+        let crlf_count = content.matches("\r\n").count();
+        let lf_count = content.matches('\n').count();
+        if crlf_count == 0 {
+            Some(Self::Lf)
+        } else if crlf_count == lf_count {
+            Some(Self::Crlf)
+        } else {
+            None
+        }
+    }
+
+    pub fn normalize(self, content: &str) -> String {
+        let content = content.replace("\r\n", "\n");
+        match self {
+            Self::Lf => content,
+            Self::Crlf => content.replace('\n', "\r\n"),
+        }
+    }
+}
+
+/// How the formatters of a language are combined when there are more than one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+pub enum FormattersMode {
+    /// Pipe the output of each formatter into the next one.
+    Chain,
+    /// Only run the first formatter that is installed,
+    /// for example, `rustfmt` if it is found, or else `cargo fmt`.
+    #[default]
+    FirstAvailable,
+}
 
 pub struct Formatter {
-    process_command: ProcessCommand,
+    process_commands: Vec<ProcessCommand>,
+    mode: FormattersMode,
+    line_ending: Option<LineEnding>,
+    /// Used instead of `process_commands` if set.
+    whitespace_formatter: Option<WhitespaceFormatter>,
+}
+
+/// A built-in formatter for languages without a formatter, which only
+/// trims trailing whitespace, ensures a final newline,
+/// and converts the indentation to `indent_unit` if it is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WhitespaceFormatter {
+    pub indent_unit: Option<IndentUnit>,
+    pub tab_width: usize,
+    /// Used to find the multi-line strings, whose whitespace is part of their content.
+    pub tree_sitter_language: Option<tree_sitter::Language>,
+}
+
+impl WhitespaceFormatter {
+    pub fn format(&self, content: &str) -> String {
+        if content.is_empty() {
+            return String::new();
+        }
+        let (rows_starting_in_string, rows_ending_in_string) = self.multi_line_string_rows(content);
+        let mut formatted = content
+            .split('\n')
+            .enumerate()
+            .map(|(row, line)| {
+                let line = if rows_ending_in_string.contains(&row) {
+                    line
+                } else {
+                    line.trim_end()
+                };
+                if rows_starting_in_string.contains(&row) {
+                    return line.to_string();
+                }
+                let content = line.trim_start();
+                let indentation = &line[..line.len() - content.len()];
+                format!("{}{content}", self.convert_indentation(indentation))
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let trimmed_len = formatted.trim_end_matches('\n').len();
+        formatted.truncate(trimmed_len);
+        formatted.push('\n');
+        formatted
+    }
+
+    /// A tab of the indentation is as wide as the indent unit,
+    /// which is `tab_width` for `IndentUnit::Tab`.
+    fn convert_indentation(&self, indentation: &str) -> String {
+        let width = |tab_width: usize| {
+            indentation
+                .chars()
+                .map(|char| if char == '\t' { tab_width } else { 1 })
+                .sum::<usize>()
+        };
+        match self.indent_unit {
+            None => indentation.to_string(),
+            Some(IndentUnit::Spaces(count)) => " ".repeat(width(count)),
+            Some(IndentUnit::Tab) => {
+                let tab_width = self.tab_width.max(1);
+                let width = width(tab_width);
+                format!(
+                    "{}{}",
+                    "\t".repeat(width / tab_width),
+                    " ".repeat(width % tab_width)
+                )
+            }
+        }
+    }
+
+    /// The rows of the lines that start within a multi-line string, whose indentation is kept,
+    /// and the rows of the lines that end within one, whose trailing whitespace is kept.
+    ///
+    /// Strings are the nodes whose kind contains `string`, e.g. `string_literal`.
+    fn multi_line_string_rows(&self, content: &str) -> (HashSet<usize>, HashSet<usize>) {
+        let mut rows = (HashSet::new(), HashSet::new());
+        let Some(tree) = self.tree_sitter_language.as_ref().and_then(|language| {
+            let mut parser = tree_sitter::Parser::new();
+            parser.set_language(language).ok()?;
+            parser.parse(content, None)
+        }) else {
+            return rows;
+        };
+        let mut cursor = tree.walk();
+        loop {
+            let node = cursor.node();
+            let (start_row, end_row) = (node.start_position().row, node.end_position().row);
+            if node.kind().contains("string") && start_row < end_row {
+                rows.0.extend(start_row + 1..=end_row);
+                rows.1.extend(start_row..end_row);
+            } else if cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    return rows;
+                }
+            }
+        }
+    }
 }
 
 impl From<ProcessCommand> for Formatter {
     fn from(value: ProcessCommand) -> Self {
-        Self {
-            process_command: value,
-        }
+        Self::new(vec![value], FormattersMode::FirstAvailable)
     }
 }
 
 impl Formatter {
+    pub fn new(process_commands: Vec<ProcessCommand>, mode: FormattersMode) -> Self {
+        Self {
+            process_commands,
+            mode,
+            line_ending: None,
+            whitespace_formatter: None,
+        }
+    }
+
+    pub fn whitespace(whitespace_formatter: WhitespaceFormatter) -> Self {
+        Self {
+            whitespace_formatter: Some(whitespace_formatter),
+            ..Self::new(Vec::new(), FormattersMode::default())
+        }
+    }
+
+    /// Convert the content piped to the formatter to `line_ending`,
+    /// or keep the line ending of the content if it is `None`.
+    pub fn with_line_ending(self, line_ending: Option<LineEnding>) -> Self {
+        Self {
+            line_ending,
+            ..self
+        }
+    }
+
     pub fn command_string(&self) -> String {
-        self.process_command.to_string()
+        if self.whitespace_formatter.is_some() {
+            return "[built-in whitespace formatter]".to_string();
+        }
+        match self.mode {
+            FormattersMode::Chain => self
+                .process_commands
+                .iter()
+                .map(|process_command| process_command.to_string())
+                .collect::<Vec<_>>()
+                .join(" | "),
+            FormattersMode::FirstAvailable => self
+                .first_available()
+                .or(self.process_commands.first())
+                .map(|process_command| process_command.to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn first_available(&self) -> Option<&ProcessCommand> {
+        self.process_commands
+            .iter()
+            .find(|process_command| process_command.is_available())
     }
+
+    /// The output is converted back to the line ending of `content`.
+    ///
+    /// Content with mixed line endings is formatted as is,
+    /// because converting it would rewrite every line of one of the endings.
     pub fn format(&self, content: &str) -> anyhow::Result<String> {
+        let Some(document_line_ending) = LineEnding::detect(content) else {
+            return self.format_without_line_ending_conversion(content);
+        };
+        let input = match self.line_ending {
+            Some(line_ending) if line_ending != document_line_ending => {
+                line_ending.normalize(content)
+            }
+            _ => content.to_string(),
+        };
+        let output = self.format_without_line_ending_conversion(&input)?;
+        Ok(document_line_ending.normalize(&output))
+    }
+
+    fn format_without_line_ending_conversion(&self, content: &str) -> anyhow::Result<String> {
+        if let Some(whitespace_formatter) = &self.whitespace_formatter {
+            return Ok(whitespace_formatter.format(content));
+        }
+        match self.mode {
+            FormattersMode::Chain => self
+                .process_commands
+                .iter()
+                .try_fold(content.to_string(), |content, process_command| {
+                    Self::run(process_command, &content)
+                }),
+            FormattersMode::FirstAvailable => {
+                let process_command = self.first_available().ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "None of the formatters is found: {}",
+                        self.process_commands
+                            .iter()
+                            .map(|process_command| process_command.to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                })?;
+                Self::run(process_command, content)
+            }
+        }
+    }
+
+    fn run(process_command: &ProcessCommand, content: &str) -> anyhow::Result<String> {
         // Run the command with the args,
         // pass in the content using stdin,
         // get the output from the stdout
 
-        let mut child = self.process_command.spawn().into_result()?;
+        let mut child = process_command.spawn().into_result()?;
 
         let stdin = child.stdin.as_mut().ok_or_else(|| {
             anyhow::anyhow!(
                 "Failed to open stdin for the command: {:?}",
-                self.process_command
+                process_command
             )
         })?;
 
@@ -51,4 +286,205 @@ impl Formatter {
             Ok(String::from_utf8(output.stdout)?)
         }
     }
+
+    /// Format each of `files` in place, returning whether each changed, in the same order.
+    ///
+    /// A file that fails to be read, formatted or written does not stop the others.
+    pub fn format_all(&self, files: &[PathBuf]) -> Vec<anyhow::Result<bool>> {
+        files
+            .iter()
+            .map(|file| {
+                let content = std::fs::read_to_string(file)?;
+                let formatted = self.format(&content)?;
+                if formatted == content {
+                    return Ok(false);
+                }
+                std::fs::write(file, formatted)?;
+                Ok(true)
+            })
+            .collect()
+    }
+
+    /// Run the formatter without applying its output, returning the changes it would make
+    /// as a unified diff, which is empty if `content` is already formatted.
+    pub fn format_diff(&self, content: &str) -> anyhow::Result<String> {
+        let formatted = self.format(content)?;
+        Ok(similar::TextDiff::from_lines(content, &formatted)
+            .unified_diff()
+            .header("original", "formatted")
+            .to_string())
+    }
+}
+
+#[cfg(test)]
+mod test_formatter {
+    use super::*;
+
+    #[test]
+    fn format_diff_is_empty_when_content_is_already_formatted() -> anyhow::Result<()> {
+        let formatter = Formatter::from(ProcessCommand::new("cat", &[]));
+        assert_eq!(formatter.format_diff("fn main() {}\n")?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn format_diff_contains_changed_lines() -> anyhow::Result<()> {
+        let formatter = Formatter::from(ProcessCommand::new(
+            "tr",
+            &["a".to_string(), "b".to_string()],
+        ));
+        let diff = formatter.format_diff("x\na\n")?;
+        assert!(diff.contains("-a\n+b\n"), "{diff}");
+        assert!(!diff.contains("-x"), "{diff}");
+        Ok(())
+    }
+
+    #[test]
+    fn crlf_document_round_trips_when_line_ending_is_preserved() -> anyhow::Result<()> {
+        // A formatter that only emits LF
+        let formatter = Formatter::from(ProcessCommand::new(
+            "tr",
+            &["-d".to_string(), "\\r".to_string()],
+        ))
+        .with_line_ending(None);
+        assert_eq!(formatter.format("a\r\nb\r\n")?, "a\r\nb\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn mixed_line_endings_are_left_alone() -> anyhow::Result<()> {
+        assert_eq!(LineEnding::detect("a\r\nb\nc\r\n"), None);
+        assert_eq!(LineEnding::detect("a\r\nb\r\n"), Some(LineEnding::Crlf));
+        assert_eq!(LineEnding::detect("a\nb"), Some(LineEnding::Lf));
+
+        let formatter = Formatter::from(tr("a", "b")).with_line_ending(Some(LineEnding::Lf));
+        assert_eq!(formatter.format("a\r\na\na\r\n")?, "b\r\nb\nb\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn format_line_ending_converts_the_input() -> anyhow::Result<()> {
+        // `od -c` shows `\r` if it receives a carriage return
+        let formatter = Formatter::from(ProcessCommand::new("od", &["-c".to_string()]))
+            .with_line_ending(Some(LineEnding::Lf));
+        assert!(!formatter.format("a\r\n")?.contains("\\r"));
+        Ok(())
+    }
+
+    fn tr(from: &str, to: &str) -> ProcessCommand {
+        ProcessCommand::new("tr", &[from.to_string(), to.to_string()])
+    }
+
+    #[test]
+    fn first_available_formatter_is_used() -> anyhow::Result<()> {
+        let formatter = Formatter::new(
+            vec![
+                ProcessCommand::new("ki-formatter-that-does-not-exist", &[]),
+                tr("a", "b"),
+                tr("b", "c"),
+            ],
+            FormattersMode::FirstAvailable,
+        );
+        assert_eq!(formatter.format("a")?, "b");
+        assert_eq!(formatter.command_string(), "tr a b");
+
+        let formatter = Formatter::new(
+            vec![ProcessCommand::new("ki-formatter-that-does-not-exist", &[])],
+            FormattersMode::FirstAvailable,
+        );
+        assert!(formatter.format("a").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn chained_formatters_are_piped() -> anyhow::Result<()> {
+        let formatter = Formatter::new(vec![tr("a", "b"), tr("b", "c")], FormattersMode::Chain);
+        assert_eq!(formatter.format("a")?, "c");
+        assert_eq!(formatter.command_string(), "tr a b | tr b c");
+        Ok(())
+    }
+
+    #[test]
+    fn format_all_isolates_failing_files() -> anyhow::Result<()> {
+        // Fails for content containing "bad"
+        let formatter = Formatter::from(ProcessCommand::new(
+            "sh",
+            &[
+                "-c".to_string(),
+                r#"input=$(cat); case "$input" in *bad*) exit 1;; esac; printf %s "$input" | tr a b"#
+                    .to_string(),
+            ],
+        ));
+        let tempdir = tempfile::tempdir()?;
+        let files = ["a", "bad", "c"].map(|name| tempdir.path().join(name));
+        for (file, content) in files.iter().zip(["a", "bad a", "c"]) {
+            std::fs::write(file, content)?;
+        }
+
+        let results = formatter.format_all(&files);
+        assert!(
+            matches!(results[..], [Ok(true), Err(_), Ok(false)]),
+            "{results:?}"
+        );
+        assert_eq!(std::fs::read_to_string(&files[0])?, "b");
+        assert_eq!(std::fs::read_to_string(&files[1])?, "bad a");
+        assert_eq!(std::fs::read_to_string(&files[2])?, "c");
+        Ok(())
+    }
+
+    #[test]
+    fn whitespace_formatter_only_normalizes_whitespace() -> anyhow::Result<()> {
+        let whitespace_formatter = WhitespaceFormatter {
+            indent_unit: None,
+            tab_width: 4,
+            tree_sitter_language: None,
+        };
+        let formatter = Formatter::whitespace(whitespace_formatter);
+        assert_eq!(
+            formatter.format("a  \n\tb = 1;\t\n\n  c\n\n\n")?,
+            "a\n\tb = 1;\n\n  c\n"
+        );
+        assert_eq!(formatter.format("a\r\nb  ")?, "a\r\nb\r\n");
+        assert_eq!(formatter.format("a\n")?, "a\n");
+        assert_eq!(formatter.format("")?, "");
+
+        let spaces = WhitespaceFormatter {
+            indent_unit: Some(IndentUnit::Spaces(2)),
+            tab_width: 2,
+            tree_sitter_language: None,
+        };
+        assert_eq!(spaces.format("\tif x:\n\t\ty\n"), "  if x:\n    y\n");
+        let tabs = WhitespaceFormatter {
+            indent_unit: Some(IndentUnit::Tab),
+            tab_width: 4,
+            tree_sitter_language: None,
+        };
+        assert_eq!(tabs.format("    a\n      b\n"), "\ta\n\t  b\n");
+
+        // A tab is converted to the width of the indent unit instead of `tab_width`
+        let spaces = WhitespaceFormatter {
+            indent_unit: Some(IndentUnit::Spaces(4)),
+            tab_width: 8,
+            tree_sitter_language: None,
+        };
+        assert_eq!(
+            spaces.format("\ta\n\t\tb\n\t  c\n"),
+            "    a\n        b\n      c\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn whitespace_formatter_keeps_the_whitespace_of_multi_line_strings() {
+        let python = crate::languages::languages().remove("python").unwrap();
+        let formatter = WhitespaceFormatter {
+            indent_unit: Some(IndentUnit::Spaces(4)),
+            tab_width: 8,
+            tree_sitter_language: python.tree_sitter_language(),
+        };
+        assert_eq!(
+            formatter.format("def f():  \n\tx = \"\"\"  \n\t  kept  \n\t\"\"\"\n\treturn x\n"),
+            "def f():\n    x = \"\"\"  \n\t  kept  \n\t\"\"\"\n    return x\n"
+        );
+    }
 }
@@ -1,11 +1,29 @@
-use grammar::grammar::GrammarConfiguration;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use grammar::grammar::{GrammarConfiguration, GrammarPhase};
+use itertools::Itertools;
+use once_cell::sync::Lazy;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tree_sitter::Query;
+use tree_sitter::{Query, QueryError, QueryErrorKind};
 
 pub(crate) use crate::process_command::ProcessCommand;
-use crate::{formatter::Formatter, ts_highlight_query::get_highlight_query};
+use crate::{
+    formatter::{Formatter, FormattersMode, LineEnding, WhitespaceFormatter},
+    query_load_metrics::{self, QueryKind, QueryLoadMetrics},
+    ts_brackets_query::get_brackets_query,
+    ts_highlight_query::{get_file_highlight_query, get_highlight_query},
+    ts_injection_query::{get_injection_query, normalize_injection_language},
+    ts_locals_query::get_locals_query,
+};
 
 #[derive(
     Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
@@ -46,19 +64,387 @@ impl Command {
 pub struct Language {
     #[schemars(example = &["ts", "tsx"])]
     pub(crate) extensions: Vec<String>,
+    /// The extension of new files of this language, which defaults to the first of `extensions`.
+    #[schemars(example = &"ts")]
+    pub(crate) primary_extension: Option<String>,
     /// For files without extensions.
     #[schemars(example = &["Dockerfile"])]
     pub(crate) file_names: Vec<String>,
     pub(crate) lsp_language_id: Option<LanguageId>,
+    /// The `languageId` sent to the LSP server for files of these extensions
+    /// instead of `lsp_language_id`.
+    #[schemars(example = &[("tsx", "typescriptreact")])]
+    #[serde(default)]
+    pub(crate) lsp_language_id_overrides: Vec<(String, LanguageId)>,
     pub(crate) lsp_command: Option<LspCommand>,
+    /// The position encoding to be requested from the LSP server.
+    /// Servers that do not support it fall back to `Utf16`.
+    pub(crate) lsp_position_encoding: Option<PositionEncoding>,
+    /// Files that mark the root directory of a project, in order of priority,
+    /// which is used as the workspace folder of the LSP server.
+    /// A marker can be a glob of file or directory names, for example, `*.xcodeproj`.
+    /// Defaults to the current working directory if none of them is found.
+    #[schemars(example = &["flake.nix", "default.nix"])]
+    #[serde(default)]
+    pub(crate) lsp_root_markers: Vec<String>,
     pub(crate) tree_sitter_grammar_config: Option<GrammarConfig>,
+    /// Grammars used instead of `tree_sitter_grammar_config` for files whose content matches,
+    /// for example, C headers containing `template<` are parsed as C++.
+    /// The first matching one is chosen.
+    #[serde(default)]
+    pub(crate) content_grammar_configs: Vec<ContentGrammarConfig>,
     /// The formatter command will receive the content from STDIN
     /// and is expected to return the formatted output to STDOUT.
+    ///
+    /// The `{tab_width}` and `{use_tabs}` placeholders in the arguments
+    /// will be substituted with the indentation settings of this language,
+    /// and `{max_line_length}` with `max_line_length` if it is set,
+    /// otherwise the arguments containing `{max_line_length}` are dropped,
+    /// so it should be in the same argument as its flag, for example, `--print-width={max_line_length}`.
     pub(crate) formatter: Option<Command>,
+    /// Other formatters, which are combined with `formatter` according to `formatters_mode`,
+    /// with `formatter` being the first.
+    #[serde(default)]
+    pub(crate) extra_formatters: Vec<Command>,
+    /// Defaults to `FirstAvailable`.
+    #[serde(default)]
+    pub(crate) formatters_mode: FormattersMode,
+    /// Whether files are formatted when saved. Defaults to `true`.
+    #[serde(default = "default_true")]
+    pub(crate) format_on_save: bool,
+    /// The line ending of the content piped to the formatter,
+    /// for formatters that cannot handle the line ending of the document.
+    /// The output is always converted back to the line ending of the document.
+    ///
+    /// Defaults to the line ending of the document.
+    pub(crate) format_line_ending: Option<LineEnding>,
+    /// Whether the built-in whitespace formatter is used if no formatter is available,
+    /// which trims trailing whitespace, ensures a final newline,
+    /// and converts the indentation to `indent_unit` if it is set.
+    #[serde(default)]
+    pub(crate) enable_whitespace_formatter: bool,
+    /// Whether `format_range` expands the range to the statements enclosing it,
+    /// because formatting part of a statement often produces invalid code.
+    #[serde(default)]
+    pub(crate) format_range_expands_to_statements: bool,
     #[schemars(example = "//")]
     pub(crate) line_comment_prefix: Option<String>,
+    /// Other prefixes that also start a line comment.
+    /// `line_comment_prefix` is still the one used when commenting.
+    #[schemars(example = &["#"])]
+    #[serde(default)]
+    pub(crate) extra_line_comment_prefixes: Vec<String>,
+    /// The prefix of documentation comments, for example, `///` in Rust.
+    #[schemars(example = "///")]
+    pub(crate) doc_comment_prefix: Option<String>,
+    /// Whether a space is inserted after `line_comment_prefix` when commenting,
+    /// for example, `// code` instead of `//code`.
+    /// Both forms are recognized when uncommenting.
+    #[serde(default = "default_true")]
+    pub(crate) line_comment_padding: bool,
+    /// Line comments starting with any of these are directives to tools,
+    /// for example, `//go:build linux`, so they are not treated as comments:
+    /// `toggle_line_comment` leaves them as is, and `strip_comments` keeps them.
+    #[schemars(example = &["//go:", "// +build"])]
+    #[serde(default)]
+    pub(crate) comment_directive_prefixes: Vec<String>,
     #[schemars(example = ("/*", "*/"))]
     pub(crate) block_comment_affixes: Option<(String, String)>,
+    /// Whether block comments can be nested,
+    /// for example, `/* a /* b */ c */` is a single comment in Rust.
+    #[serde(default)]
+    pub(crate) block_comment_nestable: bool,
+    /// Renames the captures of the highlight query, applied in order,
+    /// for example, `("type", "type.builtin")` rewrites `@type` to `@type.builtin`.
+    ///
+    /// Captures that are not mapped are left unchanged.
+    #[schemars(example = &[("type", "type.builtin")])]
+    #[serde(default)]
+    pub(crate) highlight_capture_remap: Vec<(String, String)>,
+    /// Use both the highlight query of nvim-treesitter and the default query of the grammar,
+    /// instead of only the first one that is valid.
+    /// The nvim-treesitter captures take precedence when both match the same node.
+    #[serde(default)]
+    pub(crate) highlight_query_merge: bool,
+    /// Highlight queries exceeding this budget are ignored like invalid queries,
+    /// because a pathological query can make highlighting unusably slow.
+    /// Defaults to unbounded.
+    pub(crate) highlight_query_budget: Option<HighlightQueryBudget>,
+    /// Files matching a glob use the highlight query file paired with it,
+    /// instead of the highlight query of the language, for example,
+    /// `("**/package.json", "~/.config/ki/package-json-highlights.scm")`.
+    /// A leading `~` of the file is expanded into the home directory.
+    ///
+    /// An override that cannot be read or is invalid is ignored.
+    #[serde(default)]
+    pub(crate) highlight_query_overrides: Vec<(String, PathBuf)>,
+    /// The highlight query file of `highlight_query_overrides` that is chosen
+    /// by `Language::for_path`, which is not configurable.
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub(crate) highlight_query_override: Option<PathBuf>,
+    /// For template grammars, the languages injected into their nodes, for example,
+    /// `("content", "html")` injects HTML into the text between the template tags.
+    /// The nodes of each kind are combined into one injection,
+    /// so that, for example, an element spanning multiple tags is parsed as a whole.
+    ///
+    /// This allows reusing a template grammar for templates of other languages.
+    #[schemars(example = &[("content", "html"), ("code", "ruby")])]
+    #[serde(default)]
+    pub(crate) template_injections: Vec<(String, String)>,
+    pub(crate) indent_unit: Option<IndentUnit>,
+    /// Whether the lines of a recipe must be indented with a tab, like in Makefiles,
+    /// in which case the recipe lines indented with spaces are warned about.
+    #[serde(default)]
+    pub(crate) recipe_indent_must_be_tab: bool,
+    /// Files matching any of these globs, such as generated files,
+    /// are neither formatted nor opened in the LSP server.
+    #[schemars(example = &["*.pb.go", "*.min.js"])]
+    #[serde(default)]
+    pub(crate) ignore_globs: Vec<String>,
+    /// The display width of a tab character.
+    /// Defaults to the width of `indent_unit` if it is `Spaces`, or 4 otherwise.
+    pub(crate) tab_width: Option<usize>,
+    /// The name of the file that this language is specialized for by `Language::for_path`,
+    /// which is not configurable.
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub(crate) specialized_file_name: Option<String>,
+    /// The preferred maximum length of a line, in characters.
+    pub(crate) max_line_length: Option<usize>,
+    /// Files larger than this, in bytes, are not parsed nor highlighted,
+    /// because parsing a huge file (e.g. a minified bundle) freezes the editor.
+    /// Defaults to 10 MiB.
+    pub(crate) max_grammar_file_size: Option<usize>,
+    /// Whether the grammar depends on indentation, for example, Python and YAML,
+    /// in which case an edit can change the meaning of the whole block it is in,
+    /// so `reparse_range` widens the edited range to the enclosing top-level block.
+    #[serde(default)]
+    pub(crate) whitespace_sensitive: bool,
+    /// Characters that are part of a word, in addition to alphanumerics, `_` and `-`,
+    /// for example, `./~` so that a path in a shell script is selected as one word.
+    #[schemars(example = "./~")]
+    pub(crate) word_chars: Option<String>,
+    /// A small valid source of this language, which `Language::self_test` parses
+    /// to check that the grammar works.
+    #[schemars(example = "fn main() {}")]
+    pub(crate) grammar_self_test_sample: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum IndentUnit {
+    Tab,
+    Spaces(usize),
+}
+
+const DEFAULT_TAB_WIDTH: usize = 4;
+
+fn default_true() -> bool {
+    true
+}
+
+const DEFAULT_MAX_GRAMMAR_FILE_SIZE: usize = 10 * 1024 * 1024;
+
+/// The unit of the `character` offset of a LSP position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize, JsonSchema)]
+pub enum PositionEncoding {
+    Utf8,
+    /// This is the default of the LSP protocol, which every server must support.
+    #[default]
+    Utf16,
+    /// The offset is counted in Unicode code points, which is the same as `char` in Rust.
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Returns the encoding picked by the server, or `Utf16` if the server did not pick any.
+    pub fn negotiated(server_capabilities: &lsp_types::ServerCapabilities) -> Self {
+        match &server_capabilities.position_encoding {
+            Some(kind) if *kind == lsp_types::PositionEncodingKind::UTF8 => Self::Utf8,
+            Some(kind) if *kind == lsp_types::PositionEncodingKind::UTF32 => Self::Utf32,
+            _ => Self::Utf16,
+        }
+    }
+
+    pub fn to_lsp(self) -> lsp_types::PositionEncodingKind {
+        match self {
+            Self::Utf8 => lsp_types::PositionEncodingKind::UTF8,
+            Self::Utf16 => lsp_types::PositionEncodingKind::UTF16,
+            Self::Utf32 => lsp_types::PositionEncodingKind::UTF32,
+        }
+    }
+
+    fn char_len(self, char: char) -> usize {
+        match self {
+            Self::Utf8 => char.len_utf8(),
+            Self::Utf16 => char.len_utf16(),
+            Self::Utf32 => 1,
+        }
+    }
+
+    /// Converts the `char` column of `line` to the `character` offset of a LSP position.
+    pub fn char_column_to_character(self, line: &str, column: usize) -> u32 {
+        line.chars()
+            .take(column)
+            .map(|char| self.char_len(char))
+            .sum::<usize>() as u32
+    }
+
+    /// Converts the `character` offset of a LSP position to the `char` column of `line`.
+    ///
+    /// An offset that points into the middle of a `char` is rounded down.
+    pub fn character_to_char_column(self, line: &str, character: u32) -> usize {
+        let mut offset = 0;
+        line.chars()
+            .take_while(|char| {
+                offset += self.char_len(*char);
+                offset <= character as usize
+            })
+            .count()
+    }
+}
+
+/// A hunk of a unified diff, where every line is zero-based within the diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffHunk {
+    /// The file name in the `---` header, for example, `a/src/main.rs`.
+    pub old_file: Option<String>,
+    /// The file name in the `+++` header, for example, `b/src/main.rs`.
+    pub new_file: Option<String>,
+    /// From the `@@` line to the last line of the hunk.
+    pub lines: Range<usize>,
+    /// The consecutive lines starting with `+`.
+    pub added_lines: Vec<Range<usize>>,
+    /// The consecutive lines starting with `-`.
+    pub removed_lines: Vec<Range<usize>>,
+}
+
+/// The zero-based lines of a foldable region, both inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FoldRange {
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+/// Every comment setting of a language, see `Language::effective_comment_syntax`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommentSyntax {
+    /// Starting with the prefix used when commenting.
+    pub line_prefixes: Vec<String>,
+    /// Whether a space follows the line prefix when commenting.
+    pub line_padding: bool,
+    pub doc_prefix: Option<String>,
+    pub block_affixes: Option<(String, String)>,
+    pub block_nestable: bool,
+}
+
+/// A problem found by [`Language::lint_highlight_query`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum HighlightQueryWarning {
+    /// The query does not compile, for example, because it names a node type
+    /// that does not exist in the grammar.
+    InvalidQuery(QueryError),
+    /// The capture matched nothing in the sample, so it probably has no effect.
+    DeadCapture { capture_name: String },
+}
+
+/// Why [`Language::self_test`] failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrammarSelfTestError {
+    /// The grammar cannot be loaded, for example, because it is not built.
+    GrammarUnavailable,
+    /// The parser gave up, for example, because it is incompatible with the grammar.
+    ParseFailed,
+    /// The sample was parsed with an `ERROR` or missing node at `byte_range`.
+    SyntaxError { byte_range: Range<usize> },
+}
+
+impl std::fmt::Display for GrammarSelfTestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GrammarSelfTestError::GrammarUnavailable => write!(f, "The grammar is unavailable"),
+            GrammarSelfTestError::ParseFailed => write!(f, "The sample could not be parsed"),
+            GrammarSelfTestError::SyntaxError { byte_range } => write!(
+                f,
+                "The sample has a syntax error at bytes {}..{}",
+                byte_range.start, byte_range.end
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GrammarSelfTestError {}
+
+/// The files that a LSP server asked to be notified about,
+/// by registering `workspace/didChangeWatchedFiles` dynamically.
+///
+/// There are no watchers unless the server registers some.
+#[derive(Debug, Clone, Default)]
+pub struct LspFileWatchers {
+    /// The glob patterns of each registration ID.
+    registrations: Vec<(String, Vec<String>)>,
+    /// The globs of every registration, which are rebuilt when the registrations change,
+    /// because `is_watched` is asked for every changed file.
+    glob_set: GlobSet,
+}
+
+impl LspFileWatchers {
+    pub fn register(&mut self, registrations: &[lsp_types::Registration]) {
+        for registration in registrations {
+            if registration.method != "workspace/didChangeWatchedFiles" {
+                continue;
+            }
+            let Some(options) = registration.register_options.clone().and_then(|options| {
+                serde_json::from_value::<lsp_types::DidChangeWatchedFilesRegistrationOptions>(
+                    options,
+                )
+                .ok()
+            }) else {
+                continue;
+            };
+            let globs = options
+                .watchers
+                .into_iter()
+                .map(|watcher| match watcher.glob_pattern {
+                    lsp_types::GlobPattern::String(pattern) => pattern,
+                    // The base of a relative pattern is the workspace folder in practice
+                    lsp_types::GlobPattern::Relative(relative) => relative.pattern,
+                })
+                .collect();
+            self.registrations.push((registration.id.clone(), globs));
+        }
+        self.rebuild_glob_set();
+    }
+
+    pub fn unregister(&mut self, unregistrations: &[lsp_types::Unregistration]) {
+        self.registrations.retain(|(id, _)| {
+            !unregistrations
+                .iter()
+                .any(|unregistration| &unregistration.id == id)
+        });
+        self.rebuild_glob_set();
+    }
+
+    fn rebuild_glob_set(&mut self) {
+        let mut builder = GlobSetBuilder::new();
+        for glob in self.globs() {
+            if let Ok(glob) = Glob::new(glob) {
+                builder.add(glob);
+            }
+        }
+        self.glob_set = builder.build().unwrap_or_default();
+    }
+
+    pub fn globs(&self) -> Vec<&str> {
+        self.registrations
+            .iter()
+            .flat_map(|(_, globs)| globs.iter().map(|glob| glob.as_str()))
+            .collect()
+    }
+
+    pub fn is_watched(&self, path: &Path) -> bool {
+        self.glob_set.is_match(path)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
@@ -81,6 +467,7 @@ pub enum CargoLinkedTreesitterLanguage {
     XML,
     Zig,
     Markdown,
+    MarkdownInline,
     Go,
     Lua,
     Gleam,
@@ -98,9 +485,89 @@ pub enum CargoLinkedTreesitterLanguage {
     Toml,
     KiQuickfix,
     Haskell,
+    Asm,
+    Makefile,
+    EmbeddedTemplate,
+    Regex,
 }
 
+/// `tree-sitter-asm` does not export a highlight query,
+/// so this is a trimmed-down version of the nvim-treesitter one.
+const ASM_HIGHLIGHT_QUERY: &str = r#"
+(label [(ident) (word)] @label)
+(reg) @variable.builtin
+(meta kind: (_) @function.builtin)
+(instruction kind: (_) @function.builtin)
+(const name: (word) @constant)
+[(line_comment) (block_comment)] @comment
+(int) @number
+(float) @number.float
+(string) @string
+"#;
+
+/// `tree-sitter-make` does not export a highlight query either.
+const MAKEFILE_HIGHLIGHT_QUERY: &str = r#"
+(comment) @comment
+(rule (targets (word) @function))
+(variable_assignment name: (word) @variable)
+(variable_reference (word) @variable)
+(recipe_line) @string.special
+["ifeq" "ifneq" "ifdef" "ifndef" "else" "endif"] @keyword.conditional
+["include" "-include" "define" "endef" "export" "unexport" "override"] @keyword
+["=" ":=" "::=" "?=" "+=" ":" "::" "|"] @operator
+"#;
+
 impl CargoLinkedTreesitterLanguage {
+    /// The conventional file extensions of files written in this grammar,
+    /// which can be fewer than the `extensions` of the built-in language using it.
+    pub fn default_extensions(&self) -> &'static [&'static str] {
+        match self {
+            CargoLinkedTreesitterLanguage::Typescript => &["ts"],
+            CargoLinkedTreesitterLanguage::TSX => &["tsx"],
+            CargoLinkedTreesitterLanguage::Python => &["py"],
+            CargoLinkedTreesitterLanguage::Julia => &["jl"],
+            CargoLinkedTreesitterLanguage::Scheme => &["scm", "ss"],
+            CargoLinkedTreesitterLanguage::OCaml => &["ml"],
+            CargoLinkedTreesitterLanguage::OCamlInterface => &["mli"],
+            CargoLinkedTreesitterLanguage::Rust => &["rs"],
+            CargoLinkedTreesitterLanguage::Graphql => &["graphql", "gql"],
+            CargoLinkedTreesitterLanguage::Javascript => &["js"],
+            CargoLinkedTreesitterLanguage::JSX => &["jsx"],
+            CargoLinkedTreesitterLanguage::Svelte => &["svelte"],
+            CargoLinkedTreesitterLanguage::JSON => &["json"],
+            CargoLinkedTreesitterLanguage::YAML => &["yaml", "yml"],
+            CargoLinkedTreesitterLanguage::HTML => &["html", "htm"],
+            CargoLinkedTreesitterLanguage::XML => &["xml"],
+            CargoLinkedTreesitterLanguage::Zig => &["zig"],
+            CargoLinkedTreesitterLanguage::Markdown => &["md"],
+            // The inline grammar is only injected into the paragraphs of the block grammar
+            CargoLinkedTreesitterLanguage::MarkdownInline => &[],
+            CargoLinkedTreesitterLanguage::Go => &["go"],
+            CargoLinkedTreesitterLanguage::Lua => &["lua"],
+            CargoLinkedTreesitterLanguage::Gleam => &["gleam"],
+            CargoLinkedTreesitterLanguage::Bash => &["sh", "bash"],
+            CargoLinkedTreesitterLanguage::C => &["c", "h"],
+            CargoLinkedTreesitterLanguage::CPP => &["cpp", "cc", "hpp"],
+            CargoLinkedTreesitterLanguage::CSS => &["css"],
+            CargoLinkedTreesitterLanguage::Ruby => &["rb"],
+            CargoLinkedTreesitterLanguage::Nix => &["nix"],
+            CargoLinkedTreesitterLanguage::Fish => &["fish"],
+            CargoLinkedTreesitterLanguage::Diff => &["diff"],
+            CargoLinkedTreesitterLanguage::Elixir => &["ex", "exs"],
+            CargoLinkedTreesitterLanguage::Swift => &["swift"],
+            CargoLinkedTreesitterLanguage::Heex => &["heex"],
+            CargoLinkedTreesitterLanguage::Toml => &["toml"],
+            // Quickfix lists are internal buffers instead of files
+            CargoLinkedTreesitterLanguage::KiQuickfix => &[],
+            CargoLinkedTreesitterLanguage::Haskell => &["hs"],
+            CargoLinkedTreesitterLanguage::Asm => &["asm", "s"],
+            CargoLinkedTreesitterLanguage::Makefile => &["mk"],
+            CargoLinkedTreesitterLanguage::EmbeddedTemplate => &["erb"],
+            // Regexes are only injected into other languages
+            CargoLinkedTreesitterLanguage::Regex => &[],
+        }
+    }
+
     pub(crate) fn to_tree_sitter_language(&self) -> tree_sitter::Language {
         match self {
             CargoLinkedTreesitterLanguage::Typescript => {
@@ -126,6 +593,7 @@ impl CargoLinkedTreesitterLanguage {
             CargoLinkedTreesitterLanguage::XML => tree_sitter_xml::LANGUAGE_XML.into(),
             CargoLinkedTreesitterLanguage::Zig => tree_sitter_zig::LANGUAGE.into(),
             CargoLinkedTreesitterLanguage::Markdown => tree_sitter_md::LANGUAGE.into(),
+            CargoLinkedTreesitterLanguage::MarkdownInline => tree_sitter_md::INLINE_LANGUAGE.into(),
             CargoLinkedTreesitterLanguage::Go => tree_sitter_go::LANGUAGE.into(),
             CargoLinkedTreesitterLanguage::Lua => tree_sitter_lua::LANGUAGE.into(),
             CargoLinkedTreesitterLanguage::Gleam => tree_sitter_gleam::LANGUAGE.into(),
@@ -142,6 +610,12 @@ impl CargoLinkedTreesitterLanguage {
             CargoLinkedTreesitterLanguage::Heex => tree_sitter_heex::LANGUAGE.into(),
             CargoLinkedTreesitterLanguage::Toml => tree_sitter_toml_ng::LANGUAGE.into(),
             CargoLinkedTreesitterLanguage::KiQuickfix => tree_sitter_quickfix::language(),
+            CargoLinkedTreesitterLanguage::Asm => tree_sitter_asm::LANGUAGE.into(),
+            CargoLinkedTreesitterLanguage::Makefile => tree_sitter_make::LANGUAGE.into(),
+            CargoLinkedTreesitterLanguage::EmbeddedTemplate => {
+                tree_sitter_embedded_template::LANGUAGE.into()
+            }
+            CargoLinkedTreesitterLanguage::Regex => tree_sitter_regex::LANGUAGE.into(),
         }
     }
 
@@ -172,6 +646,9 @@ impl CargoLinkedTreesitterLanguage {
             CargoLinkedTreesitterLanguage::XML => Some(tree_sitter_xml::XML_HIGHLIGHT_QUERY),
             CargoLinkedTreesitterLanguage::Zig => Some(tree_sitter_zig::HIGHLIGHTS_QUERY),
             CargoLinkedTreesitterLanguage::Markdown => Some(tree_sitter_md::HIGHLIGHT_QUERY_BLOCK),
+            CargoLinkedTreesitterLanguage::MarkdownInline => {
+                Some(tree_sitter_md::HIGHLIGHT_QUERY_INLINE)
+            }
             CargoLinkedTreesitterLanguage::Go => Some(tree_sitter_go::HIGHLIGHTS_QUERY),
             CargoLinkedTreesitterLanguage::Lua => Some(tree_sitter_lua::HIGHLIGHTS_QUERY),
             CargoLinkedTreesitterLanguage::Gleam => Some(tree_sitter_gleam::HIGHLIGHT_QUERY),
@@ -188,6 +665,12 @@ impl CargoLinkedTreesitterLanguage {
             CargoLinkedTreesitterLanguage::Heex => Some(tree_sitter_heex::HIGHLIGHTS_QUERY),
             CargoLinkedTreesitterLanguage::Toml => Some(tree_sitter_toml_ng::HIGHLIGHTS_QUERY),
             CargoLinkedTreesitterLanguage::KiQuickfix => Some(r#" (header) @keyword"#),
+            CargoLinkedTreesitterLanguage::Asm => Some(ASM_HIGHLIGHT_QUERY),
+            CargoLinkedTreesitterLanguage::Makefile => Some(MAKEFILE_HIGHLIGHT_QUERY),
+            CargoLinkedTreesitterLanguage::EmbeddedTemplate => {
+                Some(tree_sitter_embedded_template::HIGHLIGHTS_QUERY)
+            }
+            CargoLinkedTreesitterLanguage::Regex => Some(tree_sitter_regex::HIGHLIGHTS_QUERY),
         }
     }
 }
@@ -197,22 +680,235 @@ impl CargoLinkedTreesitterLanguage {
 pub struct LspCommand {
     pub(crate) command: Command,
     pub(crate) initialization_options: Option<serde_json::Value>,
+    /// Merged into `initialization_options`, in order,
+    /// if their `file` exists in the workspace folder.
+    #[serde(default)]
+    pub(crate) conditional_initialization_options: Vec<ConditionalInitializationOptions>,
+    /// Whether the server works without a workspace root, for example, a shell script server,
+    /// in which case no workspace folder is sent and `lsp_root_markers` are not searched.
+    #[serde(default)]
+    pub(crate) lsp_single_file_support: bool,
+    #[serde(default)]
+    pub(crate) lsp_save_options: LspSaveOptions,
+    /// Only code actions of these kinds are requested, for example, `quickfix`
+    /// and `refactor.extract`, for servers that offer many unwanted code actions.
+    /// Defaults to all kinds.
+    #[schemars(example = &["quickfix", "refactor.extract"])]
+    pub(crate) lsp_code_action_kinds: Option<Vec<String>>,
+    /// Characters that trigger completion, in addition to those advertised by the server,
+    /// for servers that under-report them.
+    #[schemars(example = &[".", ":"])]
+    #[serde(default)]
+    pub(crate) lsp_extra_trigger_characters: Vec<String>,
+    /// The maximum number of requests awaiting a response from the server,
+    /// beyond which requests are queued, for slow servers. Defaults to unbounded.
+    pub(crate) lsp_max_concurrent_requests: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct HighlightQueryBudget {
+    pub(crate) max_patterns: Option<usize>,
+    /// The maximum number of distinct capture names.
+    pub(crate) max_captures: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryComplexity {
+    pub pattern_count: usize,
+    /// The number of distinct capture names, including those used by predicates only.
+    pub capture_count: usize,
+}
+
+impl QueryComplexity {
+    fn of(query: &Query) -> Self {
+        Self {
+            pattern_count: query.pattern_count(),
+            capture_count: query.capture_names().len(),
+        }
+    }
+}
+
+/// The highlight query chosen by `Language::chosen_highlight_query`,
+/// with its complexity if it was compiled while being chosen, so that it is not compiled again.
+struct ChosenHighlightQuery {
+    query: String,
+    complexity: Option<QueryComplexity>,
+    compile_duration: Option<Duration>,
+}
+
+impl ChosenHighlightQuery {
+    fn uncompiled(query: String) -> Self {
+        Self {
+            query,
+            complexity: None,
+            compile_duration: None,
+        }
+    }
+
+    fn compiled(query: String, compiled: &CompiledQuery) -> Self {
+        Self {
+            query,
+            complexity: Some(QueryComplexity::of(&compiled.query)),
+            compile_duration: Some(compiled.duration),
+        }
+    }
+}
+
+/// A query compiled against the grammar of a language, with how long the compile took.
+struct CompiledQuery {
+    query: Query,
+    duration: Duration,
+}
+
+/// Overrides how the save notifications are sent to the LSP server,
+/// for servers that advertise a behavior different from what they need.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LspSaveOptions {
+    /// Whether `textDocument/didSave` includes the saved text.
+    /// Defaults to the `save.includeText` advertised by the server.
+    pub(crate) include_text: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConditionalInitializationOptions {
+    /// Relative to the workspace folder.
+    #[schemars(example = "rust-toolchain.toml")]
+    pub(crate) file: String,
+    pub(crate) options: serde_json::Value,
+}
+
+/// Pairs the bracket at `byte_index` with the nearest unbalanced bracket of the same kind.
+fn matching_bracket_by_counting(source: &str, byte_index: usize) -> Option<usize> {
+    const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+    let bracket = source.get(byte_index..)?.chars().next()?;
+    let (open, close, forward) = PAIRS.iter().find_map(|(open, close)| {
+        if bracket == *open {
+            Some((*open, *close, true))
+        } else if bracket == *close {
+            Some((*open, *close, false))
+        } else {
+            None
+        }
+    })?;
+    let mut depth = 0;
+    let mut check = |(index, char): (usize, char)| {
+        if char == open {
+            depth += if forward { 1 } else { -1 };
+        } else if char == close {
+            depth += if forward { -1 } else { 1 };
+        }
+        (depth == 0).then_some(index)
+    };
+    if forward {
+        source[byte_index..]
+            .char_indices()
+            .map(|(index, char)| (byte_index + index, char))
+            .find_map(&mut check)
+    } else {
+        source[..=byte_index]
+            .char_indices()
+            .rev()
+            .find_map(&mut check)
+    }
+}
+
+/// Merges `overrides` into `base`, recursing into objects, and replacing any other values.
+fn merge_json(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge_json(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
 }
 
 impl Language {
     pub const fn new() -> Self {
         Self {
             extensions: Vec::new(),
+            primary_extension: None,
             file_names: Vec::new(),
             lsp_language_id: None,
+            lsp_language_id_overrides: Vec::new(),
             lsp_command: None,
+            lsp_position_encoding: None,
+            lsp_root_markers: Vec::new(),
             tree_sitter_grammar_config: None,
+            content_grammar_configs: Vec::new(),
             formatter: None,
+            extra_formatters: Vec::new(),
+            formatters_mode: FormattersMode::FirstAvailable,
+            format_on_save: true,
+            format_line_ending: None,
+            enable_whitespace_formatter: false,
+            format_range_expands_to_statements: false,
             line_comment_prefix: None,
+            extra_line_comment_prefixes: Vec::new(),
+            doc_comment_prefix: None,
+            line_comment_padding: true,
+            comment_directive_prefixes: Vec::new(),
             block_comment_affixes: None,
+            block_comment_nestable: false,
+            highlight_capture_remap: Vec::new(),
+            highlight_query_merge: false,
+            highlight_query_budget: None,
+            highlight_query_overrides: Vec::new(),
+            highlight_query_override: None,
+            template_injections: Vec::new(),
+            indent_unit: None,
+            recipe_indent_must_be_tab: false,
+            ignore_globs: Vec::new(),
+            tab_width: None,
+            specialized_file_name: None,
+            max_line_length: None,
+            max_grammar_file_size: None,
+            whitespace_sensitive: false,
+            word_chars: None,
+            grammar_self_test_sample: None,
         }
     }
 
+    fn is_ignored(&self, path: &Path) -> bool {
+        // The globs are built once, because this is asked for every formatted or opened file
+        static IGNORE_GLOB_SETS: Lazy<Mutex<HashMap<Vec<String>, GlobSet>>> =
+            Lazy::new(Default::default);
+        IGNORE_GLOB_SETS
+            .lock()
+            .unwrap()
+            .entry(self.ignore_globs.clone())
+            .or_insert_with(|| {
+                let mut builder = GlobSetBuilder::new();
+                for glob in &self.ignore_globs {
+                    match Glob::new(glob) {
+                        Ok(glob) => {
+                            builder.add(glob);
+                        }
+                        Err(error) => log::error!("Invalid ignore glob {glob:?}: {error}"),
+                    }
+                }
+                builder.build().unwrap_or_default()
+            })
+            .is_match(path)
+    }
+
+    pub fn should_format(&self, path: &Path) -> bool {
+        self.format_on_save && !self.is_ignored(path)
+    }
+
+    pub fn should_attach_lsp(&self, path: &Path) -> bool {
+        !self.is_ignored(path)
+    }
+
     pub fn file_names(&self) -> &Vec<String> {
         &self.file_names
     }
@@ -221,13 +917,510 @@ impl Language {
         &self.lsp_language_id
     }
 
+    /// The `languageId` of `path` to be sent in `textDocument/didOpen`,
+    /// which is `lsp_language_id` unless overridden by `lsp_language_id_overrides`.
+    pub fn lsp_language_id_for(&self, path: &Path) -> Option<LanguageId> {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        self.lsp_language_id_overrides
+            .iter()
+            .find(|(override_extension, _)| Some(override_extension.as_str()) == extension)
+            .map(|(_, id)| id.clone())
+            .or_else(|| self.lsp_language_id.clone())
+    }
+
     pub fn line_comment_prefix(&self) -> Option<String> {
         self.line_comment_prefix.clone()
     }
 
+    /// The characters that are part of a word in addition to the default ones.
+    pub fn word_chars(&self) -> &str {
+        self.word_chars.as_deref().unwrap_or_default()
+    }
+
+    /// The comment syntax of this language as a whole, with the defaults applied.
+    pub fn effective_comment_syntax(&self) -> CommentSyntax {
+        CommentSyntax {
+            line_prefixes: self
+                .line_comment_prefixes()
+                .into_iter()
+                .filter(|prefix| !prefix.is_empty())
+                .collect(),
+            line_padding: self.line_comment_padding,
+            doc_prefix: self.doc_comment_prefix.clone(),
+            block_affixes: self.block_comment_affixes.clone(),
+            block_nestable: self.block_comment_nestable,
+        }
+    }
+
+    /// All prefixes recognized as line comments, starting with `line_comment_prefix`.
+    pub fn line_comment_prefixes(&self) -> Vec<String> {
+        self.line_comment_prefix
+            .iter()
+            .chain(self.extra_line_comment_prefixes.iter())
+            .cloned()
+            .collect()
+    }
+
+    fn is_comment_directive(&self, comment: &str) -> bool {
+        self.comment_directive_prefixes
+            .iter()
+            .any(|prefix| comment.trim_start().starts_with(prefix.as_str()))
+    }
+
+    /// Comments `lines` with `line_comment_prefix`, unless every non-blank line
+    /// is already commented with any of the `line_comment_prefixes`,
+    /// in which case the prefixes are removed instead.
+    ///
+    /// Like blank lines, comment directives (see `comment_directive_prefixes`) are left as is.
+    ///
+    /// The prefix is inserted after the smallest indentation among the lines,
+    /// so that the relative indentation of the lines is preserved.
+    pub fn toggle_line_comment(&self, lines: &[&str]) -> Vec<String> {
+        let Some(prefix) = &self.line_comment_prefix else {
+            return lines.iter().map(|line| line.to_string()).collect();
+        };
+        let prefixes = self.line_comment_prefixes();
+        let is_skipped = |line: &str| line.trim().is_empty() || self.is_comment_directive(line);
+        let comment_prefix_of = |line: &str| {
+            let content = line.trim_start();
+            prefixes
+                .iter()
+                .filter(|prefix| content.starts_with(prefix.as_str()))
+                .max_by_key(|prefix| prefix.len())
+                .cloned()
+        };
+        let non_blank_lines = lines.iter().filter(|line| !is_skipped(line)).collect_vec();
+        let all_commented = !non_blank_lines.is_empty()
+            && non_blank_lines
+                .iter()
+                .all(|line| comment_prefix_of(line).is_some());
+
+        if all_commented {
+            lines
+                .iter()
+                .map(|line| {
+                    if is_skipped(line) {
+                        return line.to_string();
+                    }
+                    match comment_prefix_of(line) {
+                        Some(prefix) => {
+                            let content = line.trim_start();
+                            let indentation = &line[..line.len() - content.len()];
+                            let content = &content[prefix.len()..];
+                            format!(
+                                "{indentation}{}",
+                                content.strip_prefix(' ').unwrap_or(content)
+                            )
+                        }
+                        None => line.to_string(),
+                    }
+                })
+                .collect()
+        } else {
+            let indentation_width = non_blank_lines
+                .iter()
+                .map(|line| line.chars().take_while(|char| char.is_whitespace()).count())
+                .min()
+                .unwrap_or_default();
+            lines
+                .iter()
+                .map(|line| {
+                    if is_skipped(line) {
+                        return line.to_string();
+                    }
+                    let (indentation, content) = line.split_at(
+                        line.char_indices()
+                            .nth(indentation_width)
+                            .map(|(index, _)| index)
+                            .unwrap_or(line.len()),
+                    );
+                    let padding = if self.line_comment_padding { " " } else { "" };
+                    format!("{indentation}{prefix}{padding}{content}")
+                })
+                .collect()
+        }
+    }
+
     pub fn block_comment_affixes(&self) -> Option<(String, String)> {
         self.block_comment_affixes.clone()
     }
+
+    /// Wraps `text` with `block_comment_affixes`, unless `text` is already a single block comment,
+    /// in which case the outermost affixes are removed instead.
+    ///
+    /// If block comments cannot be nested, the block comments within `text` are
+    /// uncommented before wrapping, because they would end the wrapping comment prematurely.
+    pub fn toggle_block_comment(&self, text: &str) -> String {
+        let Some((open, close)) = &self.block_comment_affixes else {
+            return text.to_string();
+        };
+        let content = text.trim();
+        let leading_whitespace = &text[..text.len() - text.trim_start().len()];
+        let trailing_whitespace = &text[text.trim_end().len()..];
+
+        let is_single_block_comment = content.starts_with(open.as_str())
+            && self.matching_block_comment_close(content, open, close)
+                == Some(content.len() - close.len());
+        let result = if is_single_block_comment {
+            let inner = &content[open.len()..content.len() - close.len()];
+            let inner = inner.strip_prefix(' ').unwrap_or(inner);
+            inner.strip_suffix(' ').unwrap_or(inner).to_string()
+        } else if self.block_comment_nestable {
+            format!("{open} {content} {close}")
+        } else {
+            let inner_affixes = regex::Regex::new(&format!(
+                r"{}\s?|\s?{}",
+                regex::escape(open),
+                regex::escape(close)
+            ))
+            .unwrap();
+            format!("{open} {} {close}", inner_affixes.replace_all(content, ""))
+        };
+        format!("{leading_whitespace}{result}{trailing_whitespace}")
+    }
+
+    /// Returns the byte index of the `close` that matches the `open` at the start of `content`.
+    fn matching_block_comment_close(
+        &self,
+        content: &str,
+        open: &str,
+        close: &str,
+    ) -> Option<usize> {
+        let mut depth = 0;
+        let mut index = 0;
+        while let Some(rest) = content.get(index..).filter(|rest| !rest.is_empty()) {
+            if rest.starts_with(open) && (depth == 0 || self.block_comment_nestable) {
+                depth += 1;
+                index += open.len();
+            } else if depth > 0 && rest.starts_with(close) {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+                index += close.len();
+            } else {
+                index += rest.chars().next()?.len_utf8();
+            }
+        }
+        None
+    }
+
+    /// Neutralizes the block comment delimiters in `text` by inserting a space after their first
+    /// character, for example, `*/` becomes `* /`, so that wrapping `text` in
+    /// `block_comment_affixes` gives one block comment.
+    ///
+    /// Opening delimiters are only neutralized if block comments are nestable,
+    /// because otherwise they have no effect inside a comment.
+    pub fn escape_for_comment(&self, text: &str) -> String {
+        let Some((open, close)) = &self.block_comment_affixes else {
+            return text.to_string();
+        };
+        let delimiters = Some(close)
+            .into_iter()
+            .chain(Some(open).filter(|_| self.block_comment_nestable))
+            .filter(|delimiter| delimiter.chars().count() > 1)
+            .collect_vec();
+        let mut text = text.to_string();
+        // Inserting a space can join the remaining characters into another delimiter,
+        // for example, `*/*/` when nestable, so repeat until none is left.
+        while let Some(delimiter) = delimiters
+            .iter()
+            .find(|delimiter| text.contains(delimiter.as_str()))
+        {
+            let first_char_len = delimiter.chars().next().map_or(0, char::len_utf8);
+            let escaped = format!(
+                "{} {}",
+                &delimiter[..first_char_len],
+                &delimiter[first_char_len..]
+            );
+            text = text.replace(delimiter.as_str(), &escaped);
+        }
+        text
+    }
+
+    /// Replaces every character of the comments in `source` with a space, except newlines,
+    /// so that the lines and columns of the remaining code are unchanged.
+    /// Comment directives (see `comment_directive_prefixes`) are kept.
+    ///
+    /// The comment nodes of the grammar are used if it is available,
+    /// otherwise the comment prefixes and affixes are searched outside of double-quoted strings.
+    pub fn strip_comments(&self, source: &str) -> String {
+        let comment_ranges = self
+            .comment_byte_ranges_from_grammar(source)
+            .unwrap_or_else(|| self.comment_byte_ranges_from_affixes(source))
+            .into_iter()
+            .filter(|range| !self.is_comment_directive(&source[range.clone()]))
+            .sorted_by_key(|range| range.start);
+        let mut stripped = String::with_capacity(source.len());
+        // The end of the part of `source` that is already in `stripped`
+        let mut copied_end = 0;
+        for range in comment_ranges {
+            // Overlapping ranges are only blanked once
+            let start = range.start.max(copied_end);
+            if start >= range.end {
+                continue;
+            }
+            stripped.push_str(&source[copied_end..start]);
+            stripped.extend(source[start..range.end].chars().map(|char| {
+                if char == '\n' {
+                    char
+                } else {
+                    ' '
+                }
+            }));
+            copied_end = range.end;
+        }
+        stripped.push_str(&source[copied_end..]);
+        stripped
+    }
+
+    /// The fraction of the non-whitespace bytes of `source` that are inside comments,
+    /// which are found like `strip_comments` does.
+    ///
+    /// This is 0.0 for an empty `source`, or when there is no way of finding comments.
+    pub fn comment_ratio(&self, source: &str) -> f32 {
+        let non_whitespace_bytes = |text: &str| {
+            text.chars()
+                .filter(|char| !char.is_whitespace())
+                .map(char::len_utf8)
+                .sum::<usize>()
+        };
+        let total = non_whitespace_bytes(source);
+        if total == 0 {
+            return 0.0;
+        }
+        let commented: usize = self
+            .comment_byte_ranges_from_grammar(source)
+            .unwrap_or_else(|| self.comment_byte_ranges_from_affixes(source))
+            .into_iter()
+            .filter_map(|range| source.get(range))
+            .map(non_whitespace_bytes)
+            .sum();
+        commented as f32 / total as f32
+    }
+
+    fn comment_byte_ranges_from_grammar(&self, source: &str) -> Option<Vec<Range<usize>>> {
+        let tree = self.parser()?.parse(source, None)?;
+        let mut cursor = tree.walk();
+        let mut ranges = Vec::new();
+        loop {
+            let node = cursor.node();
+            // For example, `comment`, `line_comment` and `block_comment`
+            let is_comment = node.kind().contains("comment");
+            if is_comment {
+                ranges.push(node.byte_range());
+            }
+            if !is_comment && cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    return Some(ranges);
+                }
+            }
+        }
+    }
+
+    fn comment_byte_ranges_from_affixes(&self, source: &str) -> Vec<Range<usize>> {
+        let line_comment_prefixes = self
+            .line_comment_prefix
+            .iter()
+            .chain(&self.extra_line_comment_prefixes)
+            .filter(|prefix| !prefix.is_empty())
+            .collect_vec();
+        let mut ranges = Vec::new();
+        let mut in_string = false;
+        let mut index = 0;
+        while let Some(rest) = source.get(index..).filter(|rest| !rest.is_empty()) {
+            let char = rest.chars().next().unwrap_or_default();
+            if in_string {
+                match char {
+                    '\\' => index += rest.chars().nth(1).map(char::len_utf8).unwrap_or(0),
+                    '"' | '\n' => in_string = false,
+                    _ => {}
+                }
+                index += char.len_utf8();
+                continue;
+            }
+            if let Some((open, close)) = self
+                .block_comment_affixes
+                .as_ref()
+                .filter(|(open, _)| rest.starts_with(open.as_str()))
+            {
+                let end = self
+                    .matching_block_comment_close(rest, open, close)
+                    .map(|close_index| index + close_index + close.len())
+                    .unwrap_or(source.len());
+                ranges.push(index..end);
+                index = end;
+            } else if line_comment_prefixes
+                .iter()
+                .any(|prefix| rest.starts_with(prefix.as_str()))
+            {
+                let end = rest.find('\n').map_or(source.len(), |end| index + end);
+                ranges.push(index..end);
+                index = end;
+            } else {
+                in_string = char == '"';
+                index += char.len_utf8();
+            }
+        }
+        ranges
+    }
+
+    pub fn indent_unit(&self) -> Option<IndentUnit> {
+        self.indent_unit
+    }
+
+    /// Warnings about the content that LSP servers do not report, which are
+    /// the recipe lines indented with spaces if `recipe_indent_must_be_tab` is set,
+    /// because, for example, `make` requires a tab.
+    ///
+    /// `lines` are only read if the language has such warnings.
+    pub fn content_warnings<Line: AsRef<str>>(
+        &self,
+        lines: impl IntoIterator<Item = Line>,
+    ) -> Vec<lsp_types::Diagnostic> {
+        if !self.recipe_indent_must_be_tab {
+            return Vec::new();
+        }
+        // e.g. `build: main.o`, but not `CC := gcc`
+        static RULE: Lazy<regex::Regex> =
+            Lazy::new(|| regex::Regex::new(r"^[^\s#][^=]*?:(?:[^=:]|$)").unwrap());
+        let mut in_recipe = false;
+        let mut is_continuation = false;
+        let mut warnings = Vec::new();
+        for (line_number, line) in lines.into_iter().enumerate() {
+            let line = line.as_ref().trim_end_matches(['\n', '\r']);
+            // The lines continuing a line ending with `\` can be indented freely
+            let continued = std::mem::replace(&mut is_continuation, line.ends_with('\\'));
+            let is_comment = line.trim_start().starts_with('#');
+            if continued || line.starts_with('\t') || line.trim().is_empty() || is_comment {
+                continue;
+            }
+            let spaces = line.len() - line.trim_start_matches(' ').len();
+            if in_recipe && spaces > 0 {
+                warnings.push(lsp_types::Diagnostic {
+                    range: lsp_types::Range::new(
+                        lsp_types::Position::new(line_number as u32, 0),
+                        lsp_types::Position::new(line_number as u32, spaces as u32),
+                    ),
+                    severity: Some(lsp_types::DiagnosticSeverity::WARNING),
+                    message: "Recipe lines must be indented with a tab instead of spaces"
+                        .to_string(),
+                    ..Default::default()
+                });
+                continue;
+            }
+            in_recipe = RULE.is_match(line);
+        }
+        warnings
+    }
+
+    /// Derives a fold range for every line that is followed by more indented lines,
+    /// as a fallback for languages without a better way of folding.
+    ///
+    /// Blank lines do not end a fold, but the trailing blank lines of a fold are excluded.
+    pub fn indent_fold_ranges(&self, source: &str) -> Vec<FoldRange> {
+        let tab_width = match self.indent_unit.or_else(|| Self::detect_indent(source)) {
+            Some(IndentUnit::Spaces(width)) if self.tab_width.is_none() => width,
+            _ => self.tab_width(),
+        };
+        let indentations = source.lines().enumerate().filter_map(|(line, content)| {
+            if content.trim().is_empty() {
+                return None;
+            }
+            let indentation = content
+                .chars()
+                .take_while(|char| char.is_whitespace())
+                .map(|char| if char == '\t' { tab_width } else { 1 })
+                .sum::<usize>();
+            Some((line, indentation))
+        });
+
+        let mut ranges = Vec::new();
+        // The lines that might start a fold, with their indentation
+        let mut stack: Vec<(usize, usize)> = Vec::new();
+        let mut last_line = 0;
+        for (line, indentation) in indentations {
+            while let Some(&(start_line, start_indentation)) = stack.last() {
+                if start_indentation < indentation {
+                    break;
+                }
+                stack.pop();
+                if last_line > start_line {
+                    ranges.push(FoldRange {
+                        start_line,
+                        end_line: last_line,
+                    });
+                }
+            }
+            stack.push((line, indentation));
+            last_line = line;
+        }
+        for (start_line, _) in stack {
+            if last_line > start_line {
+                ranges.push(FoldRange {
+                    start_line,
+                    end_line: last_line,
+                });
+            }
+        }
+        ranges.sort_by_key(|range| range.start_line);
+        ranges
+    }
+
+    /// Infers the indentation of `source`, useful when `indent_unit` is not configured.
+    ///
+    /// Tabs are used if more lines are indented with tabs than with spaces.
+    /// Otherwise, the width is the most common change of indentation between consecutive
+    /// space-indented lines, so that deeply nested lines do not skew the result.
+    pub fn detect_indent(source: &str) -> Option<IndentUnit> {
+        let indentations = source
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| &line[..line.len() - line.trim_start().len()])
+            .collect_vec();
+        let tab_lines = indentations
+            .iter()
+            .filter(|indentation| indentation.starts_with('\t'))
+            .count();
+        let space_widths = indentations
+            .iter()
+            .filter(|indentation| !indentation.contains('\t'))
+            .map(|indentation| indentation.len())
+            .collect_vec();
+        let space_lines = space_widths.iter().filter(|width| **width > 0).count();
+        if tab_lines == 0 && space_lines == 0 {
+            return None;
+        }
+        if tab_lines > space_lines {
+            return Some(IndentUnit::Tab);
+        }
+        space_widths
+            .iter()
+            .tuple_windows()
+            .map(|(previous, current)| previous.abs_diff(*current))
+            .filter(|delta| *delta > 0)
+            .counts()
+            .into_iter()
+            // Prefer the smaller width on ties
+            .max_by_key(|(width, count)| (*count, std::cmp::Reverse(*width)))
+            .map(|(width, _)| IndentUnit::Spaces(width))
+    }
+
+    pub fn tab_width(&self) -> usize {
+        self.tab_width
+            .or(match self.indent_unit {
+                Some(IndentUnit::Spaces(width)) => Some(width),
+                _ => None,
+            })
+            .unwrap_or(DEFAULT_TAB_WIDTH)
+    }
+
+    pub fn max_line_length(&self) -> Option<usize> {
+        self.max_line_length
+    }
 }
 
 impl Default for Language {
@@ -241,6 +1434,141 @@ impl Default for Language {
 pub struct GrammarConfig {
     pub id: String,
     pub kind: GrammarConfigKind,
+    /// Set this for large grammars that are rarely needed, so that they are skipped by
+    /// `ki @ grammar fetch` and `ki @ grammar build`, and are only fetched and built
+    /// in the background when a file of this language is first opened.
+    #[serde(default)]
+    pub lazy_load: bool,
+}
+
+/// The IDs of the lazy grammars whose fetching and building has been started.
+static REQUESTED_LAZY_GRAMMARS: Lazy<Mutex<HashSet<String>>> = Lazy::new(Default::default);
+
+type LazyGrammarLoadedHook = Box<dyn Fn(&str) + Send>;
+
+/// See `on_lazy_grammar_loaded`.
+static LAZY_GRAMMAR_LOADED_HOOK: Lazy<Mutex<Option<LazyGrammarLoadedHook>>> =
+    Lazy::new(Default::default);
+
+impl GrammarConfig {
+    fn tree_sitter_language(&self) -> Option<tree_sitter::Language> {
+        match &self.kind {
+            GrammarConfigKind::CargoLinked(language) => Some(language.to_tree_sitter_language()),
+            GrammarConfigKind::FromSource { .. } | GrammarConfigKind::FromLocalPath { .. } => {
+                let language = grammar::grammar::get_language(&self.id).ok();
+                if language.is_none() && self.lazy_load {
+                    self.request_lazy_load();
+                }
+                language
+            }
+        }
+    }
+
+    /// Fetch and build this grammar in the background, at most once per process.
+    /// Until it is built, files of this language are shown as plain text.
+    fn request_lazy_load(&self) {
+        let Ok(mut requested) = REQUESTED_LAZY_GRAMMARS.lock() else {
+            return;
+        };
+        if !requested.insert(self.id.clone()) {
+            return;
+        }
+        let Some(configuration) = self.grammar_configuration(None) else {
+            log::warn!(
+                "[GrammarConfig::request_lazy_load]: Cannot load the grammar {:?} on demand without an explicit `url`",
+                self.id
+            );
+            return;
+        };
+        log::info!(
+            "[GrammarConfig::request_lazy_load]: Loading the grammar {:?} on demand",
+            self.id
+        );
+        match grammar::grammar::fetch_and_build_grammars_with_progress(vec![configuration], None, 1)
+        {
+            Ok(progress) => {
+                std::thread::spawn(move || {
+                    for progress in progress {
+                        let grammar_id = progress.grammar_id;
+                        match progress.phase {
+                            GrammarPhase::Failed { error } => log::error!(
+                                "[GrammarConfig::request_lazy_load]: Failed to load the grammar {grammar_id:?}: {error}"
+                            ),
+                            GrammarPhase::Done { library_bytes } => {
+                                log::info!(
+                                    "[GrammarConfig::request_lazy_load]: Loaded the grammar {grammar_id:?} ({library_bytes} bytes)"
+                                );
+                                if let Ok(hook) = LAZY_GRAMMAR_LOADED_HOOK.lock() {
+                                    if let Some(hook) = hook.as_ref() {
+                                        hook(&grammar_id)
+                                    }
+                                }
+                            }
+                            phase => log::info!(
+                                "[GrammarConfig::request_lazy_load]: {phase:?} the grammar {grammar_id:?}"
+                            ),
+                        }
+                    }
+                });
+            }
+            Err(error) => log::error!(
+                "[GrammarConfig::request_lazy_load]: Failed to load the grammar {:?}: {error:#}",
+                self.id
+            ),
+        }
+    }
+
+    /// `url_template` is used for `FromSource` grammars without `url`.
+    fn grammar_configuration(&self, url_template: Option<&str>) -> Option<GrammarConfiguration> {
+        match &self.kind {
+            GrammarConfigKind::CargoLinked(_) => None,
+            GrammarConfigKind::FromSource {
+                url,
+                commit,
+                subpath,
+                experimental,
+                compile_flags,
+            } => {
+                let url = url
+                    .clone()
+                    .or_else(|| Some(url_template?.replace("{id}", &self.id)))?;
+                Some(
+                    GrammarConfiguration::remote(&self.id, &url, commit, subpath.clone())
+                        .experimental(*experimental)
+                        .compile_flags(compile_flags.clone()),
+                )
+            }
+            GrammarConfigKind::FromLocalPath { path } => {
+                Some(GrammarConfiguration::local(&self.id, path))
+            }
+        }
+    }
+}
+
+/// Register `hook` to be called with the grammar ID whenever a lazy grammar is built,
+/// so that the buffers shown as plain text in the meantime can be parsed and highlighted.
+///
+/// It replaces the previously registered hook.
+pub fn on_lazy_grammar_loaded(hook: impl Fn(&str) + Send + 'static) {
+    if let Ok(mut registered) = LAZY_GRAMMAR_LOADED_HOOK.lock() {
+        *registered = Some(Box::new(hook))
+    }
+}
+
+/// Whether `Language::tree_sitter_language` has started loading the lazy grammar `grammar_id`.
+pub fn lazy_grammar_load_requested(grammar_id: &str) -> bool {
+    REQUESTED_LAZY_GRAMMARS
+        .lock()
+        .is_ok_and(|requested| requested.contains(grammar_id))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ContentGrammarConfig {
+    /// A regex searched in the content of the file.
+    #[schemars(example = r"\btemplate\s*<")]
+    pub(crate) pattern: String,
+    pub(crate) grammar: GrammarConfig,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
@@ -249,10 +1577,29 @@ pub enum GrammarConfigKind {
     /// is not reliable across different operating system.
     CargoLinked(CargoLinkedTreesitterLanguage),
     FromSource {
-        url: String,
+        /// Defaults to `grammar_url_template` with `{id}` substituted by the grammar ID.
+        url: Option<String>,
         commit: String,
         subpath: Option<String>,
+        /// Set this when trying out a new grammar, so that failing to fetch or build it
+        /// only logs a warning, and files of this language are shown as plain text.
+        #[serde(default)]
+        experimental: bool,
+        /// Extra flags of the C compiler, for example, `-std=c11` or `-I/usr/include/foo`.
+        #[serde(default)]
+        compile_flags: Vec<String>,
     },
+    /// A grammar repository on disk, for example, one being developed.
+    /// It is built like `FromSource`, but never fetched.
+    FromLocalPath { path: PathBuf },
+}
+
+/// Where the grammar of a language comes from, see `GrammarConfigKind`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrammarProvenance {
+    CargoLinked,
+    FromSource { commit: String },
+    FromLocalPath { path: PathBuf },
 }
 
 impl Language {
@@ -260,28 +1607,388 @@ impl Language {
         &self.extensions
     }
 
+    /// The extension to give a new file of this language, for example, `rs`.
+    pub fn preferred_extension(&self) -> Option<&str> {
+        self.primary_extension
+            .as_deref()
+            .or(self.extensions.first().map(String::as_str))
+    }
+
+    /// The position encodings to be offered to the LSP server, in order of preference.
+    pub fn lsp_position_encodings(&self) -> Vec<PositionEncoding> {
+        self.lsp_position_encoding
+            .into_iter()
+            .chain(Some(PositionEncoding::Utf16))
+            .unique()
+            .collect()
+    }
+
     pub fn initialization_options(&self) -> Option<Value> {
         self.lsp_command.clone()?.initialization_options
     }
 
+    /// `initialization_options` merged with the `conditional_initialization_options`
+    /// whose file exists in `workspace_folder`.
+    pub fn initialization_options_for(&self, workspace_folder: &Path) -> Option<Value> {
+        let lsp_command = self.lsp_command.clone()?;
+        lsp_command
+            .conditional_initialization_options
+            .into_iter()
+            .filter(|fragment| workspace_folder.join(&fragment.file).exists())
+            .fold(lsp_command.initialization_options, |options, fragment| {
+                let mut options = options.unwrap_or_else(|| Value::Object(Default::default()));
+                merge_json(&mut options, fragment.options);
+                Some(options)
+            })
+    }
+
     pub fn tree_sitter_language(&self) -> Option<tree_sitter::Language> {
-        let config = self.tree_sitter_grammar_config.as_ref()?;
-        match &config.kind {
-            GrammarConfigKind::CargoLinked(language) => Some(language.to_tree_sitter_language()),
-            GrammarConfigKind::FromSource { .. } => grammar::grammar::get_language(&config.id).ok(),
+        self.tree_sitter_grammar_config
+            .as_ref()?
+            .tree_sitter_language()
+    }
+
+    /// The grammar config of `content_grammar_configs` whose pattern matches `source` first,
+    /// or else `tree_sitter_grammar_config`.
+    pub fn tree_sitter_grammar_config_for(&self, source: &str) -> Option<&GrammarConfig> {
+        // The patterns are compiled once, because this is called on every highlight
+        static PATTERNS: Lazy<Mutex<HashMap<String, Option<regex::Regex>>>> =
+            Lazy::new(Default::default);
+        self.content_grammar_configs
+            .iter()
+            .find(|config| {
+                PATTERNS
+                    .lock()
+                    .unwrap()
+                    .entry(config.pattern.clone())
+                    .or_insert_with(|| {
+                        regex::Regex::new(&config.pattern)
+                            .map_err(|error| {
+                                log::error!(
+                                    "[Language::tree_sitter_grammar_config_for]: Invalid pattern {:?}: {error}",
+                                    config.pattern
+                                )
+                            })
+                            .ok()
+                    })
+                    .as_ref()
+                    .is_some_and(|regex| regex.is_match(source))
+            })
+            .map(|config| &config.grammar)
+            .or(self.tree_sitter_grammar_config.as_ref())
+    }
+
+    /// Specializes this language for a file with the content `source`,
+    /// so that it is highlighted with the grammar chosen by `tree_sitter_grammar_config_for`.
+    pub fn for_content(self, source: &str) -> Self {
+        let tree_sitter_grammar_config = self.tree_sitter_grammar_config_for(source).cloned();
+        Self {
+            tree_sitter_grammar_config,
+            ..self
         }
     }
 
-    pub fn tree_sitter_grammar_config(&self) -> Option<GrammarConfiguration> {
-        match &self.tree_sitter_grammar_config.as_ref()?.kind {
-            GrammarConfigKind::CargoLinked(_) => None,
-            GrammarConfigKind::FromSource {
-                url,
-                commit,
-                subpath,
-            } => self.tree_sitter_grammar_config.as_ref().map(|config| {
-                GrammarConfiguration::remote(&config.id, url, commit, subpath.clone())
-            }),
+    /// Like `tree_sitter_language_for_file_size`,
+    /// but the grammar is chosen by `tree_sitter_grammar_config_for`.
+    pub fn tree_sitter_language_for(&self, source: &str) -> Option<tree_sitter::Language> {
+        if source.len() > self.max_grammar_file_size() {
+            return self.tree_sitter_language_for_file_size(source.len());
+        }
+        self.tree_sitter_grammar_config_for(source)?
+            .tree_sitter_language()
+    }
+
+    /// The parse tree of `source` as an s-expression, for debugging grammars and queries,
+    /// for example, `(source_file (function_item name: (identifier) ...))`.
+    ///
+    /// With `byte_ranges`, every node is followed by its byte range, e.g. `(identifier 3..7)`.
+    pub fn debug_tree(&self, source: &str, byte_ranges: bool) -> Option<String> {
+        let tree = self.parser()?.parse(source, None)?;
+        if !byte_ranges {
+            return Some(tree.root_node().to_sexp());
+        }
+        fn write_node(cursor: &mut tree_sitter::TreeCursor, output: &mut String) {
+            let node = cursor.node();
+            if let Some(field_name) = cursor.field_name() {
+                output.push_str(&format!("{field_name}: "));
+            }
+            let kind = if node.is_missing() {
+                format!("MISSING {}", node.kind())
+            } else {
+                node.kind().to_string()
+            };
+            let range = node.byte_range();
+            output.push_str(&format!("({kind} {}..{}", range.start, range.end));
+            if cursor.goto_first_child() {
+                loop {
+                    if cursor.node().is_named() || cursor.node().is_missing() {
+                        output.push(' ');
+                        write_node(cursor, output);
+                    }
+                    if !cursor.goto_next_sibling() {
+                        break;
+                    }
+                }
+                cursor.goto_parent();
+            }
+            output.push(')');
+        }
+        let mut output = String::new();
+        write_node(&mut tree.walk(), &mut output);
+        Some(output)
+    }
+
+    /// The hunks of the unified diff `source`, parsed with the grammar of this language,
+    /// which is expected to be the Diff language.
+    pub fn diff_hunks(&self, source: &str) -> Vec<DiffHunk> {
+        let Some(tree) = self
+            .parser()
+            .and_then(|mut parser| parser.parse(source, None))
+        else {
+            return Vec::new();
+        };
+        let file_name_of = |node: tree_sitter::Node| {
+            let mut cursor = node.walk();
+            let file_name = node
+                .named_children(&mut cursor)
+                .find(|child| child.kind() == "filename")?;
+            Some(
+                file_name
+                    .utf8_text(source.as_bytes())
+                    .ok()?
+                    .trim()
+                    .to_string(),
+            )
+        };
+        let push_line = |spans: &mut Vec<Range<usize>>, line: usize| match spans.last_mut() {
+            Some(span) if span.end == line => span.end = line + 1,
+            _ => spans.push(line..line + 1),
+        };
+
+        let mut hunks: Vec<DiffHunk> = Vec::new();
+        let (mut old_file, mut new_file) = (None, None);
+        let mut cursor = tree.walk();
+        loop {
+            let node = cursor.node();
+            let line = node.start_position().row;
+            match node.kind() {
+                "old_file" => old_file = file_name_of(node),
+                "new_file" => new_file = file_name_of(node),
+                "location" => hunks.push(DiffHunk {
+                    old_file: old_file.clone(),
+                    new_file: new_file.clone(),
+                    lines: line..line + 1,
+                    added_lines: Vec::new(),
+                    removed_lines: Vec::new(),
+                }),
+                kind @ ("addition" | "deletion" | "context") => {
+                    if let Some(hunk) = hunks.last_mut() {
+                        hunk.lines.end = line + 1;
+                        match kind {
+                            "addition" => push_line(&mut hunk.added_lines, line),
+                            "deletion" => push_line(&mut hunk.removed_lines, line),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {
+                    if cursor.goto_first_child() {
+                        continue;
+                    }
+                }
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    return hunks;
+                }
+            }
+        }
+    }
+
+    /// The byte range of `source` to be reparsed after `edited` is changed.
+    ///
+    /// It is `edited` itself, unless the language is `whitespace_sensitive`,
+    /// in which case it extends from the last unindented line at or before `edited`
+    /// to the next unindented line after `edited`.
+    pub fn reparse_range(&self, source: &str, edited: Range<usize>) -> Range<usize> {
+        if !self.whitespace_sensitive {
+            return edited;
+        }
+        let is_unindented_line_start = |index: usize| {
+            (index == 0 || source.as_bytes()[index - 1] == b'\n')
+                && source[index..]
+                    .chars()
+                    .next()
+                    .is_some_and(|char| !char.is_whitespace())
+        };
+        let line_starts = || {
+            std::iter::once(0).chain(
+                source
+                    .match_indices('\n')
+                    .map(|(index, _)| index + 1)
+                    .filter(|index| *index < source.len()),
+            )
+        };
+        let start = line_starts()
+            .rfind(|index| *index <= edited.start && is_unindented_line_start(*index))
+            .unwrap_or(0);
+        let end = line_starts()
+            .find(|index| *index > edited.end && is_unindented_line_start(*index))
+            .unwrap_or(source.len());
+        start..end
+    }
+
+    /// A parser set to the grammar of this language,
+    /// or `None` if there is no grammar or its ABI version is incompatible.
+    pub fn parser(&self) -> Option<tree_sitter::Parser> {
+        let mut parser = tree_sitter::Parser::new();
+        parser.set_language(&self.tree_sitter_language()?).ok()?;
+        Some(parser)
+    }
+
+    /// The ABI version of the grammar, useful for diagnosing incompatible grammars.
+    pub fn grammar_abi_version(&self) -> Option<usize> {
+        Some(self.tree_sitter_language()?.abi_version())
+    }
+
+    /// The number of node kinds of the grammar,
+    /// useful as the capacity of structures indexed by node kind ID.
+    pub fn node_kind_count(&self) -> Option<usize> {
+        Some(self.tree_sitter_language()?.node_kind_count())
+    }
+
+    /// The field names of the grammar, for example, `name` of `function_item` in Rust,
+    /// in the order of their IDs. Empty if there is no grammar.
+    pub fn field_names(&self) -> Vec<String> {
+        let Some(language) = self.tree_sitter_language() else {
+            return Vec::new();
+        };
+        // Field IDs start at 1
+        (1..=language.field_count())
+            .filter_map(|id| language.field_name_for_id(u16::try_from(id).ok()?))
+            .map(|name| name.to_string())
+            .collect()
+    }
+
+    pub fn max_grammar_file_size(&self) -> usize {
+        self.max_grammar_file_size
+            .unwrap_or(DEFAULT_MAX_GRAMMAR_FILE_SIZE)
+    }
+
+    /// Like `tree_sitter_language`, but returns `None` if a file of `file_size` bytes
+    /// is larger than `max_grammar_file_size`, so that the file is treated as plain text.
+    pub fn tree_sitter_language_for_file_size(
+        &self,
+        file_size: usize,
+    ) -> Option<tree_sitter::Language> {
+        if file_size > self.max_grammar_file_size() {
+            log::info!(
+                "[Language::tree_sitter_language_for_file_size]: Skipping the grammar for a file of {file_size} bytes, which exceeds `max_grammar_file_size`"
+            );
+            return None;
+        }
+        self.tree_sitter_language()
+    }
+
+    /// `url_template` is used for `FromSource` grammars without `url`,
+    /// for example, `https://github.com/tree-sitter/tree-sitter-{id}`.
+    pub fn tree_sitter_grammar_config(
+        &self,
+        url_template: Option<&str>,
+    ) -> Option<GrammarConfiguration> {
+        self.tree_sitter_grammar_config
+            .as_ref()?
+            .grammar_configuration(url_template)
+    }
+
+    /// Whether the grammar is `lazy_load`, and thus should not be fetched or built eagerly.
+    pub fn lazy_loads_grammar(&self) -> bool {
+        self.tree_sitter_grammar_config
+            .as_ref()
+            .is_some_and(|config| config.lazy_load)
+    }
+
+    /// The directory of the repository of a `FromSource` or `FromLocalPath` grammar,
+    /// whose `queries` directory has the queries in use, for example, `highlights.scm`.
+    pub fn grammar_source_dir(&self) -> Option<PathBuf> {
+        let config = self.tree_sitter_grammar_config.as_ref()?;
+        match &config.kind {
+            GrammarConfigKind::CargoLinked(_) => None,
+            GrammarConfigKind::FromSource { .. } => Some(grammar::grammar::source_dir(&config.id)),
+            GrammarConfigKind::FromLocalPath { path } => Some(path.clone()),
+        }
+    }
+
+    pub fn grammar_provenance(&self) -> Option<GrammarProvenance> {
+        Some(match &self.tree_sitter_grammar_config.as_ref()?.kind {
+            GrammarConfigKind::CargoLinked(_) => GrammarProvenance::CargoLinked,
+            GrammarConfigKind::FromSource { commit, .. } => GrammarProvenance::FromSource {
+                commit: commit.clone(),
+            },
+            GrammarConfigKind::FromLocalPath { path } => {
+                GrammarProvenance::FromLocalPath { path: path.clone() }
+            }
+        })
+    }
+
+    fn load_grammar_query_file(&self, file_name: &str) -> Option<String> {
+        let path = self.grammar_source_dir()?.join("queries").join(file_name);
+        log::info!("Loading from {}", path.display());
+        std::fs::read_to_string(path).ok()
+    }
+
+    /// Specializes this language for the file at `path`,
+    /// for example, `Cargo.toml` has a highlight query for dependencies on top of the TOML one.
+    pub fn for_path(self, path: &Path) -> Self {
+        let specialized_file_name = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .filter(|file_name| self.file_highlight_query_for(file_name).is_some())
+            .map(|file_name| file_name.to_string());
+        let highlight_query_override = self
+            .highlight_query_overrides
+            .iter()
+            .find(|(glob, _)| match Glob::new(glob) {
+                Ok(glob) => glob.compile_matcher().is_match(path),
+                Err(error) => {
+                    log::error!("Invalid highlight query override glob {glob:?}: {error}");
+                    false
+                }
+            })
+            .map(|(_, query_path)| expand_home_dir(query_path, etcetera::home_dir().ok()));
+        Self {
+            specialized_file_name,
+            highlight_query_override,
+            ..self
+        }
+    }
+
+    fn file_highlight_query_for(&self, file_name: &str) -> Option<&'static str> {
+        get_file_highlight_query(&self.tree_sitter_grammar_id()?, file_name)
+    }
+
+    /// Identifies the highlight configuration of this language, which is the grammar ID,
+    /// unless this language is specialized for a file with its own highlight query.
+    ///
+    /// Languages sharing a template grammar are told apart by their `template_injections`.
+    pub fn highlight_config_id(&self) -> Option<String> {
+        let grammar_id = self.tree_sitter_grammar_id()?;
+        let grammar_id = if self.template_injections.is_empty() {
+            grammar_id
+        } else {
+            let languages = self
+                .template_injections
+                .iter()
+                .map(|(_, language)| language)
+                .join(",");
+            format!("{grammar_id}+{languages}")
+        };
+        if let Some(query_path) = &self.highlight_query_override {
+            return Some(format!("{grammar_id}:{}", query_path.display()));
+        }
+        match &self.specialized_file_name {
+            Some(file_name) => Some(format!("{grammar_id}:{file_name}")),
+            None => Some(grammar_id),
         }
     }
 
@@ -294,9 +2001,130 @@ impl Language {
     /// (this is validated through the use of `tree_sitter::Query::new`),
     /// we will fallback to the default highlight queries.
     pub fn highlight_query(&self) -> Option<String> {
+        self.load_highlight_query(query_load_metrics::is_enabled())
+    }
+
+    fn load_highlight_query(&self, record_metrics: bool) -> Option<String> {
+        let chosen = self.chosen_highlight_query()?;
+        if record_metrics {
+            self.record_query_load_metrics(
+                QueryKind::Highlight,
+                &chosen.query,
+                chosen.compile_duration,
+            );
+        }
+        Some(chosen.query)
+    }
+
+    fn chosen_highlight_query(&self) -> Option<ChosenHighlightQuery> {
+        if let Some(chosen) = self.highlight_query_from_override() {
+            return Some(chosen);
+        }
+        let chosen = self.highlight_query_generic()?;
+        let Some(file_highlight_query) = self
+            .specialized_file_name
+            .as_ref()
+            .and_then(|file_name| self.file_highlight_query_for(file_name))
+        else {
+            return Some(chosen);
+        };
+        let specialized_query = format!("{file_highlight_query}\n{}", chosen.query);
+        match self.compile_highlight_query(&specialized_query) {
+            Ok(compiled) if self.highlight_query_within_budget(&compiled.query) => {
+                Some(ChosenHighlightQuery::compiled(specialized_query, &compiled))
+            }
+            Ok(_) => Some(chosen),
+            Err(error) => {
+                log::error!(
+                    "[Language::highlight_query]: Ignoring the file-specific highlight query of {}, because it is invalid due to error: {error:?}",
+                    self.tree_sitter_grammar_config.clone()?.id
+                );
+                Some(chosen)
+            }
+        }
+    }
+
+    fn highlight_query_from_override(&self) -> Option<ChosenHighlightQuery> {
+        let query_path = self.highlight_query_override.as_ref()?;
+        let query = std::fs::read_to_string(query_path)
+            .map_err(|error| {
+                log::error!(
+                    "[Language::highlight_query]: Ignoring the highlight query override {}, because it cannot be read due to error: {error}",
+                    query_path.display()
+                )
+            })
+            .ok()?;
+        match self.compile_highlight_query(&query) {
+            Ok(compiled) => Some(ChosenHighlightQuery::compiled(query, &compiled))
+                .filter(|_| self.highlight_query_within_budget(&compiled.query)),
+            Err(error) => {
+                log::error!(
+                    "[Language::highlight_query]: Ignoring the highlight query override {}, because it is invalid due to error: {error:?}",
+                    query_path.display()
+                );
+                None
+            }
+        }
+    }
+
+    fn highlight_query_generic(&self) -> Option<ChosenHighlightQuery> {
+        let chosen = self.highlight_query_unmapped()?;
+        if self.highlight_capture_remap.is_empty() {
+            return Some(chosen);
+        }
+        let remapped_query = self.remap_highlight_captures(&chosen.query);
+        match self.compile_highlight_query(&remapped_query) {
+            Ok(compiled) => Some(ChosenHighlightQuery::compiled(remapped_query, &compiled)),
+            Err(error) => {
+                log::error!(
+                    "[Language::highlight_query]: Ignoring `highlight_capture_remap` of {}, because the remapped query is invalid due to error: {error:?}",
+                    self.tree_sitter_grammar_config.clone()?.id
+                );
+                Some(chosen)
+            }
+        }
+    }
+
+    fn remap_highlight_captures(&self, query: &str) -> String {
+        self.highlight_capture_remap
+            .iter()
+            .fold(query.to_string(), |query, (from, to)| {
+                // The capture name must be matched entirely,
+                // so that `@type` does not match `@type.builtin`
+                let regex =
+                    regex::Regex::new(&format!(r"@{}(?P<end>[^\w.\-]|$)", regex::escape(from)))
+                        .unwrap();
+                regex
+                    .replace_all(&query, |captures: &regex::Captures| {
+                        format!("@{to}{}", &captures["end"])
+                    })
+                    .into_owned()
+            })
+    }
+
+    fn highlight_query_unmapped(&self) -> Option<ChosenHighlightQuery> {
+        if self.highlight_query_merge {
+            if let Some(query) = self.highlight_query_merged() {
+                match self.compile_highlight_query(&query) {
+                    Ok(compiled) if self.highlight_query_within_budget(&compiled.query) => {
+                        return Some(ChosenHighlightQuery::compiled(query, &compiled))
+                    }
+                    Ok(_) => {}
+                    Err(error) => {
+                        log::error!(
+                            "[Language::highlight_query]: Ignoring `highlight_query_merge` of {}, because the merged query is invalid due to error: {error:?}",
+                            self.tree_sitter_grammar_config.clone()?.id
+                        )
+                    }
+                }
+            }
+        }
         if let Some(query) = self.highlight_query_nvim_treesitter() {
-            match Query::new(&self.tree_sitter_language()?, &query) {
-                Ok(_) => return Some(query),
+            match self.compile_highlight_query(&query) {
+                Ok(compiled) if self.highlight_query_within_budget(&compiled.query) => {
+                    return Some(ChosenHighlightQuery::compiled(query, &compiled))
+                }
+                Ok(_) => {}
                 Err(error) => {
                     log::error!(
                         "[Language::highlight_query]: Falling back to default query; unable to use highlight query of {} from nvim-treesitter due to error: {error:?}",
@@ -305,46 +2133,569 @@ impl Language {
                 }
             }
         }
-        self.highlight_query_default()
+        let query = self.highlight_query_default()?;
+        // The default query is only compiled to be checked against the budget
+        if self.highlight_query_budget.is_none() {
+            return Some(ChosenHighlightQuery::uncompiled(query));
+        }
+        match self.compile_highlight_query(&query) {
+            Ok(compiled) => Some(ChosenHighlightQuery::compiled(query, &compiled))
+                .filter(|_| self.highlight_query_within_budget(&compiled.query)),
+            Err(_) => Some(ChosenHighlightQuery::uncompiled(query)),
+        }
     }
 
-    pub fn highlight_query_nvim_treesitter(&self) -> Option<String> {
-        get_highlight_query(&self.tree_sitter_grammar_config.clone()?.id).map(|result| {
-            result
-                .query
-                // Replace `nvim-treesitter`-specific predicates with builtin predicates supported by `tree-sitter-highlight` crate
-                // Reference: https://github.com/nvim-treesitter/nvim-treesitter/blob/23ba63028c6acca29be6462c0a291fc4a1b9eae8/CONTRIBUTING.md#predicates
-                .replace("lua-match", "match")
-                .replace("vim-match", "match")
-                // Remove non-highlight captures, as they are not handled by this editor
-                // See https://github.com/nvim-treesitter/nvim-treesitter/blob/23ba63028c6acca29be6462c0a291fc4a1b9eae8/CONTRIBUTING.md#non-highlighting-captures
-                .replace("@none", "")
-                .replace("@conceal", "")
-                .replace("@spell", "")
-                .replace("@nospell", "")
+    /// The complexity of the highlight query in use,
+    /// which is within `highlight_query_budget` if it is set.
+    pub fn highlight_query_complexity(&self) -> Option<QueryComplexity> {
+        let chosen = self.chosen_highlight_query()?;
+        match chosen.complexity {
+            Some(complexity) => Some(complexity),
+            None => Some(QueryComplexity::of(
+                &self.compile_highlight_query(&chosen.query).ok()?.query,
+            )),
+        }
+    }
+
+    /// Warns if `query` exceeds `highlight_query_budget`, so that the next query is tried.
+    fn highlight_query_within_budget(&self, query: &Query) -> bool {
+        let Some(budget) = self.highlight_query_budget else {
+            return true;
+        };
+        let complexity = QueryComplexity::of(query);
+        let exceeds = |count: usize, max: Option<usize>| max.is_some_and(|max| count > max);
+        if exceeds(complexity.pattern_count, budget.max_patterns)
+            || exceeds(complexity.capture_count, budget.max_captures)
+        {
+            log::warn!(
+                "[Language::highlight_query]: Ignoring a highlight query of {:?}, because its complexity {complexity:?} exceeds the budget {budget:?}",
+                self.tree_sitter_grammar_id()
+            );
+            return false;
+        }
+        true
+    }
+
+    /// The capture names of the highlight query, sorted,
+    /// excluding the captures used by predicates only, e.g. `@_key`.
+    pub fn highlight_capture_names(&self) -> Vec<String> {
+        let (Some(language), Some(query)) = (self.tree_sitter_language(), self.highlight_query())
+        else {
+            return Vec::new();
+        };
+        let Ok(query) = Query::new(&language, &query) else {
+            return Vec::new();
+        };
+        query
+            .capture_names()
+            .iter()
+            .filter(|name| !name.starts_with('_'))
+            .map(|name| name.to_string())
+            .sorted()
+            .dedup()
+            .collect()
+    }
+
+    /// The captures of the highlight query that are not styled by a theme that styles
+    /// `theme_capture_names`, which helps in completing the theme.
+    ///
+    /// Like `tree-sitter-highlight`, a capture is styled by a name that is equal to it,
+    /// or that is a prefix of it at a dot, e.g. `keyword` styles `keyword.function`.
+    pub fn captures_unused_by_theme(&self, theme_capture_names: &[&str]) -> Vec<String> {
+        self.highlight_capture_names()
+            .into_iter()
+            .filter(|capture| {
+                !theme_capture_names.iter().any(|name| {
+                    capture == name
+                        || capture
+                            .strip_prefix(name)
+                            .is_some_and(|rest| rest.starts_with('.'))
+                })
+            })
+            .collect()
+    }
+
+    /// `tree-sitter-highlight` prefers the earliest pattern when multiple patterns match the
+    /// same node, so the nvim-treesitter query is placed before the default query.
+    fn highlight_query_merged(&self) -> Option<String> {
+        match (
+            self.highlight_query_nvim_treesitter(),
+            self.highlight_query_default(),
+        ) {
+            (Some(nvim_treesitter), Some(default)) => Some(format!("{nvim_treesitter}\n{default}")),
+            (nvim_treesitter, default) => nvim_treesitter.or(default),
+        }
+    }
+
+    /// Compiles `query` against the grammar of this language,
+    /// useful for checking a candidate `highlights.scm` before using it.
+    pub fn validate_highlight_query(&self, query: &str) -> Result<(), QueryError> {
+        self.compile_highlight_query(query).map(|_| ())
+    }
+
+    fn compile_highlight_query(&self, query: &str) -> Result<CompiledQuery, QueryError> {
+        let Some(language) = self.tree_sitter_language() else {
+            return Err(QueryError {
+                row: 0,
+                column: 0,
+                offset: 0,
+                message: "No tree-sitter grammar is configured for this language".to_string(),
+                kind: QueryErrorKind::Language,
+            });
+        };
+        let start = Instant::now();
+        let query = Query::new(&language, query)?;
+        Ok(CompiledQuery {
+            query,
+            duration: start.elapsed(),
         })
     }
 
+    /// Parses `grammar_self_test_sample` and fails if it has a syntax error,
+    /// which means the grammar is broken, for example, built from a wrong revision.
+    ///
+    /// This always passes if the language has no sample.
+    pub fn self_test(&self) -> Result<(), GrammarSelfTestError> {
+        let Some(sample) = &self.grammar_self_test_sample else {
+            return Ok(());
+        };
+        let mut parser = self
+            .parser()
+            .ok_or(GrammarSelfTestError::GrammarUnavailable)?;
+        let tree = parser
+            .parse(sample, None)
+            .ok_or(GrammarSelfTestError::ParseFailed)?;
+        if !tree.root_node().has_error() {
+            return Ok(());
+        }
+        let mut cursor = tree.walk();
+        loop {
+            let node = cursor.node();
+            if node.is_error() || node.is_missing() {
+                return Err(GrammarSelfTestError::SyntaxError {
+                    byte_range: node.byte_range(),
+                });
+            }
+            if node.has_error() && cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    return Err(GrammarSelfTestError::SyntaxError {
+                        byte_range: tree.root_node().byte_range(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Runs `query` over `sample`, and warns about the captures that matched nothing,
+    /// which is a hint that a pattern is misspelled or targets the wrong node.
+    ///
+    /// Captures starting with `_` are ignored, as they only serve predicates.
+    pub fn lint_highlight_query(&self, query: &str, sample: &str) -> Vec<HighlightQueryWarning> {
+        use tree_sitter::StreamingIterator;
+
+        if let Err(error) = self.validate_highlight_query(query) {
+            return vec![HighlightQueryWarning::InvalidQuery(error)];
+        }
+        let (Some(language), Some(mut parser)) = (self.tree_sitter_language(), self.parser())
+        else {
+            return Vec::new();
+        };
+        let (Ok(query), Some(tree)) = (Query::new(&language, query), parser.parse(sample, None))
+        else {
+            return Vec::new();
+        };
+        let mut matched = vec![false; query.capture_names().len()];
+        let mut cursor = tree_sitter::QueryCursor::new();
+        let mut captures = cursor.captures(&query, tree.root_node(), sample.as_bytes());
+        while let Some((query_match, index)) = captures.next() {
+            matched[query_match.captures[*index].index as usize] = true;
+        }
+        query
+            .capture_names()
+            .iter()
+            .zip(matched)
+            .filter(|(name, matched)| !matched && !name.starts_with('_'))
+            .map(|(name, _)| HighlightQueryWarning::DeadCapture {
+                capture_name: name.to_string(),
+            })
+            .collect()
+    }
+
+    pub fn highlight_query_nvim_treesitter(&self) -> Option<String> {
+        get_highlight_query(&self.tree_sitter_grammar_config.clone()?.id)
+            .map(|result| {
+                result
+                    .query
+                    // Replace `nvim-treesitter`-specific predicates with builtin predicates supported by `tree-sitter-highlight` crate
+                    // Reference: https://github.com/nvim-treesitter/nvim-treesitter/blob/23ba63028c6acca29be6462c0a291fc4a1b9eae8/CONTRIBUTING.md#predicates
+                    .replace("lua-match", "match")
+                    .replace("vim-match", "match")
+                    // Remove non-highlight captures, as they are not handled by this editor
+                    // See https://github.com/nvim-treesitter/nvim-treesitter/blob/23ba63028c6acca29be6462c0a291fc4a1b9eae8/CONTRIBUTING.md#non-highlighting-captures
+                    .replace("@none", "")
+                    .replace("@conceal", "")
+                    .replace("@spell", "")
+                    .replace("@nospell", "")
+            })
+            .map(|query| {
+                // Remove `#set!` with a capture as the value, e.g. `(#set! @_label url @_url)`
+                // of markdown_inline, which makes the whole query invalid for `tree_sitter::Query`
+                regex::Regex::new(r"\(#set! @[\w.]+ \w+ @[\w.]+\)")
+                    .unwrap()
+                    .replace_all(&query, "")
+                    .into_owned()
+            })
+    }
+
     fn highlight_query_default(&self) -> Option<String> {
         let config = self.tree_sitter_grammar_config.as_ref()?;
         match &config.kind {
             GrammarConfigKind::CargoLinked(language) => {
                 Some(language.default_highlight_query()?.to_string())
             }
-            GrammarConfigKind::FromSource { .. } => grammar::grammar::load_runtime_file(
-                &self.tree_sitter_grammar_id()?,
-                "highlights.scm",
-            )
-            .ok(),
+            GrammarConfigKind::FromSource { .. } | GrammarConfigKind::FromLocalPath { .. } => {
+                self.load_grammar_query_file("highlights.scm")
+            }
+        }
+    }
+
+    /// The C/C++ compiler warnings recorded when the `FromSource` grammar of this language
+    /// was built, useful for debugging a grammar that parses oddly.
+    pub fn grammar_build_warnings(&self) -> Option<String> {
+        let config = self.tree_sitter_grammar_config.as_ref()?;
+        match &config.kind {
+            GrammarConfigKind::CargoLinked(_) => None,
+            GrammarConfigKind::FromSource { .. } | GrammarConfigKind::FromLocalPath { .. } => {
+                grammar::grammar::get_build_warnings(&config.id)
+            }
+        }
+    }
+
+    /// The byte range of the local definition referred to by the reference at `offset`,
+    /// for example, the `&anchor` of a YAML `*anchor` alias.
+    ///
+    /// It is the last definition of the same name before the reference,
+    /// whose innermost `@local.scope` also contains the reference.
+    pub fn local_definition_at(&self, source: &str, offset: usize) -> Option<Range<usize>> {
+        use tree_sitter::StreamingIterator;
+
+        let query = Query::new(&self.tree_sitter_language()?, self.locals_query()?).ok()?;
+        let scope_index = query.capture_index_for_name("local.scope");
+        let definition_index = query.capture_index_for_name("local.definition")?;
+        let reference_index = query.capture_index_for_name("local.reference")?;
+        let tree = self.parser()?.parse(source, None)?;
+        let mut cursor = tree_sitter::QueryCursor::new();
+        let mut captures = cursor.captures(&query, tree.root_node(), source.as_bytes());
+        let (mut scopes, mut definitions, mut reference) = (Vec::new(), Vec::new(), None);
+        while let Some((query_match, index)) = captures.next() {
+            let capture = query_match.captures[*index];
+            let range = capture.node.byte_range();
+            if Some(capture.index) == scope_index {
+                scopes.push(range)
+            } else if capture.index == definition_index {
+                definitions.push(range)
+            } else if capture.index == reference_index && range.contains(&offset) {
+                reference = Some(range)
+            }
         }
+        let reference = reference?;
+        let innermost_scope = |range: &Range<usize>| {
+            scopes
+                .iter()
+                .filter(|scope| scope.start <= range.start && range.end <= scope.end)
+                .min_by_key(|scope| scope.len())
+                .cloned()
+                .unwrap_or(0..source.len())
+        };
+        definitions
+            .into_iter()
+            .filter(|definition| {
+                definition.start < reference.start
+                    && source[definition.clone()] == source[reference.clone()]
+                    && innermost_scope(definition).contains(&reference.start)
+            })
+            .max_by_key(|definition| definition.start)
     }
 
     pub fn locals_query(&self) -> Option<&'static str> {
-        None
+        self.load_locals_query(query_load_metrics::is_enabled())
     }
 
-    pub fn injection_query(&self) -> Option<&'static str> {
-        None
+    fn load_locals_query(&self, record_metrics: bool) -> Option<&'static str> {
+        let query = get_locals_query(&self.tree_sitter_grammar_config.as_ref()?.id)?;
+        if record_metrics {
+            self.record_query_load_metrics(QueryKind::Locals, query, None);
+        }
+        Some(query)
+    }
+
+    /// Our own brackets query of the grammar,
+    /// or the `brackets.scm` of the grammar repository for `FromSource` grammars.
+    pub fn brackets_query(&self) -> Option<String> {
+        let config = self.tree_sitter_grammar_config.as_ref()?;
+        get_brackets_query(&config.id)
+            .map(|query| query.to_string())
+            .or_else(|| match &config.kind {
+                GrammarConfigKind::CargoLinked(_) => None,
+                GrammarConfigKind::FromSource { .. } | GrammarConfigKind::FromLocalPath { .. } => {
+                    self.load_grammar_query_file("brackets.scm")
+                }
+            })
+    }
+
+    /// Returns the byte index of the bracket that pairs with the bracket at `byte_index`.
+    ///
+    /// The pairs of `brackets_query` are used if it is available,
+    /// otherwise the brackets are paired by counting, which includes brackets in strings and comments.
+    pub fn matching_bracket(&self, source: &str, byte_index: usize) -> Option<usize> {
+        match self.bracket_pairs_from_query(source) {
+            Some(pairs) => pairs.into_iter().find_map(|(open, close)| {
+                if open == byte_index {
+                    Some(close)
+                } else if close == byte_index {
+                    Some(open)
+                } else {
+                    None
+                }
+            }),
+            None => matching_bracket_by_counting(source, byte_index),
+        }
+    }
+
+    fn bracket_pairs_from_query(&self, source: &str) -> Option<Vec<(usize, usize)>> {
+        use tree_sitter::StreamingIterator;
+
+        let language = self.tree_sitter_language()?;
+        let query = Query::new(&language, &self.brackets_query()?).ok()?;
+        let open_index = query.capture_index_for_name("open")?;
+        let close_index = query.capture_index_for_name("close")?;
+        let tree = self.parser()?.parse(source, None)?;
+        let mut cursor = tree_sitter::QueryCursor::new();
+        let mut pairs = Vec::new();
+        let mut matches = cursor.matches(&query, tree.root_node(), source.as_bytes());
+        while let Some(query_match) = matches.next() {
+            let position = |index| {
+                query_match
+                    .captures
+                    .iter()
+                    .find(|capture| capture.index == index)
+                    .map(|capture| capture.node.start_byte())
+            };
+            if let (Some(open), Some(close)) = (position(open_index), position(close_index)) {
+                pairs.push((open, close));
+            }
+        }
+        Some(pairs)
+    }
+
+    /// The byte range and normalized language name of every injection in `source`,
+    /// except those of `INJECTION_ONLY_GRAMMAR_IDS`, which belong to this language.
+    fn injections(&self, source: &str) -> Vec<(Range<usize>, String)> {
+        use tree_sitter::StreamingIterator;
+
+        let (Some(language), Some(query), Some(mut parser)) = (
+            self.tree_sitter_language(),
+            self.injection_query(),
+            self.parser(),
+        ) else {
+            return Vec::new();
+        };
+        let (Ok(query), Some(tree)) = (Query::new(&language, &query), parser.parse(source, None))
+        else {
+            return Vec::new();
+        };
+        let Some(content_index) = query.capture_index_for_name("injection.content") else {
+            return Vec::new();
+        };
+        let language_index = query.capture_index_for_name("injection.language");
+        let mut cursor = tree_sitter::QueryCursor::new();
+        let mut matches = cursor.matches(&query, tree.root_node(), source.as_bytes());
+        let mut injections = Vec::new();
+        while let Some(query_match) = matches.next() {
+            let name = query_match
+                .captures
+                .iter()
+                .find(|capture| Some(capture.index) == language_index)
+                .and_then(|capture| capture.node.utf8_text(source.as_bytes()).ok())
+                .or_else(|| {
+                    query
+                        .property_settings(query_match.pattern_index)
+                        .iter()
+                        .find(|property| &*property.key == "injection.language")
+                        .and_then(|property| property.value.as_deref())
+                });
+            let Some(name) = name.map(normalize_injection_language) else {
+                continue;
+            };
+            if INJECTION_ONLY_GRAMMAR_IDS.contains(&name.as_str()) {
+                continue;
+            }
+            injections.extend(
+                query_match
+                    .captures
+                    .iter()
+                    .filter(|capture| capture.index == content_index)
+                    .map(|capture| (capture.node.byte_range(), name.clone())),
+            );
+        }
+        injections
+    }
+
+    /// The normalized name of the language injected at `offset` of `source`,
+    /// or `None` if `offset` belongs to this language.
+    ///
+    /// If injections are nested, the innermost one is chosen.
+    pub fn injection_language_name_at(&self, source: &str, offset: usize) -> Option<String> {
+        self.injections(source)
+            .into_iter()
+            .filter(|(range, _)| range.contains(&offset))
+            .min_by_key(|(range, _)| range.len())
+            .map(|(_, name)| name)
+    }
+
+    /// The distinct normalized names of the languages injected anywhere in `source`,
+    /// in order of appearance, for example, the languages of the code blocks of a Markdown file.
+    pub fn injected_language_names(&self, source: &str) -> Vec<String> {
+        self.injections(source)
+            .into_iter()
+            .sorted_by_key(|(range, _)| range.start)
+            .map(|(_, name)| name)
+            .unique()
+            .collect()
+    }
+
+    /// The injection query of the grammar, followed by the `template_injections`.
+    pub fn injection_query(&self) -> Option<String> {
+        self.load_injection_query(query_load_metrics::is_enabled())
+    }
+
+    fn load_injection_query(&self, record_metrics: bool) -> Option<String> {
+        let template_query = self
+            .template_injections
+            .iter()
+            .map(|(node_kind, language)| {
+                format!(
+                    "(({node_kind}) @injection.content\n  (#set! injection.language {language:?})\n  (#set! injection.combined))\n"
+                )
+            })
+            .join("\n");
+        let query = get_injection_query(&self.tree_sitter_grammar_config.as_ref()?.id)
+            .map(str::to_string)
+            .into_iter()
+            .chain(Some(template_query).filter(|query| !query.is_empty()))
+            .reduce(|query, template_query| format!("{query}\n{template_query}"))?;
+        if record_metrics {
+            self.record_query_load_metrics(QueryKind::Injections, &query, None);
+        }
+        Some(query)
+    }
+
+    /// Records how long `query` takes to compile,
+    /// which is only compiled to be measured if its `compile_duration` is unknown.
+    fn record_query_load_metrics(
+        &self,
+        kind: QueryKind,
+        query: &str,
+        compile_duration: Option<Duration>,
+    ) {
+        let Some(grammar_id) = self.tree_sitter_grammar_id() else {
+            return;
+        };
+        let compile_duration = compile_duration.or_else(|| {
+            let language = self.tree_sitter_language()?;
+            let start = Instant::now();
+            let _ = Query::new(&language, query);
+            Some(start.elapsed())
+        });
+        if let Some(compile_duration) = compile_duration {
+            query_load_metrics::record(&grammar_id, kind, compile_duration);
+        }
+    }
+
+    /// Loads the queries of this language and records their compile durations,
+    /// regardless of `query_load_metrics::set_enabled`, see `last_query_load_metrics`.
+    pub fn load_queries_recording_metrics(&self) {
+        self.load_highlight_query(true);
+        self.load_injection_query(true);
+        self.load_locals_query(true);
+    }
+
+    /// The compile durations of the queries of this language when they were last loaded,
+    /// which are only recorded after `query_load_metrics::set_enabled(true)`,
+    /// or by `load_queries_recording_metrics`.
+    pub fn last_query_load_metrics(&self) -> Option<QueryLoadMetrics> {
+        query_load_metrics::get(&self.tree_sitter_grammar_id()?)
+    }
+
+    /// Returns the nearest ancestor directory of `path` that contains the first marker
+    /// of `lsp_root_markers` that is found,
+    /// so that a higher-priority marker wins over a nearer lower-priority marker.
+    ///
+    /// Returns `None` without searching if the LSP server has `lsp_single_file_support`.
+    pub fn lsp_root_directory(&self, path: &Path) -> Option<PathBuf> {
+        if self.lsp_single_file_support() {
+            return None;
+        }
+        self.lsp_root_markers.iter().find_map(|marker| {
+            let glob = if marker.contains(['*', '?', '[', '{']) {
+                match Glob::new(marker) {
+                    Ok(glob) => Some(glob.compile_matcher()),
+                    Err(error) => {
+                        log::error!("Invalid root marker glob {marker:?}: {error}");
+                        return None;
+                    }
+                }
+            } else {
+                None
+            };
+            path.ancestors()
+                .skip(1)
+                .find(|directory| match &glob {
+                    Some(glob) => std::fs::read_dir(directory).is_ok_and(|entries| {
+                        entries
+                            .flatten()
+                            .any(|entry| glob.is_match(entry.file_name()))
+                    }),
+                    None => directory.join(marker).is_file(),
+                })
+                .map(|directory| directory.to_path_buf())
+        })
+    }
+
+    pub fn lsp_single_file_support(&self) -> bool {
+        self.lsp_command
+            .as_ref()
+            .is_some_and(|command| command.lsp_single_file_support)
+    }
+
+    /// See `LspCommand::lsp_extra_trigger_characters`.
+    pub fn lsp_extra_trigger_characters(&self) -> &[String] {
+        self.lsp_command
+            .as_ref()
+            .map(|command| command.lsp_extra_trigger_characters.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// See `LspCommand::lsp_max_concurrent_requests`.
+    pub fn lsp_max_concurrent_requests(&self) -> Option<usize> {
+        self.lsp_command.as_ref()?.lsp_max_concurrent_requests
+    }
+
+    /// The kinds of code actions to request, or `None` for all kinds.
+    pub fn lsp_code_action_kinds(&self) -> Option<Vec<String>> {
+        self.lsp_command.as_ref()?.lsp_code_action_kinds.clone()
+    }
+
+    /// Whether `textDocument/didSave` includes the saved text,
+    /// given whether the server asked for it in its capabilities.
+    pub fn lsp_did_save_include_text(&self, server_include_text: bool) -> bool {
+        self.lsp_command
+            .as_ref()
+            .and_then(|command| command.lsp_save_options.include_text)
+            .unwrap_or(server_include_text)
     }
 
     pub fn lsp_process_command(&self) -> Option<ProcessCommand> {
@@ -357,17 +2708,1574 @@ impl Language {
         Some(self.tree_sitter_grammar_config.as_ref()?.id.to_string())
     }
 
+    /// The IDs of `tree_sitter_grammar_config` and of every `content_grammar_configs`.
+    pub fn tree_sitter_grammar_ids(&self) -> Vec<String> {
+        self.tree_sitter_grammar_config
+            .iter()
+            .chain(
+                self.content_grammar_configs
+                    .iter()
+                    .map(|config| &config.grammar),
+            )
+            .map(|config| config.id.clone())
+            .collect()
+    }
+
     pub fn id(&self) -> Option<LanguageId> {
         self.lsp_language_id.clone()
     }
 
-    fn formatter_command(&self) -> Option<ProcessCommand> {
+    /// Languages with the same key are served by one LSP server process,
+    /// for example, both OCaml implementation (`.ml`) and interface (`.mli`) files
+    /// are served by the same `ocamllsp`.
+    pub fn lsp_server_key(&self) -> Option<LanguageId> {
+        self.id()
+    }
+
+    /// The names of the external commands of the formatters and the LSP server,
+    /// for example, `rustfmt` and `rust-analyzer`.
+    pub fn required_commands(&self) -> Vec<String> {
         self.formatter
-            .as_ref()
-            .map(|command| ProcessCommand::new(&command.command, &command.arguments))
+            .iter()
+            .chain(&self.extra_formatters)
+            .chain(self.lsp_command.as_ref().map(|command| &command.command))
+            .map(|command| command.command.trim().to_string())
+            .collect()
     }
 
+    fn formatter_commands(&self) -> Vec<ProcessCommand> {
+        self.formatter
+            .iter()
+            .chain(&self.extra_formatters)
+            .map(|command| {
+                let arguments = command
+                    .arguments
+                    .iter()
+                    .filter_map(|argument| self.expand_formatter_argument(argument))
+                    .collect_vec();
+                ProcessCommand::new(&command.command, &arguments)
+            })
+            .collect()
+    }
+
+    /// Substitute the indentation placeholders in the formatter argument,
+    /// so that the external formatter agrees with the indentation used by the LSP.
+    ///
+    /// Returns `None` if the argument contains `{max_line_length}` but `max_line_length` is not set,
+    /// so that the formatter uses its own default instead of receiving the placeholder.
+    fn expand_formatter_argument(&self, argument: &str) -> Option<String> {
+        let argument = argument
+            .replace("{tab_width}", &self.tab_width().to_string())
+            .replace(
+                "{use_tabs}",
+                &(self.indent_unit == Some(IndentUnit::Tab)).to_string(),
+            );
+        match self.max_line_length {
+            Some(max_line_length) => {
+                Some(argument.replace("{max_line_length}", &max_line_length.to_string()))
+            }
+            None => Some(argument).filter(|argument| !argument.contains("{max_line_length}")),
+        }
+    }
+
+    /// Falls back to the built-in whitespace formatter
+    /// if no formatter is available and `enable_whitespace_formatter` is set.
     pub fn formatter(&self) -> Option<Formatter> {
-        self.formatter_command().map(Formatter::from)
+        let commands = self.formatter_commands();
+        if self.enable_whitespace_formatter
+            && !commands.iter().any(|command| command.is_available())
+        {
+            return Some(Formatter::whitespace(WhitespaceFormatter {
+                indent_unit: self.indent_unit,
+                tab_width: self.tab_width(),
+                tree_sitter_language: self.tree_sitter_language(),
+            }));
+        }
+        if commands.is_empty() {
+            return None;
+        }
+        Some(
+            Formatter::new(commands, self.formatters_mode)
+                .with_line_ending(self.format_line_ending),
+        )
+    }
+
+    /// Format the `range` of `source` and return the whole `source` with the range replaced.
+    ///
+    /// If `format_range_expands_to_statements` is set, the range is expanded
+    /// by `statement_range` first.
+    pub fn format_range(&self, source: &str, range: Range<usize>) -> anyhow::Result<String> {
+        let formatter = self
+            .formatter()
+            .ok_or_else(|| anyhow::anyhow!("No formatter is configured for this language"))?;
+        let range = if self.format_range_expands_to_statements {
+            self.statement_range(source, range)
+        } else {
+            range
+        };
+        let formatted = formatter.format(&source[range.clone()])?;
+        Ok(format!(
+            "{}{formatted}{}",
+            &source[..range.start],
+            &source[range.end..]
+        ))
+    }
+
+    /// Expand `range` to the smallest statements enclosing its start and its end,
+    /// or to the smallest statement enclosing both if they are not siblings.
+    ///
+    /// Nodes whose kind ends with `statement`, `declaration`, `definition` or `item`
+    /// are considered statements, for example, `let_declaration` and `function_item` in Rust.
+    ///
+    /// `range` is returned as is if there is no grammar or no enclosing statement.
+    pub fn statement_range(&self, source: &str, range: Range<usize>) -> Range<usize> {
+        let Some(tree) = self
+            .parser()
+            .and_then(|mut parser| parser.parse(source, None))
+        else {
+            return range;
+        };
+        let is_statement = |node: &tree_sitter::Node| {
+            ["statement", "declaration", "definition", "item"]
+                .iter()
+                .any(|suffix| node.kind().ends_with(suffix))
+        };
+        let enclosing_statement = |start: usize, end: usize| {
+            let mut node = tree.root_node().descendant_for_byte_range(start, end)?;
+            while !is_statement(&node) {
+                node = node.parent()?;
+            }
+            Some(node)
+        };
+        let last_byte = range.end.max(range.start + 1) - 1;
+        let statements = enclosing_statement(range.start, range.start)
+            .zip(enclosing_statement(last_byte, last_byte))
+            .filter(|(start, end)| start.parent() == end.parent())
+            .or_else(|| {
+                let statement = enclosing_statement(range.start, last_byte)?;
+                Some((statement, statement))
+            });
+        match statements {
+            Some((start, end)) => start.start_byte()..end.end_byte(),
+            None => range,
+        }
+    }
+
+    /// No capabilities are expected if there is no LSP command,
+    /// and formatting is only expected if `format_on_save` is set.
+    pub fn expected_lsp_capabilities(&self) -> LspClientCapabilities {
+        let lsp = self.lsp_command.is_some();
+        LspClientCapabilities {
+            formatting: lsp && self.format_on_save,
+            hover: lsp,
+            completion: lsp,
+            diagnostics: lsp,
+        }
+    }
+
+    /// The queries are only checked for existence instead of being compiled,
+    /// so that this stays cheap.
+    pub fn capabilities(&self) -> LanguageCapabilities {
+        let grammar_id = self.tree_sitter_grammar_id();
+        LanguageCapabilities {
+            grammar: grammar_id.is_some(),
+            highlight_query: self.highlight_query_override.is_some()
+                || grammar_id
+                    .as_deref()
+                    .is_some_and(|grammar_id| get_highlight_query(grammar_id).is_some())
+                || self.highlight_query_default().is_some(),
+            injections: grammar_id.as_deref().is_some_and(|grammar_id| {
+                get_injection_query(grammar_id).is_some() || !self.template_injections.is_empty()
+            }),
+            lsp: self.lsp_command.is_some(),
+            formatter: self.formatter.is_some() || !self.extra_formatters.is_empty(),
+            comments: self.line_comment_prefix.is_some() || self.block_comment_affixes.is_some(),
+        }
+    }
+}
+
+/// The LSP features that a language intends to use,
+/// so that the client capabilities advertised to its server match the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LspClientCapabilities {
+    pub formatting: bool,
+    pub hover: bool,
+    pub completion: bool,
+    pub diagnostics: bool,
+}
+
+/// Expands the leading `~` of `path` into `home_dir`, if any.
+fn expand_home_dir(path: &Path, home_dir: Option<PathBuf>) -> PathBuf {
+    match (path.strip_prefix("~"), home_dir) {
+        (Ok(rest), Some(home_dir)) => home_dir.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// The features configured for a language, for example, to be shown in the status line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LanguageCapabilities {
+    pub grammar: bool,
+    pub highlight_query: bool,
+    pub injections: bool,
+    pub lsp: bool,
+    pub formatter: bool,
+    pub comments: bool,
+}
+
+/// The grammars that only parse parts of another grammar, which are not languages of their own,
+/// for example, the paragraphs and headings of Markdown are parsed by `markdown_inline`.
+const INJECTION_ONLY_GRAMMAR_IDS: &[&str] = &["markdown_inline"];
+
+#[cfg(test)]
+mod test_language {
+    use super::*;
+
+    #[test]
+    fn asm_default_highlight_query_compiles() {
+        let language = crate::languages::languages().remove("asm").unwrap();
+        let query = language.highlight_query_default().unwrap();
+        Query::new(&language.tree_sitter_language().unwrap(), &query).unwrap();
+        assert_eq!(language.line_comment_prefixes(), vec![";", "#"]);
+    }
+
+    #[test]
+    fn makefile_default_highlight_query_compiles() {
+        let language = crate::languages::languages().remove("makefile").unwrap();
+        let query = language.highlight_query_default().unwrap();
+        Query::new(&language.tree_sitter_language().unwrap(), &query).unwrap();
+        assert_eq!(language.indent_unit(), Some(IndentUnit::Tab));
+    }
+
+    #[test]
+    fn makefile_recipe_lines_indented_with_spaces_are_warned() {
+        let language = crate::languages::languages().remove("makefile").unwrap();
+        let source = "CC := gcc\n\nbuild: main.o\n\t$(CC) -o app \\\n    main.o\n    rm main.o\n\n  # A comment\n\nclean:\n  rm app\n";
+        let warnings = language.content_warnings(source.lines());
+        assert_eq!(
+            warnings
+                .iter()
+                .map(|warning| (
+                    warning.range.start.line,
+                    warning.range.start.character,
+                    warning.range.end.character
+                ))
+                .collect_vec(),
+            [(5, 0, 4), (10, 0, 2)]
+        );
+
+        // Other languages have no such warnings
+        let yaml = crate::languages::languages().remove("yaml").unwrap();
+        assert!(yaml.content_warnings(["build:", "  - run"]).is_empty());
+    }
+
+    #[test]
+    fn validate_highlight_query() {
+        let language = crate::languages::languages().remove("rust").unwrap();
+        assert!(language
+            .validate_highlight_query("(line_comment) @comment")
+            .is_ok());
+        let error = language
+            .validate_highlight_query("(not_a_rust_node) @comment")
+            .unwrap_err();
+        assert_eq!(error.kind, QueryErrorKind::NodeType);
+    }
+
+    #[test]
+    fn highlight_capture_remap() {
+        let language = Language {
+            highlight_capture_remap: vec![("type".to_string(), "type.builtin".to_string())],
+            ..crate::languages::languages().remove("rust").unwrap()
+        };
+        assert_eq!(
+            language.remap_highlight_captures(
+                "(type_identifier) @type\n(primitive_type) @type.builtin\n(identifier) @variable"
+            ),
+            "(type_identifier) @type.builtin\n(primitive_type) @type.builtin\n(identifier) @variable"
+        );
+
+        let query = language.highlight_query().unwrap();
+        assert!(query.contains("@type.builtin"));
+        assert!(!regex::Regex::new(r"@type[^\w.\-]")
+            .unwrap()
+            .is_match(&query));
+    }
+
+    #[test]
+    fn capabilities() {
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        assert_eq!(
+            rust.capabilities(),
+            LanguageCapabilities {
+                grammar: true,
+                highlight_query: true,
+                injections: true,
+                lsp: true,
+                formatter: true,
+                comments: true,
+            }
+        );
+        let yaml = crate::languages::languages().remove("yaml").unwrap();
+        assert!(yaml.capabilities().injections);
+
+        assert_eq!(
+            Language::new().capabilities(),
+            LanguageCapabilities {
+                grammar: false,
+                highlight_query: false,
+                injections: false,
+                lsp: false,
+                formatter: false,
+                comments: false,
+            }
+        );
+    }
+
+    #[test]
+    fn position_encoding_column_conversion() {
+        let line = "a😀b";
+        let column_of_b = 2;
+        for (encoding, character) in [
+            (PositionEncoding::Utf8, 5),
+            (PositionEncoding::Utf16, 3),
+            (PositionEncoding::Utf32, 2),
+        ] {
+            assert_eq!(
+                encoding.char_column_to_character(line, column_of_b),
+                character,
+                "{encoding:?}"
+            );
+            assert_eq!(
+                encoding.character_to_char_column(line, character),
+                column_of_b,
+                "{encoding:?}"
+            );
+        }
+        // Pointing into the middle of the emoji
+        assert_eq!(PositionEncoding::Utf16.character_to_char_column(line, 2), 1);
+    }
+
+    #[test]
+    fn toggle_line_comment() {
+        let rust = crate::languages::languages().remove("rust").unwrap();
+
+        // Mixed lines are commented
+        assert_eq!(
+            rust.toggle_line_comment(&["// a", "b"]),
+            vec!["// // a", "// b"]
+        );
+
+        // All commented lines are uncommented, blank lines are ignored
+        assert_eq!(
+            rust.toggle_line_comment(&["// a", "", "//b"]),
+            vec!["a", "", "b"]
+        );
+
+        // Indentation is preserved
+        assert_eq!(
+            rust.toggle_line_comment(&["  if x {", "    y", "  }"]),
+            vec!["  // if x {", "  //   y", "  // }"]
+        );
+        assert_eq!(
+            rust.toggle_line_comment(&["  // if x {", "  //   y", "  // }"]),
+            vec!["  if x {", "    y", "  }"]
+        );
+
+        // Any known prefix is recognized on removal
+        let asm = crate::languages::languages().remove("asm").unwrap();
+        assert_eq!(asm.toggle_line_comment(&["; a", "# b"]), vec!["a", "b"]);
+        assert_eq!(asm.toggle_line_comment(&["a"]), vec!["; a"]);
+    }
+
+    #[test]
+    fn toggle_line_comment_without_padding() {
+        let rust = Language {
+            line_comment_padding: false,
+            ..crate::languages::languages().remove("rust").unwrap()
+        };
+        assert_eq!(rust.toggle_line_comment(&["code"]), vec!["//code"]);
+        assert_eq!(rust.toggle_line_comment(&["// a", "//b"]), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn toggle_block_comment() {
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        assert_eq!(rust.toggle_block_comment("hello"), "/* hello */");
+        assert_eq!(rust.toggle_block_comment("/* hello */"), "hello");
+        assert_eq!(rust.toggle_block_comment("  /*hello*/\n"), "  hello\n");
+
+        // Only the outermost comment is removed
+        assert_eq!(
+            rust.toggle_block_comment("/* a /* b */ c */"),
+            "a /* b */ c"
+        );
+
+        // Partially commented text is wrapped
+        assert_eq!(rust.toggle_block_comment("a /* b */"), "/* a /* b */ */");
+
+        // Comments cannot be nested in C, so inner comments are uncommented before wrapping
+        let c = crate::languages::languages().remove("c").unwrap();
+        assert_eq!(c.toggle_block_comment("a /* b */ c"), "/* a b c */");
+        assert_eq!(c.toggle_block_comment("/* a */ b /* c */"), "/* a b c */");
+    }
+
+    #[test]
+    fn grammar_url_template() {
+        let language = Language {
+            tree_sitter_grammar_config: Some(GrammarConfig {
+                id: "foo".to_string(),
+                kind: GrammarConfigKind::FromSource {
+                    url: None,
+                    commit: "main".to_string(),
+                    subpath: None,
+                    experimental: false,
+                    compile_flags: Vec::new(),
+                },
+                lazy_load: false,
+            }),
+            ..Language::new()
+        };
+        assert_eq!(
+            language.tree_sitter_grammar_config(Some(
+                "https://github.com/tree-sitter/tree-sitter-{id}"
+            )),
+            Some(GrammarConfiguration::remote(
+                "foo",
+                "https://github.com/tree-sitter/tree-sitter-foo",
+                "main",
+                None
+            ))
+        );
+        assert_eq!(language.tree_sitter_grammar_config(None), None);
+    }
+
+    #[test]
+    fn formatter_arguments_receive_tab_width() {
+        let language = Language {
+            formatter: Some(Command::new(
+                "prettierd",
+                &["--tab-width", "{tab_width}", "--use-tabs", "{use_tabs}"],
+            )),
+            indent_unit: Some(IndentUnit::Spaces(2)),
+            ..Language::new()
+        };
+        assert_eq!(
+            language.formatter().unwrap().command_string(),
+            "prettierd --tab-width 2 --use-tabs false"
+        );
+
+        let language = Language {
+            indent_unit: Some(IndentUnit::Tab),
+            tab_width: Some(8),
+            ..language
+        };
+        assert_eq!(
+            language.formatter().unwrap().command_string(),
+            "prettierd --tab-width 8 --use-tabs true"
+        );
+    }
+
+    #[test]
+    fn ignore_globs() {
+        let language = Language {
+            ignore_globs: vec!["*.pb.go".to_string()],
+            ..crate::languages::languages().remove("go").unwrap()
+        };
+        let generated = Path::new("/project/api/service.pb.go");
+        assert!(!language.should_format(generated));
+        assert!(!language.should_attach_lsp(generated));
+
+        let normal = Path::new("/project/api/service.go");
+        assert!(language.should_format(normal));
+        assert!(language.should_attach_lsp(normal));
+    }
+
+    #[test]
+    fn max_line_length() -> anyhow::Result<()> {
+        let language = Language {
+            formatter: Some(Command::new(
+                "prettierd",
+                &["--print-width", "{max_line_length}"],
+            )),
+            max_line_length: Some(100),
+            ..Language::new()
+        };
+        let language: Language = serde_json::from_value(serde_json::to_value(language)?)?;
+        assert_eq!(language.max_line_length(), Some(100));
+        assert_eq!(
+            language.formatter().unwrap().command_string(),
+            "prettierd --print-width 100"
+        );
+
+        assert_eq!(Language::new().max_line_length(), None);
+
+        // The argument of an unset `max_line_length` is dropped
+        let language = Language {
+            formatter: Some(Command::new(
+                "prettierd",
+                &["--print-width={max_line_length}", "--use-tabs={use_tabs}"],
+            )),
+            ..Language::new()
+        };
+        assert_eq!(
+            language.formatter().unwrap().command_string(),
+            "prettierd --use-tabs=false"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn highlight_query_merge() {
+        fn capture_names(language: &Language, query: &str) -> std::collections::HashSet<String> {
+            Query::new(&language.tree_sitter_language().unwrap(), query)
+                .unwrap()
+                .capture_names()
+                .iter()
+                .map(|name| name.to_string())
+                .collect()
+        }
+        let language = Language {
+            highlight_query_merge: true,
+            ..crate::languages::languages().remove("rust").unwrap()
+        };
+        let nvim_treesitter = capture_names(
+            &language,
+            &language.highlight_query_nvim_treesitter().unwrap(),
+        );
+        let default = capture_names(&language, &language.highlight_query_default().unwrap());
+        let merged = capture_names(&language, &language.highlight_query().unwrap());
+
+        let nvim_treesitter_only = nvim_treesitter.difference(&default).collect_vec();
+        let default_only = default.difference(&nvim_treesitter).collect_vec();
+        assert!(!nvim_treesitter_only.is_empty());
+        assert!(!default_only.is_empty());
+        for name in nvim_treesitter_only.into_iter().chain(default_only) {
+            assert!(merged.contains(name), "{name}");
+        }
+
+        // Without merging, only the nvim-treesitter query is used
+        let language = Language {
+            highlight_query_merge: false,
+            ..language
+        };
+        assert_eq!(
+            capture_names(&language, &language.highlight_query().unwrap()),
+            nvim_treesitter
+        );
+    }
+
+    #[test]
+    fn files_larger_than_max_grammar_file_size_get_no_grammar() {
+        let language = Language {
+            max_grammar_file_size: Some(1024),
+            ..crate::languages::languages().remove("javascript").unwrap()
+        };
+        assert!(language.tree_sitter_language_for_file_size(1024).is_some());
+        assert!(language.tree_sitter_language_for_file_size(1025).is_none());
+
+        let language = Language {
+            max_grammar_file_size: None,
+            ..language
+        };
+        assert!(language
+            .tree_sitter_language_for_file_size(DEFAULT_MAX_GRAMMAR_FILE_SIZE)
+            .is_some());
+    }
+
+    #[test]
+    fn query_load_metrics_are_recorded_on_request() {
+        let language = crate::languages::languages().remove("yaml").unwrap();
+        language.load_queries_recording_metrics();
+
+        let metrics = language.last_query_load_metrics().unwrap();
+        assert!(metrics.highlight.is_some());
+        assert!(metrics.injections.is_some());
+        assert!(metrics.locals.is_some());
+    }
+
+    #[test]
+    fn nix_lsp_root_directory_is_the_nearest_flake() -> anyhow::Result<()> {
+        let language = crate::languages::languages().remove("nix").unwrap();
+        let tempdir = tempfile::tempdir()?;
+        let root = tempdir.path();
+        let package = root.join("pkgs").join("hello");
+        std::fs::create_dir_all(&package)?;
+        std::fs::write(root.join("flake.nix"), "{}")?;
+        std::fs::write(package.join("default.nix"), "{}")?;
+        std::fs::write(package.join("hello.nix"), "{}")?;
+
+        // `flake.nix` has a higher priority than the nearer `default.nix`
+        assert_eq!(
+            language.lsp_root_directory(&package.join("hello.nix")),
+            Some(root.to_path_buf())
+        );
+
+        std::fs::remove_file(root.join("flake.nix"))?;
+        assert_eq!(
+            language.lsp_root_directory(&package.join("hello.nix")),
+            Some(package.clone())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn strip_comments_using_grammar() {
+        let language = crate::languages::languages().remove("rust").unwrap();
+        let source = "let url = \"https://example.com\"; // line\n/* block\n comment */ let x = 1;";
+        assert_eq!(
+            language.strip_comments(source),
+            "let url = \"https://example.com\";        \n        \n            let x = 1;"
+        );
+
+        // Each character is blanked with one space, so that the columns are unchanged
+        assert_eq!(language.strip_comments("x; // é\n"), "x;     \n");
+    }
+
+    #[test]
+    fn strip_comments_using_affixes() {
+        let language = Language {
+            line_comment_prefix: Some("//".to_string()),
+            block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
+            ..Language::new()
+        };
+        let source = "a(\"http://x\\\" // y\"); // line\nb /* block\n */ c";
+        assert_eq!(
+            language.strip_comments(source),
+            "a(\"http://x\\\" // y\");        \nb         \n    c"
+        );
+    }
+
+    #[test]
+    fn detect_indent() {
+        let spaces = "fn main() {\n    if x {\n        y();\n    }\n\n    z();\n}\n";
+        assert_eq!(Language::detect_indent(spaces), Some(IndentUnit::Spaces(4)));
+
+        let tabs = "fn main() {\n\tif x {\n\t\ty();\n\t}\n}\n";
+        assert_eq!(Language::detect_indent(tabs), Some(IndentUnit::Tab));
+
+        // Two lines are indented with spaces, but three with tabs
+        let mixed = "a\n\tb\n\tc\n\td\na\n  b\n  c\n";
+        assert_eq!(Language::detect_indent(mixed), Some(IndentUnit::Tab));
+        let mixed = "a\n  b\n  c\n  d\na\n\tb\n";
+        assert_eq!(Language::detect_indent(mixed), Some(IndentUnit::Spaces(2)));
+
+        assert_eq!(Language::detect_indent("a\nb\n"), None);
+    }
+
+    #[test]
+    fn conditional_initialization_options() -> anyhow::Result<()> {
+        let language = Language {
+            lsp_command: Some(LspCommand {
+                command: Command::new("rust-analyzer", &[]),
+                initialization_options: Some(serde_json::json!({
+                    "cargo": { "features": "all" }
+                })),
+                conditional_initialization_options: vec![ConditionalInitializationOptions {
+                    file: "rust-toolchain.toml".to_string(),
+                    options: serde_json::json!({ "cargo": { "extraEnv": { "X": "1" } } }),
+                }],
+                ..LspCommand::default()
+            }),
+            ..Language::new()
+        };
+        let tempdir = tempfile::tempdir()?;
+        assert_eq!(
+            language.initialization_options_for(tempdir.path()),
+            Some(serde_json::json!({ "cargo": { "features": "all" } }))
+        );
+
+        std::fs::write(tempdir.path().join("rust-toolchain.toml"), "")?;
+        assert_eq!(
+            language.initialization_options_for(tempdir.path()),
+            Some(serde_json::json!({
+                "cargo": { "features": "all", "extraEnv": { "X": "1" } }
+            }))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cargo_toml_has_its_own_highlight_query() {
+        let toml = crate::languages::languages().remove("toml").unwrap();
+        let cargo_toml = toml.clone().for_path(Path::new("/project/Cargo.toml"));
+        let foo_toml = toml.clone().for_path(Path::new("/project/foo.toml"));
+
+        let cargo_toml_query = cargo_toml.highlight_query().unwrap();
+        assert!(cargo_toml_query.starts_with(include_str!("../queries/cargo-toml-highlights.scm")));
+        assert_eq!(foo_toml.highlight_query(), toml.highlight_query());
+
+        assert_eq!(cargo_toml.highlight_config_id().unwrap(), "toml:Cargo.toml");
+        assert_eq!(foo_toml.highlight_config_id().unwrap(), "toml");
+    }
+
+    #[test]
+    fn captures_unused_by_theme() {
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        let captures = rust.highlight_capture_names();
+        assert!(captures.contains(&"keyword".to_string()));
+        assert!(captures.contains(&"comment".to_string()));
+
+        let unused = rust.captures_unused_by_theme(&["keyword", "comment"]);
+        assert!(!unused.is_empty());
+        assert!(unused
+            .iter()
+            .all(|capture| { !capture.starts_with("keyword") && !capture.starts_with("comment") }));
+        assert!(unused.contains(&"string".to_string()));
+
+        assert_eq!(
+            rust.captures_unused_by_theme(
+                &captures
+                    .iter()
+                    .map(|capture| capture.as_str())
+                    .collect_vec()
+            ),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn matching_bracket_ignores_brackets_in_strings() {
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        let source = r#"f(")", x)"#;
+        let close = source.len() - 1;
+        assert_eq!(rust.matching_bracket(source, 1), Some(close));
+        assert_eq!(rust.matching_bracket(source, close), Some(1));
+
+        // Without a brackets query, the `)` in the string is paired instead
+        assert_eq!(Language::new().matching_bracket(source, 1), Some(3));
+    }
+
+    #[test]
+    fn lsp_file_watchers() {
+        let mut watchers = LspFileWatchers::default();
+        assert!(watchers.globs().is_empty());
+
+        watchers.register(&[lsp_types::Registration {
+            id: "rust-analyzer-watcher".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: Some(serde_json::json!({
+                "watchers": [{ "globPattern": "**/*.rs" }]
+            })),
+        }]);
+        assert_eq!(watchers.globs(), vec!["**/*.rs"]);
+        assert!(watchers.is_watched(Path::new("/project/src/main.rs")));
+        assert!(!watchers.is_watched(Path::new("/project/Cargo.toml")));
+
+        watchers.unregister(&[lsp_types::Unregistration {
+            id: "rust-analyzer-watcher".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+        }]);
+        assert!(watchers.globs().is_empty());
+    }
+
+    #[test]
+    fn indent_fold_ranges() {
+        let source = "
+a:
+  b:
+    c
+
+  d:
+    e
+f
+"
+        .trim_start();
+        assert_eq!(
+            Language::new().indent_fold_ranges(source),
+            vec![
+                FoldRange {
+                    start_line: 0,
+                    end_line: 5
+                },
+                FoldRange {
+                    start_line: 1,
+                    end_line: 2
+                },
+                FoldRange {
+                    start_line: 4,
+                    end_line: 5
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parser() {
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        let tree = rust.parser().unwrap().parse("fn main(){}", None).unwrap();
+        assert!(!tree.root_node().has_error());
+        assert_eq!(tree.root_node().child(0).unwrap().kind(), "function_item");
+
+        assert!(Language::new().parser().is_none());
+    }
+
+    #[test]
+    fn grammar_abi_version() {
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        assert!(rust.grammar_abi_version().unwrap() > 0);
+        assert_eq!(Language::new().grammar_abi_version(), None);
+    }
+
+    #[test]
+    fn format_range_expands_to_statements() -> anyhow::Result<()> {
+        let source = "fn main() {\n    let x = 1;\n    let y = 2;\n}\n";
+        let rust = Language {
+            formatter: Some(Command::new("tr", &["a-z", "A-Z"])),
+            format_range_expands_to_statements: true,
+            ..crate::languages::languages().remove("rust").unwrap()
+        };
+        // The selected `x = ` is part of the first `let` statement
+        let start = source.find("x = ").unwrap();
+        let range = start..start + "x = ".len();
+        assert_eq!(
+            rust.format_range(source, range.clone())?,
+            "fn main() {\n    LET X = 1;\n    let y = 2;\n}\n"
+        );
+
+        // Without the mode, only the selected range is formatted
+        let rust = Language {
+            format_range_expands_to_statements: false,
+            ..rust
+        };
+        assert_eq!(
+            rust.format_range(source, range)?,
+            "fn main() {\n    let X = 1;\n    let y = 2;\n}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn whitespace_sensitive_languages_reparse_the_enclosing_block() {
+        let source = "def f():\n    x = 1\n    y = 2\n\ndef g():\n    pass\n";
+        // Dedenting `y = 2`
+        let edited = source.find("    y").unwrap()..source.find("y").unwrap();
+
+        let python = crate::languages::languages().remove("python").unwrap();
+        assert_eq!(
+            python.reparse_range(source, edited.clone()),
+            0..source.find("def g").unwrap()
+        );
+
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        assert_eq!(rust.reparse_range(source, edited.clone()), edited);
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn diff_hunks() {
+        let source = "
+diff --git a/a.txt b/a.txt
+--- a/a.txt
++++ b/a.txt
+@@ -1,2 +1,2 @@
+-x
++y
+ z
+diff --git a/b.txt b/b.txt
+--- a/b.txt
++++ b/b.txt
+@@ -1 +1,2 @@
+ w
++v
+"
+        .trim_start();
+        let diff = crate::languages::languages().remove("diff").unwrap();
+        assert_eq!(
+            diff.diff_hunks(source),
+            vec![
+                DiffHunk {
+                    old_file: Some("a/a.txt".to_string()),
+                    new_file: Some("b/a.txt".to_string()),
+                    lines: 3..7,
+                    added_lines: vec![5..6],
+                    removed_lines: vec![4..5],
+                },
+                DiffHunk {
+                    old_file: Some("a/b.txt".to_string()),
+                    new_file: Some("b/b.txt".to_string()),
+                    lines: 10..13,
+                    added_lines: vec![12..13],
+                    removed_lines: Vec::new(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn injection_language_name_at() {
+        let markdown = crate::languages::languages().remove("markdown").unwrap();
+        let source = "# Config\n\n```lua\nlocal x = 1\n```\n";
+        assert_eq!(
+            markdown.injection_language_name_at(source, source.find("local").unwrap()),
+            Some("lua".to_string())
+        );
+
+        // The heading belongs to Markdown itself
+        assert_eq!(markdown.injection_language_name_at(source, 0), None);
+    }
+
+    #[test]
+    fn markdown_paragraphs_are_not_injections() {
+        let markdown = crate::languages::languages().remove("markdown").unwrap();
+        let source = "# Config\n\nA *plain* paragraph.\n";
+        assert_eq!(
+            markdown.injection_language_name_at(source, source.find("plain").unwrap()),
+            None
+        );
+        assert!(markdown.injected_language_names(source).is_empty());
+    }
+
+    #[test]
+    fn format_on_save_advertises_formatting() {
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        assert_eq!(
+            rust.expected_lsp_capabilities(),
+            LspClientCapabilities {
+                formatting: true,
+                hover: true,
+                completion: true,
+                diagnostics: true,
+            }
+        );
+
+        let rust = Language {
+            format_on_save: false,
+            ..rust
+        };
+        assert!(!rust.expected_lsp_capabilities().formatting);
+        assert!(!rust.should_format(Path::new("main.rs")));
+
+        assert!(!Language::new().expected_lsp_capabilities().formatting);
+    }
+
+    #[test]
+    fn c_header_with_template_picks_cpp_grammar() {
+        let c = crate::languages::languages().remove("c").unwrap();
+        let source = "template<typename T>\nclass Box {\n  T value;\n};\n";
+        assert_eq!(c.tree_sitter_grammar_config_for(source).unwrap().id, "cpp");
+        assert_eq!(
+            c.clone().for_content(source).highlight_config_id(),
+            Some("cpp".to_string())
+        );
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&c.tree_sitter_language_for(source).unwrap())
+            .unwrap();
+        assert!(!parser.parse(source, None).unwrap().root_node().has_error());
+
+        assert_eq!(
+            c.tree_sitter_grammar_config_for("int main(void);\n")
+                .unwrap()
+                .id,
+            "c"
+        );
+    }
+
+    #[test]
+    fn yaml_alias_resolves_to_anchor() {
+        let yaml = crate::languages::languages().remove("yaml").unwrap();
+        let source = "defaults: &defaults\n  a: 1\nproduction:\n  <<: *defaults\n";
+        let alias = source.rfind("defaults").unwrap();
+        let anchor = source.find("&defaults").unwrap() + 1;
+        assert_eq!(
+            yaml.local_definition_at(source, alias),
+            Some(anchor..anchor + "defaults".len())
+        );
+
+        // Not on an alias
+        assert_eq!(yaml.local_definition_at(source, 0), None);
+    }
+
+    #[test]
+    fn cargo_linked_default_extensions() {
+        assert_eq!(
+            CargoLinkedTreesitterLanguage::Rust.default_extensions(),
+            ["rs"]
+        );
+        assert_eq!(
+            CargoLinkedTreesitterLanguage::Typescript.default_extensions(),
+            ["ts"]
+        );
+
+        // Every default extension is recognized by a built-in language using the grammar
+        let languages = crate::languages::languages();
+        let cargo_linked_languages = languages.values().filter_map(|language| {
+            match &language.tree_sitter_grammar_config.as_ref()?.kind {
+                GrammarConfigKind::CargoLinked(cargo_linked) => Some((cargo_linked, language)),
+                GrammarConfigKind::FromSource { .. } | GrammarConfigKind::FromLocalPath { .. } => {
+                    None
+                }
+            }
+        });
+        for (cargo_linked, _) in cargo_linked_languages.clone() {
+            if let Some(extension) = cargo_linked.default_extensions().iter().find(|extension| {
+                !cargo_linked_languages.clone().any(|(other, language)| {
+                    other == cargo_linked && language.extensions.contains(&extension.to_string())
+                })
+            }) {
+                panic!("{cargo_linked:?} has an unknown default extension {extension:?}")
+            }
+        }
+    }
+
+    #[test]
+    fn lsp_single_file_support_skips_root_markers() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::write(tempdir.path().join(".git"), "")?;
+        let path = tempdir.path().join("script.sh");
+        let bash = Language {
+            lsp_root_markers: vec![".git".to_string()],
+            ..crate::languages::languages().remove("bash").unwrap()
+        };
+        assert!(bash.lsp_single_file_support());
+        assert_eq!(bash.lsp_root_directory(&path), None);
+
+        let bash = Language {
+            lsp_command: bash.lsp_command.clone().map(|command| LspCommand {
+                lsp_single_file_support: false,
+                ..command
+            }),
+            ..bash
+        };
+        assert_eq!(
+            bash.lsp_root_directory(&path),
+            Some(tempdir.path().to_path_buf())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn lint_highlight_query() {
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        let sample = "fn main() { let x = 1; }";
+        assert_eq!(
+            rust.lint_highlight_query(
+                "(function_item name: (identifier) @function) (macro_invocation) @function.macro",
+                sample
+            ),
+            vec![HighlightQueryWarning::DeadCapture {
+                capture_name: "function.macro".to_string()
+            }]
+        );
+        assert!(matches!(
+            rust.lint_highlight_query("(nonexistent_node) @keyword", sample)[..],
+            [HighlightQueryWarning::InvalidQuery(QueryError {
+                kind: QueryErrorKind::NodeType,
+                ..
+            })]
+        ));
+    }
+
+    #[test]
+    fn shell_languages_share_comment_and_word_chars() {
+        let mut languages = crate::languages::languages();
+        for id in ["bash", "fish"] {
+            let language = languages.remove(id).unwrap();
+            assert_eq!(language.line_comment_prefix.as_deref(), Some("#"));
+            assert_eq!(language.word_chars(), "./~");
+            assert_eq!(
+                language.toggle_line_comment(&["if true; then", "  echo ~/bin", "fi"]),
+                ["# if true; then", "#   echo ~/bin", "# fi"]
+            );
+            assert_eq!(
+                language.toggle_line_comment(&["# echo hi", "#   echo ~/bin"]),
+                ["echo hi", "  echo ~/bin"]
+            );
+        }
+    }
+
+    #[test]
+    fn node_kind_count() {
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        let count = rust.node_kind_count().unwrap();
+        // Rust has well over a hundred node kinds, but not an absurd number
+        assert!((100..10_000).contains(&count), "{count}");
+        assert_eq!(Language::new().node_kind_count(), None);
+    }
+
+    #[test]
+    fn go_build_constraints_are_not_uncommented() {
+        let go = crate::languages::languages().remove("go").unwrap();
+        assert_eq!(
+            go.toggle_line_comment(&["//go:build linux", "package main"]),
+            ["//go:build linux", "// package main"]
+        );
+        assert_eq!(
+            go.toggle_line_comment(&["// //go:build linux", "// package main"]),
+            ["//go:build linux", "package main"]
+        );
+        assert_eq!(
+            go.toggle_line_comment(&["// +build linux", "// A comment"]),
+            ["// +build linux", "A comment"]
+        );
+
+        let source = "//go:build linux\n// A comment\npackage main\n";
+        assert_eq!(
+            go.strip_comments(source),
+            "//go:build linux\n            \npackage main\n"
+        );
+    }
+
+    #[test]
+    fn grammar_source_dir() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        std::fs::create_dir(tempdir.path().join("queries"))?;
+        std::fs::write(
+            tempdir.path().join("queries").join("highlights.scm"),
+            "(identifier) @variable",
+        )?;
+        let language = Language {
+            tree_sitter_grammar_config: Some(GrammarConfig {
+                id: "local".to_string(),
+                kind: GrammarConfigKind::FromLocalPath {
+                    path: tempdir.path().to_path_buf(),
+                },
+                lazy_load: false,
+            }),
+            ..Language::new()
+        };
+        assert_eq!(
+            language.grammar_source_dir(),
+            Some(tempdir.path().to_path_buf())
+        );
+        assert_eq!(
+            language.highlight_query_default().as_deref(),
+            Some("(identifier) @variable")
+        );
+
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        assert_eq!(rust.grammar_source_dir(), None);
+        let sql = crate::languages::languages().remove("sql").unwrap();
+        assert!(sql.grammar_source_dir().unwrap().ends_with("sources/sql"));
+        Ok(())
+    }
+
+    #[test]
+    fn effective_comment_syntax() {
+        let mut languages = crate::languages::languages();
+        let expected = CommentSyntax {
+            line_prefixes: vec!["//".to_string()],
+            line_padding: true,
+            doc_prefix: None,
+            block_affixes: Some(("/*".to_string(), "*/".to_string())),
+            block_nestable: false,
+        };
+        // TSX does not set its comments, but inherits them from TypeScript
+        let tsx = languages.remove("typescriptreact").unwrap();
+        assert_eq!(tsx.effective_comment_syntax(), expected);
+        assert_eq!(tsx.tree_sitter_grammar_id().as_deref(), Some("tsx"));
+
+        let rust = languages.remove("rust").unwrap();
+        assert_eq!(
+            rust.effective_comment_syntax(),
+            CommentSyntax {
+                doc_prefix: Some("///".to_string()),
+                block_nestable: true,
+                ..expected
+            }
+        );
+    }
+
+    #[test]
+    fn debug_tree() {
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        let tree = rust.debug_tree("fn main(){}", false).unwrap();
+        assert!(tree.contains("(function_item name: (identifier)"), "{tree}");
+
+        let tree = rust.debug_tree("fn main(){}", true).unwrap();
+        assert!(
+            tree.starts_with("(source_file 0..11 (function_item 0..11 name: (identifier 3..7)"),
+            "{tree}"
+        );
+        assert_eq!(Language::new().debug_tree("fn main(){}", false), None);
+    }
+
+    #[test]
+    fn injected_language_names() {
+        let markdown = crate::languages::languages().remove("markdown").unwrap();
+        let source =
+            "```rust\nfn main() {}\n```\n\n```python\nx = 1\n```\n\n```rust\nfn f() {}\n```\n";
+        assert_eq!(markdown.injected_language_names(source), ["rust", "python"]);
+        assert!(Language::new().injected_language_names(source).is_empty());
+    }
+
+    #[test]
+    fn lazy_grammar_is_loaded_on_first_resolution() {
+        let language = |id: &str, lazy_load: bool| Language {
+            tree_sitter_grammar_config: Some(GrammarConfig {
+                id: id.to_string(),
+                kind: GrammarConfigKind::FromLocalPath {
+                    path: PathBuf::from("/nonexistent/tree-sitter-lazy"),
+                },
+                lazy_load,
+            }),
+            ..Language::new()
+        };
+        let lazy = language("lazy-grammar-test", true);
+        let eager = language("eager-grammar-test", false);
+        assert!(lazy.lazy_loads_grammar());
+        assert!(!eager.lazy_loads_grammar());
+
+        assert!(!super::lazy_grammar_load_requested("lazy-grammar-test"));
+        assert!(lazy.tree_sitter_grammar_config(None).is_some());
+        assert!(!super::lazy_grammar_load_requested("lazy-grammar-test"));
+
+        assert!(lazy.tree_sitter_language().is_none());
+        assert!(super::lazy_grammar_load_requested("lazy-grammar-test"));
+
+        assert!(eager.tree_sitter_language().is_none());
+        assert!(!super::lazy_grammar_load_requested("eager-grammar-test"));
+    }
+
+    #[test]
+    fn comment_ratio() {
+        // 7 non-whitespace bytes of comments, and 7 of code
+        let source = "// abcde\nfn f() {}\n";
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        assert!((rust.comment_ratio(source) - 0.5).abs() < 0.01);
+
+        let affixes = Language {
+            line_comment_prefix: Some("//".to_string()),
+            ..Language::new()
+        };
+        assert!((affixes.comment_ratio(source) - 0.5).abs() < 0.01);
+
+        assert_eq!(Language::new().comment_ratio(source), 0.0);
+        assert_eq!(rust.comment_ratio("  \n"), 0.0);
+    }
+
+    #[test]
+    fn language_without_grammar() -> anyhow::Result<()> {
+        let dotenv = crate::languages::languages().remove("dotenv").unwrap();
+        assert!(dotenv.tree_sitter_language().is_none());
+        assert_eq!(dotenv.highlight_query(), None);
+        assert_eq!(dotenv.line_comment_prefix(), Some("#".to_string()));
+        assert_eq!(
+            dotenv.strip_comments("# secret\nA=1 # one\n"),
+            "        \nA=1      \n"
+        );
+
+        let language = Language {
+            formatter: Some(Command::new("tr", &["a-z", "A-Z"])),
+            lsp_command: Some(LspCommand {
+                command: Command::new("dotenv-lsp", &["--stdio"]),
+                ..LspCommand::default()
+            }),
+            ..dotenv
+        };
+        assert_eq!(language.formatter().unwrap().format("a=b\n")?, "A=B\n");
+        assert_eq!(
+            language.lsp_process_command().unwrap().to_string(),
+            "dotenv-lsp --stdio"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn highlight_query_overrides() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let valid = tempdir.path().join("valid.scm");
+        std::fs::write(&valid, "(string) @constant")?;
+        let invalid = tempdir.path().join("invalid.scm");
+        std::fs::write(&invalid, "(no_such_node) @constant")?;
+        let language = Language {
+            highlight_query_overrides: vec![
+                ("**/schema.json".to_string(), valid.clone()),
+                ("**/broken.json".to_string(), invalid),
+            ],
+            ..crate::languages::languages().remove("json").unwrap()
+        };
+        let default_query = language.clone().highlight_query().unwrap();
+
+        let overridden = language.clone().for_path(Path::new("/project/schema.json"));
+        assert_eq!(
+            overridden.highlight_query(),
+            Some("(string) @constant".to_string())
+        );
+        assert_eq!(
+            overridden.highlight_config_id(),
+            Some(format!("json:{}", valid.display()))
+        );
+
+        let other = language.clone().for_path(Path::new("/project/other.json"));
+        assert_eq!(other.highlight_query(), Some(default_query.clone()));
+        assert_eq!(other.highlight_config_id(), Some("json".to_string()));
+
+        let broken = language.for_path(Path::new("/project/broken.json"));
+        assert_eq!(broken.highlight_query(), Some(default_query));
+        Ok(())
+    }
+
+    #[test]
+    fn highlight_query_override_paths_expand_the_home_dir() {
+        let home_dir = Some(PathBuf::from("/home/user"));
+        assert_eq!(
+            expand_home_dir(
+                Path::new("~/.config/ki/package-json-highlights.scm"),
+                home_dir.clone()
+            ),
+            PathBuf::from("/home/user/.config/ki/package-json-highlights.scm")
+        );
+        assert_eq!(
+            expand_home_dir(Path::new("/etc/ki/highlights.scm"), home_dir.clone()),
+            PathBuf::from("/etc/ki/highlights.scm")
+        );
+        assert_eq!(
+            expand_home_dir(Path::new("~user/highlights.scm"), home_dir),
+            PathBuf::from("~user/highlights.scm")
+        );
+        assert_eq!(
+            expand_home_dir(Path::new("~/highlights.scm"), None),
+            PathBuf::from("~/highlights.scm")
+        );
+    }
+
+    #[test]
+    fn swift_lsp_root_directory_is_the_nearest_package() -> anyhow::Result<()> {
+        let swift = crate::languages::languages().remove("swift").unwrap();
+        assert_eq!(
+            swift.lsp_process_command().unwrap().to_string(),
+            "sourcekit-lsp "
+        );
+
+        let tempdir = tempfile::tempdir()?;
+        let root = tempdir.path();
+        let sources = root.join("Sources").join("App");
+        std::fs::create_dir_all(&sources)?;
+        std::fs::create_dir_all(root.join("App.xcodeproj"))?;
+        std::fs::write(sources.join("main.swift"), "")?;
+        assert_eq!(
+            swift.lsp_root_directory(&sources.join("main.swift")),
+            Some(root.to_path_buf())
+        );
+
+        let package = root.join("Packages").join("Core");
+        std::fs::create_dir_all(package.join("Sources"))?;
+        std::fs::write(package.join("Package.swift"), "")?;
+        assert_eq!(
+            swift.lsp_root_directory(&package.join("Sources").join("Core.swift")),
+            Some(package.clone())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn preferred_extension() {
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        assert_eq!(rust.preferred_extension(), Some("rs"));
+
+        let language = Language {
+            extensions: vec!["yml".to_string(), "yaml".to_string()],
+            primary_extension: Some("yaml".to_string()),
+            ..Language::new()
+        };
+        assert_eq!(language.preferred_extension(), Some("yaml"));
+        assert_eq!(Language::new().preferred_extension(), None);
+    }
+
+    #[test]
+    fn self_test() {
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        assert_eq!(rust.self_test(), Ok(()));
+
+        let broken_sample = Language {
+            grammar_self_test_sample: Some("fn main() {}\nfn (".to_string()),
+            ..rust.clone()
+        };
+        assert!(matches!(
+            broken_sample.self_test(),
+            Err(GrammarSelfTestError::SyntaxError { byte_range }) if byte_range.start >= 13
+        ));
+
+        let broken_grammar = Language {
+            tree_sitter_grammar_config: Some(GrammarConfig {
+                id: "broken-self-test-grammar".to_string(),
+                kind: GrammarConfigKind::FromLocalPath {
+                    path: PathBuf::from("/nonexistent/tree-sitter-broken"),
+                },
+                lazy_load: false,
+            }),
+            ..rust
+        };
+        assert_eq!(
+            broken_grammar.self_test(),
+            Err(GrammarSelfTestError::GrammarUnavailable)
+        );
+
+        // There is nothing to test without a sample
+        assert_eq!(Language::new().self_test(), Ok(()));
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn escape_for_comment() {
+        let languages = crate::languages::languages();
+        let wrap = |language: &Language, text: &str| {
+            let (open, close) = language.block_comment_affixes.clone().unwrap();
+            format!("{open} {} {close}", language.escape_for_comment(text))
+        };
+
+        let c = languages.get("c").unwrap();
+        assert_eq!(c.escape_for_comment("a */ b /* c"), "a * / b /* c");
+        let comment = wrap(c, "int x = 1; /* one */ **//");
+        assert_eq!(
+            c.comment_byte_ranges_from_grammar(&comment),
+            Some(vec![0..comment.len()])
+        );
+
+        // Opening delimiters nest in Rust
+        let rust = languages.get("rust").unwrap();
+        assert_eq!(rust.escape_for_comment("a */ b /* c"), "a * / b / * c");
+        let comment = wrap(rust, "let x = 1; /* one */*/*");
+        assert_eq!(
+            rust.comment_byte_ranges_from_grammar(&comment),
+            Some(vec![0..comment.len()])
+        );
+
+        assert_eq!(Language::new().escape_for_comment("a */ b"), "a */ b");
+    }
+
+    #[test]
+    fn field_names() {
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        let field_names = rust.field_names();
+        assert!(field_names.contains(&"name".to_string()), "{field_names:?}");
+        assert!(field_names.contains(&"body".to_string()), "{field_names:?}");
+        assert_eq!(field_names.iter().unique().count(), field_names.len());
+        assert!(Language::new().field_names().is_empty());
+    }
+
+    #[test]
+    fn whitespace_formatter_is_used_when_no_formatter_is_available() -> anyhow::Result<()> {
+        let language = Language {
+            enable_whitespace_formatter: true,
+            ..Language::new()
+        };
+        let formatter = language.formatter().unwrap();
+        assert_eq!(
+            formatter.format("fn main() {  \n    let x = 1;\t\n}")?,
+            "fn main() {\n    let x = 1;\n}\n"
+        );
+
+        let language = Language {
+            formatter: Some(Command::new("a-command-that-does-not-exist", &[])),
+            ..language
+        };
+        assert_eq!(
+            language.formatter().unwrap().command_string(),
+            "[built-in whitespace formatter]"
+        );
+        assert!(Language::new().formatter().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn grammar_provenance_follows_grammar_config_kind() {
+        let with_kind = |kind| Language {
+            tree_sitter_grammar_config: Some(GrammarConfig {
+                id: "foo".to_string(),
+                kind,
+                lazy_load: false,
+            }),
+            ..Language::new()
+        };
+        assert_eq!(Language::new().grammar_provenance(), None);
+        assert_eq!(
+            with_kind(GrammarConfigKind::CargoLinked(
+                CargoLinkedTreesitterLanguage::Rust
+            ))
+            .grammar_provenance(),
+            Some(GrammarProvenance::CargoLinked)
+        );
+        assert_eq!(
+            with_kind(GrammarConfigKind::FromSource {
+                url: None,
+                commit: "main".to_string(),
+                subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
+            })
+            .grammar_provenance(),
+            Some(GrammarProvenance::FromSource {
+                commit: "main".to_string()
+            })
+        );
+        assert_eq!(
+            with_kind(GrammarConfigKind::FromLocalPath {
+                path: PathBuf::from("/tmp/tree-sitter-foo"),
+            })
+            .grammar_provenance(),
+            Some(GrammarProvenance::FromLocalPath {
+                path: PathBuf::from("/tmp/tree-sitter-foo")
+            })
+        );
+    }
+
+    #[test]
+    fn lsp_language_id_for_uses_the_extension_override() -> anyhow::Result<()> {
+        let languages = crate::languages::languages();
+        let id_for = |language: &str, path: &str| {
+            languages[language]
+                .lsp_language_id_for(Path::new(path))
+                .map(|id| id.to_string())
+        };
+        assert_eq!(id_for("typescript", "a.ts").as_deref(), Some("typescript"));
+        assert_eq!(
+            id_for("typescriptreact", "a.tsx").as_deref(),
+            Some("typescriptreact")
+        );
+
+        // A language spanning both extensions
+        let language: Language = serde_json::from_value(serde_json::json!({
+            "extensions": ["ts", "tsx"],
+            "file_names": [],
+            "lsp_language_id": "typescript",
+            "lsp_language_id_overrides": [["tsx", "typescriptreact"]]
+        }))?;
+        let id_for = |path: &str| {
+            language
+                .lsp_language_id_for(Path::new(path))
+                .map(|id| id.to_string())
+        };
+        assert_eq!(id_for("src/a.ts").as_deref(), Some("typescript"));
+        assert_eq!(id_for("src/a.tsx").as_deref(), Some("typescriptreact"));
+        assert_eq!(id_for("a").as_deref(), Some("typescript"));
+        Ok(())
+    }
+
+    #[test]
+    fn over_budget_highlight_query_falls_back() {
+        let rust = crate::languages::languages().remove("rust").unwrap();
+        let complexity_of =
+            |query: &str| QueryComplexity::of(&rust.compile_highlight_query(query).unwrap().query);
+        let nvim_treesitter = complexity_of(&rust.highlight_query_nvim_treesitter().unwrap());
+        let default = complexity_of(&rust.highlight_query_default().unwrap());
+        assert!(nvim_treesitter.pattern_count > default.pattern_count);
+        assert_eq!(
+            rust.highlight_query_complexity(),
+            Some(complexity_of(&rust.highlight_query().unwrap()))
+        );
+
+        // The nvim-treesitter query exceeds the budget, so the default query is used
+        let budgeted = Language {
+            highlight_query_budget: Some(HighlightQueryBudget {
+                max_patterns: Some(default.pattern_count),
+                max_captures: None,
+            }),
+            ..rust.clone()
+        };
+        assert_eq!(budgeted.highlight_query(), rust.highlight_query_default());
+        assert_eq!(budgeted.highlight_query_complexity(), Some(default));
+
+        // No query is within the budget
+        let budgeted = Language {
+            highlight_query_budget: Some(HighlightQueryBudget {
+                max_patterns: None,
+                max_captures: Some(1),
+            }),
+            ..rust
+        };
+        assert_eq!(budgeted.highlight_query(), None);
+        assert_eq!(budgeted.highlight_query_complexity(), None);
     }
 }
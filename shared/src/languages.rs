@@ -2,9 +2,11 @@ use std::collections::HashMap;
 
 use serde_json::json;
 
-use crate::language::{CargoLinkedTreesitterLanguage, GrammarConfigKind};
+use crate::language::{CargoLinkedTreesitterLanguage, GrammarConfigKind, IndentUnit};
 
-use super::language::{Command, GrammarConfig, Language, LanguageId, LspCommand};
+use super::language::{
+    Command, ContentGrammarConfig, GrammarConfig, Language, LanguageId, LspCommand,
+};
 
 fn to_vec(slice: &[&'static str]) -> Vec<String> {
     slice.iter().map(|s| s.to_string()).collect()
@@ -12,6 +14,7 @@ fn to_vec(slice: &[&'static str]) -> Vec<String> {
 
 pub fn languages() -> HashMap<String, Language> {
     [
+        ("asm", asm()),
         ("bash", bash()),
         ("fish", fish()),
         ("unison", unison()),
@@ -25,7 +28,10 @@ pub fn languages() -> HashMap<String, Language> {
         ("csv", csv()),
         ("diff", diff()),
         ("dockerfile", dockerfile()),
+        ("dotenv", dotenv()),
         ("elixir", elixir()),
+        ("ejs", ejs()),
+        ("erb", erb()),
         ("fsharp", fsharp()),
         ("gitattributes", gitattributes()),
         ("gitcommit", gitcommit()),
@@ -39,26 +45,32 @@ pub fn languages() -> HashMap<String, Language> {
         ("heex", heex()),
         ("html", html()),
         ("idris", idris()),
+        ("jinja", jinja()),
         ("haskell", haskell()),
         ("javascript", javascript()),
         ("javascriptreact", javascriptreact()),
         ("svelte", svelte()),
+        ("jsdoc", jsdoc()),
         ("json", json()),
         ("julia", julia()),
         ("just", just()),
         ("kiquickfix", kiquickfix()),
         ("lua", lua()),
+        ("makefile", makefile()),
         ("markdown", markdown()),
+        ("markdown_inline", markdown_inline()),
         ("nix", nix()),
         ("ocaml", ocaml()),
         ("ocaml_interface", ocaml_interface()),
         ("odin", odin()),
         ("dune", dune()),
         ("python", python()),
+        ("regex", regex()),
         ("rescript", rescript()),
         ("roc", roc()),
         ("ruby", ruby()),
         ("rust", rust()),
+        ("scss", scss()),
         ("sql", sql()),
         ("swift", swift()),
         ("typst", typst()),
@@ -66,6 +78,7 @@ pub fn languages() -> HashMap<String, Language> {
         ("tree_sitter_query", tree_sitter_query()),
         ("typescript", typescript()),
         ("typescriptreact", typescriptreact()),
+        ("vue", vue()),
         ("xml", xml()),
         ("yaml", yaml()),
         ("zig", zig()),
@@ -75,6 +88,24 @@ pub fn languages() -> HashMap<String, Language> {
     .collect()
 }
 
+fn asm() -> Language {
+    Language {
+        // Extensions are case-sensitive, so `.S`, which is preprocessed by `cpp`, is listed besides `.s`
+        extensions: to_vec(&["asm", "s", "S"]),
+        tree_sitter_grammar_config: Some(GrammarConfig {
+            id: "asm".to_string(),
+            kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Asm),
+            lazy_load: false,
+        }),
+        line_comment_prefix: Some(";".to_string()),
+        extra_line_comment_prefixes: to_vec(&["#"]),
+        ..Language::new()
+    }
+}
+
+/// The characters of paths and home directories, which are words in shell scripts.
+const SHELL_WORD_CHARS: &str = "./~";
+
 fn bash() -> Language {
     Language {
         extensions: to_vec(&["sh", "bash"]),
@@ -82,14 +113,17 @@ fn bash() -> Language {
         formatter: Some(Command::new("shfmt", &[".sh", ".bash"])),
         lsp_command: Some(LspCommand {
             command: Command::new("bash-language-server", &["start"]),
+            lsp_single_file_support: true,
             ..LspCommand::default()
         }),
         lsp_language_id: Some(LanguageId::new("bash")),
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "bash".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Bash),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
+        word_chars: Some(SHELL_WORD_CHARS.to_string()),
         ..Language::new()
     }
 }
@@ -106,8 +140,10 @@ fn fish() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "fish".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Fish),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
+        word_chars: Some(SHELL_WORD_CHARS.to_string()),
         ..Language::new()
     }
 }
@@ -124,7 +160,17 @@ fn c() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "c".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::C),
-        }),
+            lazy_load: false,
+        }),
+        // Headers are shared by C and C++
+        content_grammar_configs: vec![ContentGrammarConfig {
+            pattern: r"(?m)\btemplate\s*<|^\s*(class|namespace)\s+\w+".to_string(),
+            grammar: GrammarConfig {
+                id: "cpp".to_string(),
+                kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::CPP),
+                lazy_load: false,
+            },
+        }],
         line_comment_prefix: Some("//".to_string()),
         block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
         ..Language::new()
@@ -142,6 +188,7 @@ fn racket() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "racket".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Scheme),
+            lazy_load: false,
         }),
         line_comment_prefix: Some(";".to_string()),
         block_comment_affixes: Some(("#|".to_string(), "|#".to_string())),
@@ -156,6 +203,7 @@ fn scheme() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "scheme".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Scheme),
+            lazy_load: false,
         }),
         line_comment_prefix: Some(";".to_string()),
         block_comment_affixes: Some(("#|".to_string(), "|#".to_string())),
@@ -176,6 +224,7 @@ fn commonlisp() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "commonlisp".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Scheme),
+            lazy_load: false,
         }),
         line_comment_prefix: Some(";".to_string()),
         ..Language::new()
@@ -197,6 +246,7 @@ fn cpp() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "cpp".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::CPP),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("//".to_string()),
         block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
@@ -210,10 +260,13 @@ fn csv() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "csv".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/arnau/tree-sitter-csv".to_string(),
+                url: Some("https://github.com/arnau/tree-sitter-csv".to_string()),
                 commit: "main".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         ..Language::new()
     }
@@ -231,10 +284,13 @@ fn c_sharp() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "c_sharp".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/tree-sitter/tree-sitter-c-sharp".to_string(),
+                url: Some("https://github.com/tree-sitter/tree-sitter-c-sharp".to_string()),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
                 commit: "master".to_string(),
             },
+            lazy_load: false,
         }),
         line_comment_prefix: Some("//".to_string()),
         block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
@@ -249,6 +305,7 @@ fn css() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "css".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::CSS),
+            lazy_load: false,
         }),
         block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
         ..Language::new()
@@ -261,6 +318,7 @@ fn diff() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "diff".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Diff),
+            lazy_load: false,
         }),
         ..Language::new()
     }
@@ -272,16 +330,29 @@ fn dockerfile() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "dockerfile".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/camdencheek/tree-sitter-dockerfile".to_string(),
+                url: Some("https://github.com/camdencheek/tree-sitter-dockerfile".to_string()),
                 commit: "main".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
         ..Language::new()
     }
 }
 
+/// There is no grammar, but comments can still be toggled.
+fn dotenv() -> Language {
+    Language {
+        extensions: to_vec(&["env"]),
+        file_names: to_vec(&[".env"]),
+        line_comment_prefix: Some("#".to_string()),
+        ..Language::new()
+    }
+}
+
 fn elixir() -> Language {
     Language {
         extensions: to_vec(&["ex", "exs"]),
@@ -289,17 +360,51 @@ fn elixir() -> Language {
         lsp_command: Some(LspCommand {
             command: Command::new("elixir-ls", &[]),
             initialization_options: None,
+            ..LspCommand::default()
         }),
         lsp_language_id: Some(LanguageId::new("elixir")),
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "elixir".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Elixir),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
         ..Language::new()
     }
 }
 
+fn erb() -> Language {
+    Language {
+        // `Path::extension` of `index.html.erb` is `erb` as well
+        extensions: to_vec(&["erb"]),
+        lsp_language_id: Some(LanguageId::new("erb")),
+        tree_sitter_grammar_config: Some(GrammarConfig {
+            id: "embedded_template".to_string(),
+            kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::EmbeddedTemplate),
+            lazy_load: false,
+        }),
+        block_comment_affixes: Some(("<%#".to_string(), "%>".to_string())),
+        template_injections: vec![
+            ("content".to_string(), "html".to_string()),
+            ("code".to_string(), "ruby".to_string()),
+        ],
+        ..Language::new()
+    }
+}
+
+/// Embedded JavaScript templates, whose tags are like those of ERB.
+fn ejs() -> Language {
+    Language {
+        template_injections: vec![
+            ("content".to_string(), "html".to_string()),
+            ("code".to_string(), "javascript".to_string()),
+        ],
+        extensions: to_vec(&["ejs"]),
+        lsp_language_id: None,
+        ..erb()
+    }
+}
+
 fn fsharp() -> Language {
     Language {
         extensions: to_vec(&["fs", "fsi", "fsx", "fsscript"]),
@@ -314,10 +419,13 @@ fn fsharp() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "fsharp".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/ionide/tree-sitter-fsharp.git".to_string(),
+                url: Some("https://github.com/ionide/tree-sitter-fsharp.git".to_string()),
                 commit: "main".to_string(),
                 subpath: Some("fsharp".to_string()),
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         line_comment_prefix: Some("//".to_string()),
         block_comment_affixes: Some(("(*".to_string(), "*)".to_string())),
@@ -331,11 +439,15 @@ fn gitattributes() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "gitattributes".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/tree-sitter-grammars/tree-sitter-gitattributes"
-                    .to_string(),
+                url: Some(
+                    "https://github.com/tree-sitter-grammars/tree-sitter-gitattributes".to_string(),
+                ),
                 commit: "master".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
         ..Language::new()
@@ -348,10 +460,13 @@ fn gitcommit() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "gitcommit".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/gbprod/tree-sitter-gitcommit".to_string(),
+                url: Some("https://github.com/gbprod/tree-sitter-gitcommit".to_string()),
                 commit: "main".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
         ..Language::new()
@@ -364,10 +479,13 @@ fn gitconfig() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "git_config".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/the-mikedavis/tree-sitter-git-config".to_string(),
+                url: Some("https://github.com/the-mikedavis/tree-sitter-git-config".to_string()),
                 commit: "main".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
         ..Language::new()
@@ -380,10 +498,13 @@ fn gitignore() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "gitignore".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/shunsambongi/tree-sitter-gitignore".to_string(),
+                url: Some("https://github.com/shunsambongi/tree-sitter-gitignore".to_string()),
                 commit: "main".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
         ..Language::new()
@@ -396,10 +517,13 @@ fn gitrebase() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "git_rebase".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/the-mikedavis/tree-sitter-git-rebase".to_string(),
+                url: Some("https://github.com/the-mikedavis/tree-sitter-git-rebase".to_string()),
                 commit: "main".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
         ..Language::new()
@@ -418,6 +542,7 @@ fn gleam() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "gleam".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Gleam),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("//".to_string()),
         ..Language::new()
@@ -436,8 +561,11 @@ fn go() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "go".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Go),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("//".to_string()),
+        // Build constraints and compiler directives
+        comment_directive_prefixes: to_vec(&["//go:", "// +build"]),
         block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
         ..Language::new()
     }
@@ -452,11 +580,13 @@ fn graphql() -> Language {
             initialization_options: Some(
                 json! {r#"{ "graphql-config.load.legacy": true }"#.to_string()},
             ),
+            ..LspCommand::default()
         }),
         lsp_language_id: Some(LanguageId::new("graphql")),
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "graphql".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Graphql),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
         ..Language::new()
@@ -469,10 +599,13 @@ fn hare() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "hare".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/tree-sitter-grammars/tree-sitter-hare".to_string(),
+                url: Some("https://github.com/tree-sitter-grammars/tree-sitter-hare".to_string()),
                 commit: "master".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         ..Language::new()
     }
@@ -488,11 +621,13 @@ fn heex() -> Language {
         lsp_command: Some(LspCommand {
             command: Command::new("elixir-ls", &[]),
             initialization_options: None,
+            ..LspCommand::default()
         }),
         lsp_language_id: Some(LanguageId::new("heex")),
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "heex".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Heex),
+            lazy_load: false,
         }),
         block_comment_affixes: Some(("<!--".to_string(), "-->".to_string())),
         ..Language::new()
@@ -501,7 +636,8 @@ fn heex() -> Language {
 
 fn html() -> Language {
     Language {
-        extensions: to_vec(&["htm", "html", "svg"]),
+        // SVG is XML, see `xml()`
+        extensions: to_vec(&["htm", "html"]),
         formatter: Some(Command::new("prettierd", &[".html"])),
         lsp_command: Some(LspCommand {
             command: Command::new("emmet-language-server", &["--stdio"]),
@@ -511,6 +647,7 @@ fn html() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "html".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::HTML),
+            lazy_load: false,
         }),
         block_comment_affixes: Some(("<!--".to_string(), "-->".to_string())),
         ..Language::new()
@@ -528,10 +665,13 @@ fn idris() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "idris".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/kayhide/tree-sitter-idris".to_string(),
+                url: Some("https://github.com/kayhide/tree-sitter-idris".to_string()),
                 commit: "main".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         ..Language::new()
     }
@@ -548,9 +688,33 @@ fn haskell() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "haskell".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Haskell),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("--".to_string()),
         block_comment_affixes: Some(("{-".to_string(), "-}".to_string())),
+        block_comment_nestable: true,
+        whitespace_sensitive: true,
+        ..Language::new()
+    }
+}
+
+/// Jinja, Django and Tera templates, which share the `{{ }}` and `{% %}` tags.
+fn jinja() -> Language {
+    Language {
+        extensions: to_vec(&["j2", "jinja", "jinja2", "djhtml", "html.tera"]),
+        tree_sitter_grammar_config: Some(GrammarConfig {
+            id: "htmldjango".to_string(),
+            kind: GrammarConfigKind::FromSource {
+                url: Some("https://github.com/interdependence/tree-sitter-htmldjango".to_string()),
+                commit: "master".to_string(),
+                subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
+            },
+            lazy_load: false,
+        }),
+        block_comment_affixes: Some(("{#".to_string(), "#}".to_string())),
+        template_injections: vec![("content".to_string(), "html".to_string())],
         ..Language::new()
     }
 }
@@ -567,6 +731,7 @@ fn javascript() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "javascript".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Javascript),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("//".to_string()),
         block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
@@ -586,6 +751,7 @@ fn javascriptreact() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "jsx".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::JSX),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("//".to_string()),
         block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
@@ -604,6 +770,7 @@ fn svelte() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "svelte".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Svelte),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("//".to_string()),
         block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
@@ -611,6 +778,24 @@ fn svelte() -> Language {
     }
 }
 
+/// Only injected into the documentation comments of JavaScript and TypeScript.
+fn jsdoc() -> Language {
+    Language {
+        tree_sitter_grammar_config: Some(GrammarConfig {
+            id: "jsdoc".to_string(),
+            kind: GrammarConfigKind::FromSource {
+                url: Some("https://github.com/tree-sitter/tree-sitter-jsdoc".to_string()),
+                commit: "master".to_string(),
+                subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
+            },
+            lazy_load: false,
+        }),
+        ..Language::new()
+    }
+}
+
 fn json() -> Language {
     Language {
         extensions: to_vec(&["json"]),
@@ -618,7 +803,9 @@ fn json() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "json".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::JSON),
+            lazy_load: false,
         }),
+        grammar_self_test_sample: Some("{\"a\": [1, true, null]}\n".to_string()),
         ..Language::new()
     }
 }
@@ -643,6 +830,7 @@ fn julia() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "julia".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Julia),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
         block_comment_affixes: Some(("#=".to_string(), "=#".to_string())),
@@ -656,10 +844,13 @@ fn just() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "just".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/IndianBoy42/tree-sitter-just".to_string(),
+                url: Some("https://github.com/IndianBoy42/tree-sitter-just".to_string()),
                 commit: "main".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
         ..Language::new()
@@ -673,6 +864,7 @@ fn kiquickfix() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "ki_quickfix".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::KiQuickfix),
+            lazy_load: false,
         }),
         ..Language::new()
     }
@@ -690,6 +882,7 @@ fn lua() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "lua".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Lua),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("--".to_string()),
         block_comment_affixes: Some(("--[[".to_string(), "]]".to_string())),
@@ -697,6 +890,23 @@ fn lua() -> Language {
     }
 }
 
+fn makefile() -> Language {
+    Language {
+        extensions: to_vec(&["mk"]),
+        file_names: to_vec(&["Makefile", "makefile", "GNUmakefile"]),
+        tree_sitter_grammar_config: Some(GrammarConfig {
+            id: "make".to_string(),
+            kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Makefile),
+            lazy_load: false,
+        }),
+        line_comment_prefix: Some("#".to_string()),
+        // Recipe lines must be indented with literal tabs
+        indent_unit: Some(IndentUnit::Tab),
+        recipe_indent_must_be_tab: true,
+        ..Language::new()
+    }
+}
+
 fn markdown() -> Language {
     Language {
         extensions: to_vec(&["md", "mdx"]),
@@ -709,12 +919,25 @@ fn markdown() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "markdown".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Markdown),
+            lazy_load: false,
         }),
         block_comment_affixes: Some(("<!--".to_string(), "-->".to_string())),
         ..Language::new()
     }
 }
 
+/// Only used for injections into the paragraphs, headings, etc. of Markdown.
+fn markdown_inline() -> Language {
+    Language {
+        tree_sitter_grammar_config: Some(GrammarConfig {
+            id: "markdown_inline".to_string(),
+            kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::MarkdownInline),
+            lazy_load: false,
+        }),
+        ..Language::new()
+    }
+}
+
 fn nix() -> Language {
     Language {
         formatter: Some(Command::new("nixfmt", &[])),
@@ -724,9 +947,11 @@ fn nix() -> Language {
             ..LspCommand::default()
         }),
         lsp_language_id: Some(LanguageId::new("nix")),
+        lsp_root_markers: to_vec(&["flake.nix", "default.nix", "shell.nix"]),
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "nix".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Nix),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
         block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
@@ -746,8 +971,10 @@ fn ocaml() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "ocaml".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::OCaml),
+            lazy_load: false,
         }),
         block_comment_affixes: Some(("(*".to_string(), "*)".to_string())),
+        block_comment_nestable: true,
         ..Language::new()
     }
 }
@@ -764,8 +991,10 @@ fn ocaml_interface() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "ocaml_interface".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::OCamlInterface),
+            lazy_load: false,
         }),
         block_comment_affixes: Some(("(*".to_string(), "*)".to_string())),
+        block_comment_nestable: true,
         ..Language::new()
     }
 }
@@ -777,10 +1006,13 @@ fn odin() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "odin".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/tree-sitter-grammars/tree-sitter-odin".to_string(),
+                url: Some("https://github.com/tree-sitter-grammars/tree-sitter-odin".to_string()),
                 commit: "master".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         line_comment_prefix: Some("//".to_string()),
         block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
@@ -795,6 +1027,7 @@ fn dune() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "dune".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Scheme),
+            lazy_load: false,
         }),
         line_comment_prefix: Some(";".to_string()),
         ..Language::new()
@@ -813,8 +1046,23 @@ fn python() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "python".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Python),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
+        whitespace_sensitive: true,
+        grammar_self_test_sample: Some("def main():\n    pass\n".to_string()),
+        ..Language::new()
+    }
+}
+
+/// Only used for injections, for example, into JavaScript regex literals.
+fn regex() -> Language {
+    Language {
+        tree_sitter_grammar_config: Some(GrammarConfig {
+            id: "regex".to_string(),
+            kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Regex),
+            lazy_load: false,
+        }),
         ..Language::new()
     }
 }
@@ -834,10 +1082,13 @@ fn rescript() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "rescript".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/rescript-lang/tree-sitter-rescript".to_string(),
+                url: Some("https://github.com/rescript-lang/tree-sitter-rescript".to_string()),
                 commit: "main".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         line_comment_prefix: Some("//".to_string()),
         block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
@@ -861,6 +1112,7 @@ fn ruby() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "ruby".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Ruby),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
         ..Language::new()
@@ -876,10 +1128,13 @@ fn roc() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "roc".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/faldor20/tree-sitter-roc".to_string(),
+                url: Some("https://github.com/faldor20/tree-sitter-roc".to_string()),
                 commit: "master".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
         ..Language::new()
@@ -898,6 +1153,32 @@ fn rust() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "rust".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Rust),
+            lazy_load: false,
+        }),
+        line_comment_prefix: Some("//".to_string()),
+        doc_comment_prefix: Some("///".to_string()),
+        block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
+        block_comment_nestable: true,
+        grammar_self_test_sample: Some("fn main() {}\n".to_string()),
+        ..Language::new()
+    }
+}
+
+fn scss() -> Language {
+    Language {
+        extensions: to_vec(&["scss"]),
+        formatter: Some(Command::new("prettierd", &[".scss"])),
+        lsp_language_id: Some(LanguageId::new("scss")),
+        tree_sitter_grammar_config: Some(GrammarConfig {
+            id: "scss".to_string(),
+            kind: GrammarConfigKind::FromSource {
+                url: Some("https://github.com/tree-sitter-grammars/tree-sitter-scss".to_string()),
+                commit: "eb69c8f55ecc17235f43358c65ec644578554f92".to_string(),
+                subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
+            },
+            lazy_load: false,
         }),
         line_comment_prefix: Some("//".to_string()),
         block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
@@ -912,10 +1193,13 @@ fn sql() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "sql".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/DerekStride/tree-sitter-sql".to_string(),
+                url: Some("https://github.com/DerekStride/tree-sitter-sql".to_string()),
                 commit: "25f94f998de79bae9df28add9782f9ea6ea0e2b8".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         line_comment_prefix: Some("--".to_string()),
         block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
@@ -932,12 +1216,15 @@ fn swift() -> Language {
             ..LspCommand::default()
         }),
         lsp_language_id: Some(LanguageId::new("swift")),
+        lsp_root_markers: to_vec(&["Package.swift", "*.xcodeproj"]),
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "swift".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Swift),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("//".to_string()),
         block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
+        block_comment_nestable: true,
         ..Language::new()
     }
 }
@@ -954,10 +1241,13 @@ fn typst() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "typst".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/uben0/tree-sitter-typst".to_string(),
+                url: Some("https://github.com/uben0/tree-sitter-typst".to_string()),
                 commit: "master".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
         ..Language::new()
@@ -970,6 +1260,7 @@ fn toml() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "toml".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Toml),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
         ..Language::new()
@@ -982,10 +1273,13 @@ fn tree_sitter_query() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "tsq".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/tree-sitter/tree-sitter-tsq".to_string(),
+                url: Some("https://github.com/tree-sitter/tree-sitter-tsq".to_string()),
                 commit: "main".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         line_comment_prefix: Some(";".to_string()),
         ..Language::new()
@@ -1004,6 +1298,7 @@ fn typescript() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "typescript".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Typescript),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("//".to_string()),
         block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
@@ -1011,21 +1306,46 @@ fn typescript() -> Language {
     }
 }
 
+/// Inherits everything else, e.g. the LSP server and comments, from `typescript`.
 fn typescriptreact() -> Language {
     Language {
         extensions: to_vec(&["tsx"]),
         formatter: Some(Command::new("prettierd", &[".tsx"])),
-        lsp_command: Some(LspCommand {
-            command: Command::new("typescript-language-server", &["--stdio"]),
-            ..LspCommand::default()
-        }),
         lsp_language_id: Some(LanguageId::new("typescriptreact")),
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "tsx".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::TSX),
+            lazy_load: false,
         }),
-        line_comment_prefix: Some("//".to_string()),
-        block_comment_affixes: Some(("/*".to_string(), "*/".to_string())),
+        ..typescript()
+    }
+}
+
+fn vue() -> Language {
+    Language {
+        extensions: to_vec(&["vue"]),
+        formatter: Some(Command::new("prettierd", &[".vue"])),
+        lsp_command: Some(LspCommand {
+            command: Command::new("vue-language-server", &["--stdio"]),
+            ..LspCommand::default()
+        }),
+        lsp_language_id: Some(LanguageId::new("vue")),
+        // There is no `tree-sitter-vue` crate that is compatible with the
+        // `tree-sitter` version we use, so it has to be built from source.
+        tree_sitter_grammar_config: Some(GrammarConfig {
+            id: "vue".to_string(),
+            kind: GrammarConfigKind::FromSource {
+                url: Some("https://github.com/tree-sitter-grammars/tree-sitter-vue".to_string()),
+                commit: "main".to_string(),
+                subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
+            },
+            lazy_load: false,
+        }),
+        // The `<script>` and `<style>` blocks are commented by their injected languages,
+        // so HTML-style comment is only meant for the `<template>` block.
+        block_comment_affixes: Some(("<!--".to_string(), "-->".to_string())),
         ..Language::new()
     }
 }
@@ -1041,21 +1361,28 @@ fn unison() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "unison".to_string(),
             kind: GrammarConfigKind::FromSource {
-                url: "https://github.com/kylegoetz/tree-sitter-unison".to_string(),
+                url: Some("https://github.com/kylegoetz/tree-sitter-unison".to_string()),
                 commit: "master".to_string(),
                 subpath: None,
+                experimental: false,
+                compile_flags: Vec::new(),
             },
+            lazy_load: false,
         }),
         ..Language::new()
     }
 }
 
+/// Unlike HTML, XML is case-sensitive and has no void elements (e.g. `<br>`),
+/// so XML-based formats such as SVG are parsed with the XML grammar.
+/// To treat SVG as HTML instead, move `svg` to the extensions of `html` in the config.
 fn xml() -> Language {
     Language {
-        extensions: to_vec(&["xml", "xaml", "axaml"]),
+        extensions: to_vec(&["xml", "xaml", "axaml", "svg", "xsd", "xsl", "xslt"]),
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "xml".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::XML),
+            lazy_load: false,
         }),
         block_comment_affixes: Some(("<!--".to_string(), "-->".to_string())),
         ..Language::new()
@@ -1068,8 +1395,10 @@ fn yaml() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "yaml".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::YAML),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("#".to_string()),
+        whitespace_sensitive: true,
         ..Language::new()
     }
 }
@@ -1086,6 +1415,7 @@ fn zig() -> Language {
         tree_sitter_grammar_config: Some(GrammarConfig {
             id: "zig".to_string(),
             kind: GrammarConfigKind::CargoLinked(CargoLinkedTreesitterLanguage::Zig),
+            lazy_load: false,
         }),
         line_comment_prefix: Some("//".to_string()),
         ..Language::new()
@@ -1094,6 +1424,33 @@ fn zig() -> Language {
 
 #[cfg(test)]
 mod test {
+    #[test]
+    fn zig_ships_zig_fmt_formatter() {
+        let zig = super::languages().remove("zig").unwrap();
+        assert_eq!(zig.formatter().unwrap().command_string(), "zig fmt --stdin");
+    }
+
+    #[test]
+    fn gleam_ships_formatter_and_lsp() {
+        let gleam = super::languages().remove("gleam").unwrap();
+        assert_eq!(
+            gleam.formatter().unwrap().command_string(),
+            "gleam format --stdin"
+        );
+        assert_eq!(
+            gleam.lsp_process_command().unwrap().to_string(),
+            "gleam lsp"
+        );
+        assert_eq!(gleam.line_comment_prefix().as_deref(), Some("//"));
+    }
+
+    #[test]
+    fn built_in_grammars_pass_self_test() {
+        for (name, language) in super::languages() {
+            assert_eq!(language.self_test(), Ok(()), "{name}");
+        }
+    }
+
     #[test]
     fn test_languages_match_nvim_treesitter_languages() {
         const MISSING_NVIM_HIGHLIGHTS: &[&str] = &["dune", "ki_quickfix", "tsq"];
@@ -5,4 +5,8 @@ pub mod icons;
 pub mod language;
 pub mod languages;
 pub mod process_command;
+pub mod query_load_metrics;
+pub(crate) mod ts_brackets_query;
 pub mod ts_highlight_query;
+pub mod ts_injection_query;
+pub(crate) mod ts_locals_query;
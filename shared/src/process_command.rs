@@ -31,6 +31,11 @@ impl ProcessCommand {
         }
     }
 
+    /// Whether the command can be found in `PATH`.
+    pub fn is_available(&self) -> bool {
+        which::which(&self.command).is_ok()
+    }
+
     pub fn spawn(&self) -> SpawnCommandResult {
         log::info!("ProcessCommand::spawn {:?} {:?}", self.command, self.args);
         if which::which(&self.command).is_err() {
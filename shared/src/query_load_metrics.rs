@@ -0,0 +1,58 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+
+/// Disabled by default, because recording the metrics compiles the queries
+/// that are not compiled otherwise, such as the injection query.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static METRICS: Lazy<Mutex<HashMap<String, QueryLoadMetrics>>> = Lazy::new(Default::default);
+
+/// How long the queries of a grammar took to compile when they were last loaded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryLoadMetrics {
+    pub highlight: Option<Duration>,
+    pub injections: Option<Duration>,
+    pub locals: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum QueryKind {
+    Highlight,
+    Injections,
+    Locals,
+}
+
+/// Enable this to profile which grammar queries are slow to compile,
+/// see `Language::last_query_load_metrics`.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed)
+}
+
+pub(crate) fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub(crate) fn record(grammar_id: &str, kind: QueryKind, duration: Duration) {
+    let Ok(mut metrics) = METRICS.lock() else {
+        return;
+    };
+    let metrics = metrics.entry(grammar_id.to_string()).or_default();
+    let field = match kind {
+        QueryKind::Highlight => &mut metrics.highlight,
+        QueryKind::Injections => &mut metrics.injections,
+        QueryKind::Locals => &mut metrics.locals,
+    };
+    *field = Some(duration);
+}
+
+pub(crate) fn get(grammar_id: &str) -> Option<QueryLoadMetrics> {
+    METRICS.lock().ok()?.get(grammar_id).cloned()
+}
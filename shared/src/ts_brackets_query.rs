@@ -0,0 +1,26 @@
+/// Get the brackets query of the given grammar.
+///
+/// A brackets query pairs the `@open` and `@close` delimiters of a node,
+/// so that matching brackets respects the syntax, for example, by ignoring a `)` within a string.
+pub(crate) fn get_brackets_query(grammar_id: &str) -> Option<&'static str> {
+    match grammar_id {
+        "json" => Some(include_str!("../queries/json-brackets.scm")),
+        "rust" => Some(include_str!("../queries/rust-brackets.scm")),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test_ts_brackets_query {
+    #[test]
+    fn brackets_queries_compile() {
+        for (_, language) in crate::languages::languages() {
+            let (Some(query), Some(tree_sitter_language)) =
+                (language.brackets_query(), language.tree_sitter_language())
+            else {
+                continue;
+            };
+            tree_sitter::Query::new(&tree_sitter_language, &query).unwrap();
+        }
+    }
+}
@@ -17,6 +17,15 @@ pub(crate) fn get_highlight_query(language_id: &str) -> Option<GetHighlightQuery
     Some(GetHighlightQueryResult { query: result })
 }
 
+/// Get the highlight query that is specific to a file of the given grammar, e.g. `Cargo.toml`,
+/// which is placed before the generic highlight query so that its captures take precedence.
+pub(crate) fn get_file_highlight_query(grammar_id: &str, file_name: &str) -> Option<&'static str> {
+    match (grammar_id, file_name) {
+        ("toml", "Cargo.toml") => Some(include_str!("../queries/cargo-toml-highlights.scm")),
+        _ => None,
+    }
+}
+
 /// This function extracts the parent of a Tree-sitter highlight query parents,
 /// based on the format defined by `nvim-treesitter`.
 ///
@@ -0,0 +1,58 @@
+/// Get the injection query of the given grammar.
+///
+/// These queries are maintained by us instead of being taken from nvim-treesitter,
+/// because most nvim-treesitter injection queries rely on Neovim-specific directives
+/// (e.g. `#offset!`) which are not supported by the `tree-sitter-highlight` crate.
+pub(crate) fn get_injection_query(grammar_id: &str) -> Option<&'static str> {
+    match grammar_id {
+        "c" | "cpp" => Some(include_str!("../queries/c-injections.scm")),
+        "dockerfile" => Some(include_str!("../queries/dockerfile-injections.scm")),
+        "javascript" | "jsx" | "typescript" | "tsx" => {
+            Some(include_str!("../queries/javascript-injections.scm"))
+        }
+        "markdown" => Some(include_str!("../queries/markdown-injections.scm")),
+        "nix" => Some(include_str!("../queries/nix-injections.scm")),
+        "ruby" => Some(include_str!("../queries/ruby-injections.scm")),
+        "rust" => Some(include_str!("../queries/rust-injections.scm")),
+        "vue" => Some(include_str!("../queries/vue-injections.scm")),
+        "yaml" => Some(include_str!("../queries/yaml-injections.scm")),
+        "zig" => Some(include_str!("../queries/zig-injections.scm")),
+        _ => None,
+    }
+}
+
+/// Normalize the language name obtained from an injection query,
+/// for example, both `sh` and `shell` refer to the `bash` language.
+pub fn normalize_injection_language(name: &str) -> String {
+    let name = name.trim().to_lowercase();
+    match name.as_str() {
+        "sh" | "shell" | "zsh" => "bash".to_string(),
+        "luajit" => "lua".to_string(),
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod test_ts_injection_query {
+    use super::*;
+
+    #[test]
+    fn injection_queries_compile() {
+        for (_, language) in crate::languages::languages() {
+            let (Some(query), Some(tree_sitter_language)) =
+                (language.injection_query(), language.tree_sitter_language())
+            else {
+                continue;
+            };
+            tree_sitter::Query::new(&tree_sitter_language, &query).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_normalize_injection_language() {
+        assert_eq!(normalize_injection_language("sh"), "bash");
+        assert_eq!(normalize_injection_language("Shell"), "bash");
+        assert_eq!(normalize_injection_language("luajit"), "lua");
+        assert_eq!(normalize_injection_language("rust"), "rust");
+    }
+}
@@ -0,0 +1,26 @@
+/// Get the locals query of the given grammar.
+///
+/// A locals query allows a reference to be highlighted like its definition,
+/// for example, a parameter that shadows a top-level declaration.
+pub(crate) fn get_locals_query(grammar_id: &str) -> Option<&'static str> {
+    match grammar_id {
+        "haskell" => Some(include_str!("../queries/haskell-locals.scm")),
+        "yaml" => Some(include_str!("../queries/yaml-locals.scm")),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test_ts_locals_query {
+    #[test]
+    fn locals_queries_compile() {
+        for (_, language) in crate::languages::languages() {
+            let (Some(query), Some(tree_sitter_language)) =
+                (language.locals_query(), language.tree_sitter_language())
+            else {
+                continue;
+            };
+            tree_sitter::Query::new(&tree_sitter_language, query).unwrap();
+        }
+    }
+}
@@ -207,6 +207,14 @@ impl<T: Frontend> App<T> {
         } else {
             None
         };
+        {
+            let sender = sender.clone();
+            shared::language::on_lazy_grammar_loaded(move |grammar_id| {
+                let _ = sender.send(AppMessage::LazyGrammarLoaded {
+                    grammar_id: grammar_id.to_string(),
+                });
+            });
+        }
         let mut app = App {
             context: Context::new(
                 working_directory.clone(),
@@ -335,6 +343,10 @@ impl<T: Frontend> App<T> {
                 self.handle_file_watcher_event(event)?;
                 Ok(false)
             }
+            AppMessage::LazyGrammarLoaded { grammar_id } => {
+                self.handle_lazy_grammar_loaded(&grammar_id)?;
+                Ok(false)
+            }
             AppMessage::NotifyError(error) => {
                 self.show_global_info(Info::new("App Error".to_string(), format!("{error:#?}")));
                 Ok(false)
@@ -884,7 +896,7 @@ impl<T: Frontend> App<T> {
                     self.request_syntax_highlight(
                         component_id,
                         batch_id,
-                        language,
+                        *language,
                         content.clone(),
                     )?;
                 }
@@ -2004,6 +2016,9 @@ impl<T: Frontend> App<T> {
         language: Language,
         content: String,
     ) -> anyhow::Result<()> {
+        if content.len() > language.max_grammar_file_size() {
+            return Ok(());
+        }
         if let Some(sender) = &self.syntax_highlight_request_sender {
             sender.send(SyntaxHighlightRequest {
                 component_id,
@@ -3133,6 +3148,34 @@ Conflict markers will be injected in areas that cannot be merged gracefully."
         Ok(())
     }
 
+    /// The buffers of the lazily loaded grammar are reparsed, because they were shown as plain text,
+    /// and every buffer is highlighted again, because the grammar might be injected in it.
+    fn handle_lazy_grammar_loaded(&mut self, grammar_id: &str) -> anyhow::Result<()> {
+        crate::config::invalidate_grammar_caches([grammar_id.to_string()].into());
+        for editor in self.layout.background_suggestive_editors() {
+            let mut editor = editor.borrow_mut();
+            let component_id = editor.id();
+            let mut buffer = editor.editor_mut().buffer_mut();
+            let Some(language) = buffer.language() else {
+                continue;
+            };
+            let content = buffer.content();
+            if language
+                .tree_sitter_grammar_config_for(&content)
+                .is_some_and(|config| config.id == grammar_id)
+            {
+                buffer.reload_tree_sitter_language();
+            }
+            self.request_syntax_highlight(
+                component_id,
+                buffer.batch_id().clone(),
+                language,
+                content,
+            )?;
+        }
+        Ok(())
+    }
+
     fn send_file_watcher_input(&self, input: FileWatcherInput) {
         if let Some(sender) = self.file_watcher_input_sender.as_ref() {
             if let Err(error) = sender.send(input) {
@@ -3445,7 +3488,7 @@ pub enum Dispatch {
         batch_id: SyntaxHighlightRequestBatchId,
         path: Option<CanonicalizedPath>,
         content: String,
-        language: Option<Language>,
+        language: Option<Box<Language>>,
     },
     DocumentDidSave {
         path: CanonicalizedPath,
@@ -3725,6 +3768,10 @@ pub enum AppMessage {
     ExternalDispatch(Box<Dispatch>),
     NucleoTickDebounced,
     FileWatcherEvent(FileWatcherEvent),
+    /// See `shared::language::on_lazy_grammar_loaded`.
+    LazyGrammarLoaded {
+        grammar_id: String,
+    },
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
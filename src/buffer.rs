@@ -47,6 +47,8 @@ pub struct Buffer {
     path: Option<CanonicalizedPath>,
     highlighted_spans: HighlightedSpans,
     diagnostics: Vec<Diagnostic>,
+    /// The `content_warnings` of the language, which are updated whenever the content changes.
+    content_warnings: Vec<Diagnostic>,
     decorations: Vec<Decoration>,
     selection_set_history: History<SelectionSet>,
     dirty: bool,
@@ -100,6 +102,7 @@ impl Buffer {
             highlighted_spans: HighlightedSpans::default(),
             decorations: Vec::new(),
             diagnostics: Vec::new(),
+            content_warnings: Vec::new(),
             selection_set_history: History::new(),
             dirty: false,
             owner: BufferOwner::System,
@@ -200,8 +203,25 @@ impl Buffer {
             .collect()
     }
 
+    /// The diagnostics of the LSP server, followed by the `content_warnings` of the language.
     pub fn diagnostics(&self) -> Vec<Diagnostic> {
-        self.diagnostics.clone()
+        self.diagnostics
+            .iter()
+            .chain(&self.content_warnings)
+            .cloned()
+            .collect()
+    }
+
+    fn update_content_warnings(&mut self) {
+        let content_warnings = self
+            .language
+            .as_ref()
+            .map(|language| language.content_warnings(self.rope.lines().map(String::from)))
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|warning| Diagnostic::try_from(self, warning).ok())
+            .collect();
+        self.content_warnings = content_warnings;
     }
 
     pub fn words(&self) -> Vec<String> {
@@ -337,7 +357,8 @@ impl Buffer {
 
     pub fn update(&mut self, text: &str) {
         (self.rope, self.tree) = Self::get_rope_and_tree(self.treesitter_language.clone(), text);
-        self.flag_as_modified()
+        self.flag_as_modified();
+        self.update_content_warnings()
     }
 
     pub fn update_path(&mut self, path: CanonicalizedPath) {
@@ -639,6 +660,7 @@ impl Buffer {
         self.rope
             .try_insert(edit.range.start.0, edit.new.to_string().as_str())?;
         self.flag_as_modified();
+        self.update_content_warnings();
 
         // Update all the non-positional spans
         self.diagnostics.retain_mut(|diagnostic| {
@@ -677,8 +699,7 @@ impl Buffer {
     pub fn from_path(path: &CanonicalizedPath, enable_tree_sitter: bool) -> anyhow::Result<Buffer> {
         let content = path.read()?;
         let language = if enable_tree_sitter {
-            crate::config::from_path(path)
-                .or_else(|| crate::config::from_content_directive(&content))
+            crate::config::from_path_or_content_directive(path, &content)
         } else {
             None
         };
@@ -686,18 +707,30 @@ impl Buffer {
         let mut buffer = Buffer::new(
             language
                 .as_ref()
-                .and_then(|language| language.tree_sitter_language()),
+                .and_then(|language| language.tree_sitter_language_for(&content)),
             &content,
         );
 
         buffer.path = Some(path.clone());
         buffer.language = language;
+        buffer.update_content_warnings();
 
         buffer.last_synced_time = path.last_modified_time().ok();
 
         Ok(buffer)
     }
 
+    /// Resolve the tree-sitter language again and reparse,
+    /// for example, after the lazily loaded grammar of this buffer is built.
+    pub fn reload_tree_sitter_language(&mut self) {
+        let content = self.content();
+        self.treesitter_language = self
+            .language
+            .as_ref()
+            .and_then(|language| language.tree_sitter_language_for(&content));
+        (_, self.tree) = Self::get_rope_and_tree(self.treesitter_language.clone(), &content);
+    }
+
     pub fn reparse_tree(&mut self) -> anyhow::Result<()> {
         let mut parser = tree_sitter::Parser::new();
         if let Some(tree) = self.tree.as_ref() {
@@ -709,6 +742,12 @@ impl Buffer {
 
     pub fn get_formatted_content(&self) -> Option<String> {
         if let Some(content) = self.language.as_ref().and_then(|language| {
+            if let Some(path) = &self.path {
+                if !language.should_format(path.as_ref()) {
+                    log::info!("[FORMAT]: {path:?} is ignored");
+                    return None;
+                }
+            }
             language.formatter().map(|formatter| {
                 log::info!("[FORMAT]: {}", formatter.command_string());
                 formatter.format(&self.rope.to_string())
@@ -827,6 +866,7 @@ impl Buffer {
 
     pub fn set_language(&mut self, language: Language) -> anyhow::Result<()> {
         self.language = Some(language);
+        self.update_content_warnings();
         self.reparse_tree()
     }
 
@@ -1249,6 +1289,25 @@ mod test_buffer {
         pretty_assertions::assert_eq!(actual, expected)
     }
 
+    #[test]
+    fn makefile_recipe_lines_indented_with_spaces_are_diagnostics() -> anyhow::Result<()> {
+        let makefile = crate::config::from_extension("mk").unwrap();
+        let mut buffer = Buffer::new(makefile.tree_sitter_language(), "build:\n    cc main.c\n");
+        buffer.set_language(makefile)?;
+        let diagnostics = buffer.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].severity,
+            Some(lsp_types::DiagnosticSeverity::WARNING)
+        );
+        assert_eq!(buffer.slice(&diagnostics[0].range)?.to_string(), "    ");
+
+        // The warnings are updated when the content changes
+        buffer.update("build:\n\tcc main.c\n");
+        assert!(buffer.diagnostics().is_empty());
+        Ok(())
+    }
+
     #[test]
     fn get_parent_lines_2() {
         let buffer = Buffer::new(
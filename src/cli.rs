@@ -209,10 +209,13 @@ pub fn get_version() -> String {
 use grammar::grammar::GrammarConfiguration;
 
 pub fn grammar_configs() -> Vec<GrammarConfiguration> {
-    crate::config::AppConfig::singleton()
-        .languages()
+    let config = crate::config::AppConfig::singleton();
+    crate::config::languages()
         .iter()
-        .flat_map(|(_, language)| language.tree_sitter_grammar_config())
+        .filter(|(_, language)| !language.lazy_loads_grammar())
+        .flat_map(|(_, language)| {
+            language.tree_sitter_grammar_config(config.grammar_url_template())
+        })
         .collect()
 }
 pub fn build_grammars() {
@@ -1540,7 +1540,7 @@ impl Editor {
             batch_id: self.buffer().batch_id().clone(),
             path: self.buffer().path(),
             content: self.buffer().rope().to_string(),
-            language: self.buffer().language(),
+            language: self.buffer().language().map(Box::new),
         }]
         .into_iter()
         .collect_vec()
@@ -4060,25 +4060,35 @@ impl Editor {
     }
 
     fn toggle_line_comment(&mut self, context: &Context) -> anyhow::Result<Dispatches> {
-        let Some(prefix) = self
+        let Some(language) = self
             .buffer()
             .language()
-            .and_then(|langugae| langugae.line_comment_prefix())
+            .filter(|language| language.line_comment_prefix().is_some())
         else {
             return Ok(Default::default());
         };
-        self.transform_selection(Transformation::ToggleLineComment { prefix }, context)
+        self.transform_selection(
+            Transformation::ToggleLineComment {
+                language: Box::new(language),
+            },
+            context,
+        )
     }
 
     fn toggle_block_comment(&mut self, context: &Context) -> anyhow::Result<Dispatches> {
-        let Some((open, close)) = self
+        let Some(language) = self
             .buffer()
             .language()
-            .and_then(|langugae| langugae.block_comment_affixes())
+            .filter(|language| language.block_comment_affixes().is_some())
         else {
             return Ok(Default::default());
         };
-        self.transform_selection(Transformation::ToggleBlockComment { open, close }, context)
+        self.transform_selection(
+            Transformation::ToggleBlockComment {
+                language: Box::new(language),
+            },
+            context,
+        )
     }
 
     fn handle_movement_with_prior_change(
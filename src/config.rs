@@ -1,5 +1,12 @@
 use std::borrow::Cow;
-use std::{collections::HashMap, io::Read, path::PathBuf};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+    path::PathBuf,
+};
 
 use itertools::Itertools;
 use regex::Regex;
@@ -10,11 +17,12 @@ use crate::scripting::{Keybinding, Script};
 use crate::themes::Theme;
 use figment::providers;
 use figment::providers::Format;
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
 use schemars::{JsonSchema, Schema, SchemaGenerator};
 use serde::{Deserialize, Serialize};
 use shared::canonicalized_path::CanonicalizedPath;
 use shared::language::{self, Language};
+use shared::process_command::ProcessCommand;
 
 #[derive(Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
@@ -24,6 +32,15 @@ pub struct AppConfig {
     theme: ConfigTheme,
     status_lines: Vec<StatusLine>,
     leader_keymap: LeaderKeymap,
+    /// The maximum levels of nested language injections to be highlighted,
+    /// for example, Markdown → HTML → JavaScript is 2 levels.
+    ///
+    /// Regions nested deeper than this are not highlighted with their injected language.
+    max_injection_depth: usize,
+    /// The URL of `FromSource` grammars that do not specify `url`,
+    /// where `{id}` is substituted by the grammar ID.
+    #[schemars(example = "https://github.com/tree-sitter/tree-sitter-{id}")]
+    grammar_url_template: Option<String>,
 }
 
 /// The leader keymap is a 3x10 matrix representing three rows of 10 columns.
@@ -129,10 +146,13 @@ impl AppConfig {
     }
 
     pub fn load_from_current_directory() -> anyhow::Result<Self> {
-        let workspace_dir = ki_workspace_directory()?;
+        Self::load_from_directories(&ki_global_directory(), &ki_workspace_directory()?)
+    }
+
+    /// The config files of `workspace_dir` take precedence over those of `global_dir`.
+    fn load_from_directories(global_dir: &Path, workspace_dir: &Path) -> anyhow::Result<Self> {
         let workspace_config = |extension: &str| workspace_dir.join(format!("config.{extension}"));
-        let global_config =
-            |extension: &str| ki_global_directory().join(format!("config.{extension}"));
+        let global_config = |extension: &str| global_dir.join(format!("config.{extension}"));
         let config: AppConfig =
             figment::Figment::from(providers::Serialized::defaults(&AppConfig::default()))
                 .merge(providers::Json::file(global_config("json")))
@@ -178,31 +198,311 @@ impl AppConfig {
     pub fn leader_keymap(&self) -> &LeaderKeymap {
         &self.leader_keymap
     }
+
+    pub fn max_injection_depth(&self) -> usize {
+        self.max_injection_depth
+    }
+
+    pub fn grammar_url_template(&self) -> Option<&str> {
+        self.grammar_url_template.as_deref()
+    }
+}
+
+/// The languages currently in use, which start as the languages of `AppConfig::singleton`
+/// and are replaced by `reload_languages`.
+///
+/// The whole map is swapped on reload, so a lookup never sees a mix of old and new languages.
+static LANGUAGES: Lazy<RwLock<Arc<HashMap<String, Language>>>> =
+    Lazy::new(|| RwLock::new(Arc::new(AppConfig::singleton().languages().clone())));
+
+/// Incremented by every reload, so that caches derived from the languages
+/// (e.g. `HighlightConfigs`) know when to be invalidated.
+static LANGUAGES_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// The grammar IDs invalidated by each generation, where the generation `n`
+/// is at index `n - 1`, see `invalidated_grammar_ids_since`.
+static INVALIDATED_GRAMMAR_IDS: Lazy<Mutex<Vec<HashSet<String>>>> = Lazy::new(Default::default);
+
+pub fn languages() -> Arc<HashMap<String, Language>> {
+    LANGUAGES
+        .read()
+        .unwrap_or_else(|error| error.into_inner())
+        .clone()
+}
+
+pub fn languages_generation() -> usize {
+    LANGUAGES_GENERATION.load(Ordering::Acquire)
+}
+
+/// Invalidate the caches derived from the grammars of `grammar_ids`,
+/// for example, after a lazily loaded grammar is built.
+pub fn invalidate_grammar_caches(grammar_ids: HashSet<String>) {
+    let mut invalidated_grammar_ids = INVALIDATED_GRAMMAR_IDS
+        .lock()
+        .unwrap_or_else(|error| error.into_inner());
+    invalidated_grammar_ids.push(grammar_ids);
+    LANGUAGES_GENERATION.store(invalidated_grammar_ids.len(), Ordering::Release);
+}
+
+/// The grammar IDs invalidated after `generation`, so that caches derived from the languages
+/// only need to evict the entries of these grammars.
+pub fn invalidated_grammar_ids_since(generation: usize) -> HashSet<String> {
+    INVALIDATED_GRAMMAR_IDS
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .iter()
+        .skip(generation)
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+/// Re-read the config files, so that the next language detection and highlight
+/// use the updated languages without restarting Ki.
+pub fn reload_languages() -> anyhow::Result<LanguagesDiff> {
+    reload_languages_from(&ki_global_directory(), &ki_workspace_directory()?)
+}
+
+fn reload_languages_from(global_dir: &Path, workspace_dir: &Path) -> anyhow::Result<LanguagesDiff> {
+    let config = AppConfig::load_from_directories(global_dir, workspace_dir)?;
+    let (_, diff) = replace_languages(Arc::new(config.languages));
+    Ok(diff)
+}
+
+/// Swap the languages in use for `current`, and invalidate the caches of the grammars that changed.
+/// Returns the previous languages.
+fn replace_languages(
+    current: Arc<HashMap<String, Language>>,
+) -> (Arc<HashMap<String, Language>>, LanguagesDiff) {
+    let previous = std::mem::replace(
+        &mut *LANGUAGES.write().unwrap_or_else(|error| error.into_inner()),
+        current.clone(),
+    );
+    let diff = LanguagesDiff::new(&previous, &current);
+    invalidate_grammar_caches(diff.grammar_ids(&previous, &current));
+    (previous, diff)
+}
+
+/// The names of the languages that are added, removed or changed by a reload,
+/// so that only the caches of these languages need to be invalidated.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LanguagesDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl LanguagesDiff {
+    /// The names in each list are sorted.
+    pub fn new(previous: &HashMap<String, Language>, current: &HashMap<String, Language>) -> Self {
+        let added = current
+            .keys()
+            .filter(|name| !previous.contains_key(*name))
+            .cloned()
+            .sorted()
+            .collect();
+        let removed = previous
+            .keys()
+            .filter(|name| !current.contains_key(*name))
+            .cloned()
+            .sorted()
+            .collect();
+        let changed = current
+            .iter()
+            .filter(|(name, language)| {
+                previous
+                    .get(*name)
+                    .is_some_and(|previous| previous != *language)
+            })
+            .map(|(name, _)| name.clone())
+            .sorted()
+            .collect();
+        Self {
+            added,
+            removed,
+            changed,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// The grammar IDs of the added, removed and changed languages, before and after the reload,
+    /// whose caches need to be invalidated.
+    pub fn grammar_ids(
+        &self,
+        previous: &HashMap<String, Language>,
+        current: &HashMap<String, Language>,
+    ) -> HashSet<String> {
+        self.added
+            .iter()
+            .chain(&self.removed)
+            .chain(&self.changed)
+            .flat_map(|name| previous.get(name).into_iter().chain(current.get(name)))
+            .flat_map(|language| language.tree_sitter_grammar_ids())
+            .collect()
+    }
+}
+
+/// An external command that the languages depend on, see `required_binaries`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequiredBinary {
+    pub name: String,
+    /// Whether it is found in `PATH`.
+    pub is_available: bool,
+}
+
+/// The distinct external commands used by the formatters and LSP servers of every language,
+/// sorted by name, so that users can check which of them are missing from their environment.
+pub fn required_binaries() -> Vec<RequiredBinary> {
+    required_binaries_of(&languages())
+}
+
+fn required_binaries_of(languages: &HashMap<String, Language>) -> Vec<RequiredBinary> {
+    languages
+        .values()
+        .flat_map(|language| language.required_commands())
+        .filter(|name| !name.is_empty())
+        .sorted()
+        .dedup()
+        .map(|name| RequiredBinary {
+            is_available: ProcessCommand::new(&name, &[]).is_available(),
+            name,
+        })
+        .collect()
 }
 
 pub fn from_path(path: &CanonicalizedPath) -> Option<Language> {
-    path.extension()
-        .and_then(from_extension)
-        .or_else(|| from_filename(path))
+    path.file_name()
+        .and_then(|file_name| from_file_name(&file_name))
+        .map(|language| language.for_path(path.as_ref()))
+}
+
+/// The language of the longest extension that `file_name` ends with,
+/// or else the language that `file_name` is registered for.
+fn from_file_name(file_name: &str) -> Option<Language> {
+    from_longest_extension(file_name).or_else(|| from_exact_file_name(file_name))
 }
 
 pub fn from_extension(extension: &str) -> Option<Language> {
-    AppConfig::singleton()
-        .languages()
+    languages()
         .iter()
         .find(|(_, language)| language.extensions().contains(&extension.to_string()))
         .map(|(_, language)| (*language).clone())
 }
 
+/// The language with the longest extension that `file_name` ends with,
+/// so that a multi-part extension like `d.ts` is preferred over `ts`.
+fn from_longest_extension(file_name: &str) -> Option<Language> {
+    languages()
+        .values()
+        .filter_map(|language| Some((longest_extension_length(language, file_name)?, language)))
+        .max_by_key(|(length, _)| *length)
+        .map(|(_, language)| language.clone())
+}
+
+/// The length of the longest extension of `language` that `file_name` ends with.
+fn longest_extension_length(language: &Language, file_name: &str) -> Option<usize> {
+    language
+        .extensions()
+        .iter()
+        .filter(|extension| {
+            file_name
+                .strip_suffix(extension.as_str())
+                .and_then(|rest| rest.strip_suffix('.'))
+                .is_some_and(|stem| !stem.is_empty())
+        })
+        .map(|extension| extension.len())
+        .max()
+}
+
 pub fn from_filename(path: &CanonicalizedPath) -> Option<Language> {
-    let file_name = path.file_name()?;
-    AppConfig::singleton()
-        .languages()
+    from_exact_file_name(&path.file_name()?)
+}
+
+fn from_exact_file_name(file_name: &str) -> Option<Language> {
+    languages()
         .iter()
-        .find(|(_, language)| language.file_names().contains(&file_name))
+        .find(|(_, language)| language.file_names().iter().any(|name| name == file_name))
         .map(|(_, language)| (*language).clone())
 }
 
+/// The file extensions of all configured languages, sorted and deduplicated.
+pub fn all_known_extensions() -> Vec<String> {
+    languages()
+        .values()
+        .flat_map(|language| language.extensions())
+        .cloned()
+        .sorted()
+        .dedup()
+        .collect()
+}
+
+/// The file names (e.g. `Dockerfile`) of all configured languages, sorted and deduplicated.
+pub fn all_known_file_names() -> Vec<String> {
+    languages()
+        .values()
+        .flat_map(|language| language.file_names())
+        .cloned()
+        .sorted()
+        .dedup()
+        .collect()
+}
+
+/// Find the language referred to by an injection query,
+/// for example, `bash` in `(#set! injection.language "bash")`.
+pub fn from_injection_language(name: &str) -> Option<Language> {
+    let name = shared::ts_injection_query::normalize_injection_language(name);
+    let languages = languages();
+    languages
+        .get(&name)
+        .or_else(|| {
+            languages
+                .values()
+                .find(|language| language.tree_sitter_grammar_id().as_ref() == Some(&name))
+        })
+        .cloned()
+        .or_else(|| from_extension(&name))
+}
+
+/// Languages injected in a language, resolved by `from_injection_language`,
+/// so that the languages of the config are found too.
+pub trait GetInjectedLanguages {
+    /// The language injected at `offset` of `source`, see `Language::injection_language_name_at`.
+    fn injection_language_at(&self, source: &str, offset: usize) -> Option<Language>;
+
+    /// The distinct languages injected anywhere in `source`, in order of appearance,
+    /// see `Language::injected_language_names`.
+    fn injected_languages(&self, source: &str) -> Vec<Language>;
+}
+
+impl GetInjectedLanguages for Language {
+    fn injection_language_at(&self, source: &str, offset: usize) -> Option<Language> {
+        from_injection_language(&self.injection_language_name_at(source, offset)?)
+    }
+
+    fn injected_languages(&self, source: &str) -> Vec<Language> {
+        let mut languages: Vec<Language> = Vec::new();
+        for name in self.injected_language_names(source) {
+            match from_injection_language(&name) {
+                Some(language) if !languages.contains(&language) => languages.push(language),
+                Some(_) => {}
+                None => log::debug!(
+                    "[GetInjectedLanguages::injected_languages]: Skipping the unknown injected language {name:?}"
+                ),
+            }
+        }
+        languages
+    }
+}
+
+/// Detect the language from the path, and fallback to the content directive
+/// (e.g. shebang) if the path is not associated with any language.
+pub fn from_path_or_content_directive(path: &CanonicalizedPath, content: &str) -> Option<Language> {
+    from_path(path).or_else(|| from_content_directive(content))
+}
+
 /// Detect the language from the first line of the file content.
 ///
 /// Standard shebang format is checked as well as vim's `ft=` method and various
@@ -212,6 +512,7 @@ pub fn from_filename(path: &CanonicalizedPath) -> Option<Language> {
 /// detected as bash.
 ///
 /// - `#!/bin/bash`
+/// - `#!/usr/bin/env bash`
 /// - `# vim: ft=bash`
 /// - `# mode: bash
 ///
@@ -219,14 +520,8 @@ pub fn from_filename(path: &CanonicalizedPath) -> Option<Language> {
 pub fn from_content_directive(content: &str) -> Option<Language> {
     let first_line = content.lines().next()?;
 
-    let re = Regex::new(r"(?:(?:^#!.*/)|(?:mode:)|(?:ft\s*=))\s*(\w+)").unwrap();
-    let language_id = re
-        .captures(first_line)
-        .and_then(|captures| captures.get(1).map(|mode| mode.as_str().to_string()));
-
-    language_id.and_then(|id| {
-        AppConfig::singleton()
-            .languages()
+    content_directive_language_id(first_line).and_then(|id| {
+        languages()
             .iter()
             .find(|(_, language)| {
                 language
@@ -238,10 +533,144 @@ pub fn from_content_directive(content: &str) -> Option<Language> {
     })
 }
 
+fn content_directive_language_id(first_line: &str) -> Option<String> {
+    let re = Regex::new(r"(?:(?:^#!.*/(?:env\s+)?)|(?:mode:)|(?:ft\s*=))\s*(\w+)").unwrap();
+    re.captures(first_line)
+        .and_then(|captures| captures.get(1).map(|mode| mode.as_str().to_string()))
+}
+
+/// The rules for detecting the language of a file, in order of precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LanguageResolutionRule {
+    Extension,
+    FileName,
+    Shebang,
+    /// e.g. `mode: bash` or `vim: ft=bash`
+    Modeline,
+}
+
+#[derive(Debug, Clone)]
+pub struct LanguageCandidate {
+    /// The key of the language in the `languages` config.
+    pub name: String,
+    pub language: Language,
+    pub rule: LanguageResolutionRule,
+}
+
+#[derive(Debug, Clone)]
+pub struct LanguageResolution {
+    /// Sorted by the precedence of their rules, so the first candidate is the chosen one.
+    pub candidates: Vec<LanguageCandidate>,
+}
+
+impl LanguageResolution {
+    pub fn chosen(&self) -> Option<&LanguageCandidate> {
+        self.candidates.first()
+    }
+}
+
+/// Like `from_path_or_content_directive`, but returns every language matched by each rule,
+/// which is helpful for finding out why a file is detected as an unexpected language.
+///
+/// The extension candidates are sorted by the length of their extension, longest first,
+/// like the extension lookup of `from_path`.
+///
+/// There are no glob or `.gitattributes` rules, because the detection of `from_path`
+/// does not use them, so they would explain a language that is never chosen.
+pub fn resolve_language_explained(
+    path: &std::path::Path,
+    first_line: Option<&str>,
+) -> LanguageResolution {
+    let file_name = path.file_name().and_then(|file_name| file_name.to_str());
+    let directive = first_line.and_then(|first_line| {
+        let rule = if first_line.starts_with("#!") {
+            LanguageResolutionRule::Shebang
+        } else {
+            LanguageResolutionRule::Modeline
+        };
+        Some((content_directive_language_id(first_line)?, rule))
+    });
+
+    let candidates = languages()
+        .iter()
+        .flat_map(|(name, language)| {
+            let extension_length =
+                file_name.and_then(|file_name| longest_extension_length(language, file_name));
+            let file_name_matched = file_name
+                .is_some_and(|file_name| language.file_names().contains(&file_name.to_string()));
+            let directive_rule = directive.as_ref().and_then(|(id, rule)| {
+                language
+                    .lsp_language_id()
+                    .clone()
+                    .is_some_and(|lsp_id| &lsp_id.to_string() == id)
+                    .then_some(*rule)
+            });
+            [
+                extension_length.map(|length| (LanguageResolutionRule::Extension, length)),
+                file_name_matched.then_some((LanguageResolutionRule::FileName, 0)),
+                directive_rule.map(|rule| (rule, 0)),
+            ]
+            .into_iter()
+            .flatten()
+            .map(|(rule, extension_length)| {
+                let candidate = LanguageCandidate {
+                    name: name.clone(),
+                    language: language.clone(),
+                    rule,
+                };
+                (candidate, extension_length)
+            })
+        })
+        .sorted_by_key(|(candidate, extension_length)| {
+            (
+                candidate.rule,
+                std::cmp::Reverse(*extension_length),
+                candidate.name.clone(),
+            )
+        })
+        .map(|(candidate, _)| candidate)
+        .collect();
+    LanguageResolution { candidates }
+}
+
+/// Whether `a` and `b` are detected as the same language,
+/// for example, to rename a symbol only across files of the same type.
+///
+/// The languages are detected like `from_path`, but without being specialized for the path,
+/// so that, for example, `Cargo.toml` and `config.toml` are both TOML.
+///
+/// Files of no known language are both plain text, so they count as the same language.
+pub fn same_language(a: &Path, b: &Path) -> bool {
+    let language = |path: &Path| {
+        path.file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(from_file_name)
+    };
+    language(a) == language(b)
+}
+
 #[cfg(test)]
 mod test_language {
     use super::*;
+    use serial_test::serial;
     use std::fs::File;
+
+    /// Restores the languages in use when dropped,
+    /// so that a test reloading the languages does not affect the other tests.
+    struct RestoreLanguages(Arc<HashMap<String, Language>>);
+
+    impl RestoreLanguages {
+        fn new() -> Self {
+            Self(languages())
+        }
+    }
+
+    impl Drop for RestoreLanguages {
+        fn drop(&mut self) {
+            replace_languages(self.0.clone());
+        }
+    }
+
     #[test]
     fn test_from_path() -> anyhow::Result<()> {
         fn run_test_case(filename: &str, expected_language_id: &'static str) -> anyhow::Result<()> {
@@ -257,6 +686,14 @@ mod test_language {
         }
         run_test_case("hello.rs", "rust")?;
         run_test_case("justfile", "just")?;
+        run_test_case("rules.mk", "make")?;
+        run_test_case("Makefile", "make")?;
+        run_test_case("icon.svg", "xml")?;
+        run_test_case("schema.xsd", "xml")?;
+        run_test_case("foo.d.ts", "typescript")?;
+        run_test_case("component.tsx", "tsx")?;
+        run_test_case("boot.s", "asm")?;
+        run_test_case("boot.S", "asm")?;
         Ok(())
     }
 
@@ -273,6 +710,7 @@ mod test_language {
 
         run_test_case("#!/bin/bash", "bash")?;
         run_test_case("#!/usr/local/bin/bash", "bash")?;
+        run_test_case("#!/usr/bin/env bash", "bash")?;
         run_test_case("// mode: python", "python")?;
         run_test_case("-- tab_spaces: 5, mode: bash, use_tabs: false", "bash")?;
         run_test_case("-- tab_spaces: 5, mode:bash, use_tabs: false", "bash")?;
@@ -280,6 +718,371 @@ mod test_language {
 
         Ok(())
     }
+
+    #[test]
+    fn shebang_only_file_uses_bash_formatter() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("script");
+        let content = "#!/usr/bin/env bash\necho hi";
+        std::fs::write(&path, content)?;
+        let language = from_path_or_content_directive(
+            &path.to_string_lossy().to_string().try_into()?,
+            content,
+        )
+        .unwrap();
+        assert!(language
+            .formatter()
+            .unwrap()
+            .command_string()
+            .starts_with("shfmt"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_language_explained() {
+        // The extension says Python, but the shebang says Bash
+        let resolution = resolve_language_explained(
+            std::path::Path::new("script.py"),
+            Some("#!/usr/bin/env bash"),
+        );
+        let chosen = resolution.chosen().unwrap();
+        assert_eq!(chosen.name, "python");
+        assert_eq!(chosen.rule, LanguageResolutionRule::Extension);
+        assert_eq!(
+            resolution
+                .candidates
+                .iter()
+                .map(|candidate| (candidate.name.as_str(), candidate.rule))
+                .collect_vec(),
+            vec![
+                ("python", LanguageResolutionRule::Extension),
+                ("bash", LanguageResolutionRule::Shebang)
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_language_explained_prefers_the_longest_extension_like_from_path(
+    ) -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("page.html.tera");
+        File::create(&path)?;
+        let resolution = resolve_language_explained(&path, None);
+        let chosen = resolution.chosen().unwrap();
+        assert_eq!(chosen.rule, LanguageResolutionRule::Extension);
+        assert_eq!(
+            chosen.language.tree_sitter_grammar_id(),
+            from_path(&path.try_into()?)
+                .unwrap()
+                .tree_sitter_grammar_id()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_all_known_extensions_and_file_names() {
+        let extensions = all_known_extensions();
+        assert!(extensions.contains(&"rs".to_string()));
+        assert!(extensions.contains(&"ts".to_string()));
+        assert!(extensions.is_sorted());
+        assert_eq!(extensions.iter().unique().count(), extensions.len());
+
+        assert!(all_known_file_names().contains(&"Dockerfile".to_string()));
+    }
+
+    #[test]
+    fn injection_language_at() {
+        let markdown = from_extension("md").unwrap();
+        let source = "# Config\n\n```lua\nlocal x = 1\n```\n";
+        let language = markdown
+            .injection_language_at(source, source.find("local").unwrap())
+            .unwrap();
+        assert_eq!(language.tree_sitter_grammar_id().unwrap(), "lua");
+
+        // The heading belongs to Markdown itself
+        assert_eq!(markdown.injection_language_at(source, 0), None);
+    }
+
+    #[test]
+    fn injected_languages() {
+        let markdown = from_extension("md").unwrap();
+        let source = "```rust\nfn main() {}\n```\n\n```python\nx = 1\n```\n\n```rust\nfn f() {}\n```\n\n```unknown\n?\n```\n";
+        let grammar_ids = markdown
+            .injected_languages(source)
+            .into_iter()
+            .filter_map(|language| language.tree_sitter_grammar_id())
+            .collect_vec();
+        assert_eq!(grammar_ids, ["rust", "python"]);
+        assert!(Language::new().injected_languages(source).is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn injection_language_at_resolves_languages_of_the_config() -> anyhow::Result<()> {
+        let _restore_languages = RestoreLanguages::new();
+        let global_dir = tempfile::tempdir()?;
+        let workspace_dir = tempfile::tempdir()?;
+        std::fs::write(
+            global_dir.path().join("config.json"),
+            r#"{"languages": {"kifence": {"extensions": ["kifence"], "file_names": []}}}"#,
+        )?;
+        reload_languages_from(global_dir.path(), workspace_dir.path())?;
+
+        let markdown = from_extension("md").unwrap();
+        let source = "```kifence\nhello\n```\n";
+        let language = markdown
+            .injection_language_at(source, source.find("hello").unwrap())
+            .unwrap();
+        assert_eq!(language.extensions(), &["kifence".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn vue_lang_attribute_resolves_to_injected_language() {
+        // e.g. `<script lang="ts">` and `<style lang="css">`
+        let language = from_injection_language("ts").unwrap();
+        assert_eq!(language.tree_sitter_grammar_id().unwrap(), "typescript");
+        let language = from_injection_language("css").unwrap();
+        assert_eq!(language.tree_sitter_grammar_id().unwrap(), "css");
+
+        let language = from_extension("vue").unwrap();
+        assert_eq!(language.tree_sitter_grammar_id().unwrap(), "vue");
+    }
+
+    #[test]
+    #[serial]
+    fn languages_added_to_config_are_resolvable_after_reload() -> anyhow::Result<()> {
+        let _restore_languages = RestoreLanguages::new();
+        let global_dir = tempfile::tempdir()?;
+        let workspace_dir = tempfile::tempdir()?;
+        assert!(from_extension("kireload").is_none());
+
+        std::fs::write(
+            global_dir.path().join("config.json"),
+            r#"{"languages": {"kireload": {"extensions": ["kireload"], "file_names": []}}}"#,
+        )?;
+        let generation = languages_generation();
+        let diff = reload_languages_from(global_dir.path(), workspace_dir.path())?;
+
+        assert!(languages_generation() > generation);
+        assert!(diff.added.contains(&"kireload".to_string()));
+        assert!(from_extension("kireload").is_some());
+        // The default languages are still there
+        assert!(from_extension("rs").is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn overriding_only_the_formatter_keeps_the_rest_of_the_built_in_language() -> anyhow::Result<()>
+    {
+        let global_dir = tempfile::tempdir()?;
+        let workspace_dir = tempfile::tempdir()?;
+        std::fs::write(
+            global_dir.path().join("config.json"),
+            r#"{"languages": {"rust": {"formatter": {"command": "cargo", "arguments": ["fmt"]}}}}"#,
+        )?;
+        let config = AppConfig::load_from_directories(global_dir.path(), workspace_dir.path())?;
+
+        let rust = &config.languages()["rust"];
+        let built_in = shared::languages::languages().remove("rust").unwrap();
+        assert_eq!(rust.formatter().unwrap().command_string(), "cargo fmt");
+        assert_eq!(
+            rust.tree_sitter_grammar_id(),
+            built_in.tree_sitter_grammar_id()
+        );
+        assert_eq!(
+            rust.effective_comment_syntax(),
+            built_in.effective_comment_syntax()
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn longest_extension_is_preferred() -> anyhow::Result<()> {
+        let _restore_languages = RestoreLanguages::new();
+        let global_dir = tempfile::tempdir()?;
+        let workspace_dir = tempfile::tempdir()?;
+        std::fs::write(
+            global_dir.path().join("config.json"),
+            r#"{"languages": {"kideclaration": {"extensions": ["d.kits"], "file_names": []}, "kiscript": {"extensions": ["kits"], "file_names": []}}}"#,
+        )?;
+        reload_languages_from(global_dir.path(), workspace_dir.path())?;
+
+        let extensions_of = |file_name: &str| {
+            from_longest_extension(file_name).map(|language| language.extensions().clone())
+        };
+        assert_eq!(
+            extensions_of("foo.d.kits"),
+            Some(vec!["d.kits".to_string()])
+        );
+        assert_eq!(extensions_of("foo.kits"), Some(vec!["kits".to_string()]));
+        // The extension must follow a dot
+        assert_eq!(extensions_of("food.kits"), Some(vec!["kits".to_string()]));
+        assert_eq!(extensions_of("d.kits"), Some(vec!["kits".to_string()]));
+        assert_eq!(extensions_of("kits"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn ocaml_implementation_and_interface_share_an_lsp_server() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let [implementation, interface] = ["main.ml", "main.mli"].map(|file_name| {
+            let path = tempdir.path().join(file_name);
+            File::create(&path)?;
+            let path: CanonicalizedPath = path.try_into()?;
+            anyhow::Ok(from_path(&path).unwrap())
+        });
+        let (implementation, interface) = (implementation?, interface?);
+        assert_ne!(
+            implementation.tree_sitter_grammar_id(),
+            interface.tree_sitter_grammar_id()
+        );
+        assert!(implementation.lsp_server_key().is_some());
+        assert_eq!(implementation.lsp_server_key(), interface.lsp_server_key());
+        Ok(())
+    }
+
+    #[test]
+    fn test_same_language() {
+        assert!(same_language(Path::new("src/a.rs"), Path::new("b.rs")));
+        assert!(!same_language(Path::new("a.rs"), Path::new("a.py")));
+        assert!(same_language(Path::new("a.unknown"), Path::new("LICENSE")));
+        assert!(!same_language(Path::new("a.unknown"), Path::new("a.rs")));
+        assert!(same_language(
+            Path::new("Cargo.toml"),
+            Path::new("config.toml")
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn same_language_prefers_the_longest_extension() -> anyhow::Result<()> {
+        // `d.ts` is not a language of its own by default
+        assert!(same_language(Path::new("a.d.ts"), Path::new("a.ts")));
+
+        let _restore_languages = RestoreLanguages::new();
+        let global_dir = tempfile::tempdir()?;
+        let workspace_dir = tempfile::tempdir()?;
+        std::fs::write(
+            global_dir.path().join("config.json"),
+            r#"{"languages": {"kideclaration": {"extensions": ["d.kits"], "file_names": []}, "kiscript": {"extensions": ["kits"], "file_names": []}}}"#,
+        )?;
+        reload_languages_from(global_dir.path(), workspace_dir.path())?;
+
+        assert!(same_language(Path::new("a.d.kits"), Path::new("b.d.kits")));
+        assert!(!same_language(Path::new("a.d.kits"), Path::new("a.kits")));
+        Ok(())
+    }
+
+    #[test]
+    fn languages_diff() -> anyhow::Result<()> {
+        let language = |extensions: &[&str]| {
+            serde_json::from_value::<Language>(serde_json::json!({
+                "extensions": extensions,
+                "file_names": [],
+            }))
+        };
+        let previous = HashMap::from([
+            ("kione".to_string(), language(&["one"])?),
+            ("kitwo".to_string(), language(&["two"])?),
+        ]);
+        let current = HashMap::from([
+            ("kione".to_string(), language(&["one"])?),
+            ("kitwo".to_string(), language(&["two", "2"])?),
+            ("kithree".to_string(), language(&["three"])?),
+        ]);
+
+        let diff = LanguagesDiff::new(&previous, &current);
+        assert_eq!(
+            diff,
+            LanguagesDiff {
+                added: vec!["kithree".to_string()],
+                removed: Vec::new(),
+                changed: vec!["kitwo".to_string()],
+            }
+        );
+        assert_eq!(
+            LanguagesDiff::new(&current, &previous).removed,
+            vec!["kithree".to_string()]
+        );
+        assert!(LanguagesDiff::new(&current, &current).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn languages_diff_grammar_ids_are_those_of_the_changed_languages() -> anyhow::Result<()> {
+        let language = |grammar_id: &str| {
+            serde_json::from_value::<Language>(serde_json::json!({
+                "extensions": [grammar_id],
+                "file_names": [],
+                "tree_sitter_grammar_config": {
+                    "id": grammar_id,
+                    "kind": {"CargoLinked": "Rust"},
+                },
+            }))
+        };
+        let previous = HashMap::from([
+            ("kione".to_string(), language("kione")?),
+            ("kitwo".to_string(), language("kitwo")?),
+        ]);
+        let current = HashMap::from([
+            ("kione".to_string(), language("kione")?),
+            ("kitwo".to_string(), language("kitwo2")?),
+        ]);
+
+        let diff = LanguagesDiff::new(&previous, &current);
+        assert_eq!(
+            diff.grammar_ids(&previous, &current),
+            HashSet::from(["kitwo".to_string(), "kitwo2".to_string()])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn required_binaries_are_listed_with_their_availability() -> anyhow::Result<()> {
+        let rust: Language = serde_json::from_value(serde_json::json!({
+            "extensions": ["rs"],
+            "file_names": [],
+            "formatter": { "command": "rustfmt", "arguments": [] },
+            "lsp_command": {
+                "command": { "command": "rust-analyzer", "arguments": [] }
+            }
+        }))?;
+        let shell: Language = serde_json::from_value(serde_json::json!({
+            "extensions": ["sh"],
+            "file_names": [],
+            "formatter": { "command": "sh", "arguments": [] },
+            "extra_formatters": [{ "command": "ki-missing-formatter", "arguments": [] }]
+        }))?;
+        let languages = HashMap::from([
+            ("rust".to_string(), rust.clone()),
+            ("rust-copy".to_string(), rust),
+            ("shell".to_string(), shell),
+        ]);
+
+        let binaries = required_binaries_of(&languages);
+        assert_eq!(
+            binaries
+                .iter()
+                .map(|binary| binary.name.as_str())
+                .collect_vec(),
+            ["ki-missing-formatter", "rust-analyzer", "rustfmt", "sh"]
+        );
+        let is_available = |name: &str| {
+            binaries
+                .iter()
+                .find(|binary| binary.name == name)
+                .map(|binary| binary.is_available)
+        };
+        assert_eq!(is_available("sh"), Some(true));
+        assert_eq!(is_available("ki-missing-formatter"), Some(false));
+        assert_eq!(
+            is_available("rustfmt"),
+            Some(ProcessCommand::new("rustfmt", &[]).is_available())
+        );
+        Ok(())
+    }
 }
 
 mod test_config {
@@ -346,6 +346,13 @@ impl Layout {
         Ok(())
     }
 
+    pub fn background_suggestive_editors(&self) -> Vec<Rc<RefCell<SuggestiveEditor>>> {
+        self.background_suggestive_editors
+            .values()
+            .cloned()
+            .collect_vec()
+    }
+
     pub fn buffers(&self) -> Vec<Rc<RefCell<Buffer>>> {
         self.background_suggestive_editors
             .iter()
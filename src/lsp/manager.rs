@@ -52,7 +52,10 @@ impl LspManager {
         f: impl Fn(&LspServerProcessChannel) -> anyhow::Result<()>,
     ) -> anyhow::Result<()> {
         crate::config::from_path(path)
-            .and_then(|language| self.lsp_server_process_channels.get(&language.id()?))
+            .and_then(|language| {
+                self.lsp_server_process_channels
+                    .get(&language.lsp_server_key()?)
+            })
             .map(f)
             .unwrap_or_else(|| Ok(()))
     }
@@ -81,44 +84,46 @@ impl LspManager {
         let Some(language) = crate::config::from_path(&path) else {
             return Ok(());
         };
-        let Some(language_id) = language.id() else {
+        if !language.should_attach_lsp(path.as_ref()) {
+            return Ok(());
+        }
+        let Some(server_key) = language.lsp_server_key() else {
             return Ok(());
         };
 
-        if let Some(channel) = self.lsp_server_process_channels.get(&language_id) {
+        if let Some(channel) = self.lsp_server_process_channels.get(&server_key) {
             if channel.is_initialized() {
                 channel.document_did_open(path.clone())
             } else {
                 Ok(())
             }
         } else {
-            LspServerProcessChannel::new(
-                language.clone(),
-                self.sender.clone(),
-                self.current_working_directory.clone(),
-            )
-            .map(|channel| {
-                if let Some(channel) = channel {
-                    self.lsp_server_process_channels
-                        .insert(language.id()?, channel);
-                }
-                Some(())
-            })?;
+            let root_directory = language
+                .lsp_root_directory(path.as_ref())
+                .and_then(|directory| directory.try_into().ok())
+                .unwrap_or_else(|| self.current_working_directory.clone());
+            LspServerProcessChannel::new(language.clone(), self.sender.clone(), root_directory)
+                .map(|channel| {
+                    if let Some(channel) = channel {
+                        self.lsp_server_process_channels.insert(server_key, channel);
+                    }
+                    Some(())
+                })?;
             Ok(())
         }
     }
 
     pub fn initialized(&mut self, language: Language, opened_documents: Vec<CanonicalizedPath>) {
-        let Some(language_id) = language.id() else {
+        let Some(server_key) = language.lsp_server_key() else {
             return;
         };
 
         #[cfg(test)]
         self.lsp_server_initialized_args_history
-            .push((language_id.clone(), opened_documents.clone()));
+            .push((server_key.clone(), opened_documents.clone()));
 
         self.lsp_server_process_channels
-            .get_mut(&language_id)
+            .get_mut(&server_key)
             .map(|channel| {
                 channel.initialized();
                 channel.documents_did_open(opened_documents)
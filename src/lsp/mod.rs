@@ -6,6 +6,7 @@ pub mod documentation;
 pub mod goto_definition_response;
 pub mod hover;
 pub mod manager;
+mod position_encoding;
 pub mod prepare_rename_response;
 pub mod process;
 pub mod signature_help;
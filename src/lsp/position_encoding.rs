@@ -0,0 +1,344 @@
+use lsp_types::{
+    AnnotatedTextEdit, ApplyWorkspaceEditParams, CodeAction, CodeActionOrCommand, CodeActionParams,
+    CompletionItem, CompletionParams, CompletionResponse, CompletionTextEdit, Diagnostic,
+    DiagnosticRelatedInformation, DocumentChangeOperation, DocumentChanges, DocumentSymbol,
+    DocumentSymbolParams, DocumentSymbolResponse, ExecuteCommandParams, GotoDefinitionParams,
+    GotoDefinitionResponse, Hover, HoverParams, InitializeParams, InsertReplaceEdit, Location,
+    LocationLink, OneOf, Position, PrepareRenameResponse, PublishDiagnosticsParams, Range,
+    ReferenceParams, RenameParams, SignatureHelp, SignatureHelpParams, SymbolInformation,
+    TextDocumentEdit, TextDocumentPositionParams, TextEdit, Url, WorkspaceEdit, WorkspaceLocation,
+    WorkspaceSymbol, WorkspaceSymbolParams, WorkspaceSymbolResponse,
+};
+
+/// Called with the document URI, the line and the `character` of a position,
+/// and returns the converted `character`.
+pub type Convert<'a> = dyn FnMut(&Url, u32, u32) -> u32 + 'a;
+
+/// The LSP requests, responses and notifications whose positions are converted
+/// between the char columns of Ki and the position encoding of the server.
+pub trait ConvertPositions {
+    /// Replaces the `character` of every position with the result of `convert`.
+    ///
+    /// `uri` is the document of the positions that are not in a document of their own,
+    /// for example, the text edits of a completion.
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert);
+}
+
+/// The messages that contain no positions
+macro_rules! without_positions {
+    ($($type:ty),* $(,)?) => {
+        $(
+            impl ConvertPositions for $type {
+                fn convert_positions(&mut self, _: Option<&Url>, _: &mut Convert) {}
+            }
+        )*
+    };
+}
+
+without_positions!(
+    (),
+    InitializeParams,
+    DocumentSymbolParams,
+    WorkspaceSymbolParams,
+    ExecuteCommandParams,
+    SignatureHelp,
+    WorkspaceLocation,
+);
+
+impl<T: ConvertPositions> ConvertPositions for Option<T> {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        if let Some(value) = self {
+            value.convert_positions(uri, convert)
+        }
+    }
+}
+
+impl<T: ConvertPositions> ConvertPositions for Vec<T> {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        for value in self {
+            value.convert_positions(uri, convert)
+        }
+    }
+}
+
+impl<A: ConvertPositions, B: ConvertPositions> ConvertPositions for OneOf<A, B> {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        match self {
+            OneOf::Left(value) => value.convert_positions(uri, convert),
+            OneOf::Right(value) => value.convert_positions(uri, convert),
+        }
+    }
+}
+
+impl ConvertPositions for Position {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        if let Some(uri) = uri {
+            self.character = convert(uri, self.line, self.character)
+        }
+    }
+}
+
+impl ConvertPositions for Range {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.start.convert_positions(uri, convert);
+        self.end.convert_positions(uri, convert);
+    }
+}
+
+impl ConvertPositions for TextDocumentPositionParams {
+    fn convert_positions(&mut self, _: Option<&Url>, convert: &mut Convert) {
+        self.position
+            .convert_positions(Some(&self.text_document.uri), convert)
+    }
+}
+
+impl ConvertPositions for CompletionParams {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.text_document_position.convert_positions(uri, convert)
+    }
+}
+
+impl ConvertPositions for HoverParams {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.text_document_position_params
+            .convert_positions(uri, convert)
+    }
+}
+
+/// Also the params of `textDocument/declaration`, `textDocument/typeDefinition`
+/// and `textDocument/implementation`
+impl ConvertPositions for GotoDefinitionParams {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.text_document_position_params
+            .convert_positions(uri, convert)
+    }
+}
+
+impl ConvertPositions for ReferenceParams {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.text_document_position.convert_positions(uri, convert)
+    }
+}
+
+impl ConvertPositions for RenameParams {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.text_document_position.convert_positions(uri, convert)
+    }
+}
+
+impl ConvertPositions for SignatureHelpParams {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.text_document_position_params
+            .convert_positions(uri, convert)
+    }
+}
+
+impl ConvertPositions for CodeActionParams {
+    fn convert_positions(&mut self, _: Option<&Url>, convert: &mut Convert) {
+        let uri = Some(&self.text_document.uri);
+        self.range.convert_positions(uri, convert);
+        self.context.diagnostics.convert_positions(uri, convert);
+    }
+}
+
+impl ConvertPositions for TextEdit {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.range.convert_positions(uri, convert)
+    }
+}
+
+impl ConvertPositions for AnnotatedTextEdit {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.text_edit.convert_positions(uri, convert)
+    }
+}
+
+impl ConvertPositions for InsertReplaceEdit {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.insert.convert_positions(uri, convert);
+        self.replace.convert_positions(uri, convert);
+    }
+}
+
+impl ConvertPositions for CompletionTextEdit {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        match self {
+            CompletionTextEdit::Edit(edit) => edit.convert_positions(uri, convert),
+            CompletionTextEdit::InsertAndReplace(edit) => edit.convert_positions(uri, convert),
+        }
+    }
+}
+
+impl ConvertPositions for CompletionItem {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.text_edit.convert_positions(uri, convert);
+        self.additional_text_edits.convert_positions(uri, convert);
+    }
+}
+
+impl ConvertPositions for CompletionResponse {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        match self {
+            CompletionResponse::Array(items) => items.convert_positions(uri, convert),
+            CompletionResponse::List(list) => list.items.convert_positions(uri, convert),
+        }
+    }
+}
+
+impl ConvertPositions for Hover {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.range.convert_positions(uri, convert)
+    }
+}
+
+impl ConvertPositions for Location {
+    fn convert_positions(&mut self, _: Option<&Url>, convert: &mut Convert) {
+        self.range.convert_positions(Some(&self.uri), convert)
+    }
+}
+
+impl ConvertPositions for LocationLink {
+    /// The `originSelectionRange` is in the requested document
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.origin_selection_range.convert_positions(uri, convert);
+        let target_uri = Some(&self.target_uri);
+        self.target_range.convert_positions(target_uri, convert);
+        self.target_selection_range
+            .convert_positions(target_uri, convert);
+    }
+}
+
+impl ConvertPositions for GotoDefinitionResponse {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        match self {
+            GotoDefinitionResponse::Scalar(location) => location.convert_positions(uri, convert),
+            GotoDefinitionResponse::Array(locations) => locations.convert_positions(uri, convert),
+            GotoDefinitionResponse::Link(links) => links.convert_positions(uri, convert),
+        }
+    }
+}
+
+impl ConvertPositions for PrepareRenameResponse {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        match self {
+            PrepareRenameResponse::Range(range)
+            | PrepareRenameResponse::RangeWithPlaceholder { range, .. } => {
+                range.convert_positions(uri, convert)
+            }
+            PrepareRenameResponse::DefaultBehavior { .. } => {}
+        }
+    }
+}
+
+impl ConvertPositions for TextDocumentEdit {
+    fn convert_positions(&mut self, _: Option<&Url>, convert: &mut Convert) {
+        self.edits
+            .convert_positions(Some(&self.text_document.uri), convert)
+    }
+}
+
+impl ConvertPositions for DocumentChanges {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        match self {
+            DocumentChanges::Edits(edits) => edits.convert_positions(uri, convert),
+            DocumentChanges::Operations(operations) => {
+                for operation in operations {
+                    if let DocumentChangeOperation::Edit(edit) = operation {
+                        edit.convert_positions(uri, convert)
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl ConvertPositions for WorkspaceEdit {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        // The edits of `changes` are keyed by their document
+        for (uri, edits) in self.changes.iter_mut().flatten() {
+            edits.convert_positions(Some(uri), convert)
+        }
+        self.document_changes.convert_positions(uri, convert);
+    }
+}
+
+impl ConvertPositions for DiagnosticRelatedInformation {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.location.convert_positions(uri, convert)
+    }
+}
+
+impl ConvertPositions for Diagnostic {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.range.convert_positions(uri, convert);
+        self.related_information.convert_positions(uri, convert);
+    }
+}
+
+impl ConvertPositions for CodeAction {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.diagnostics.convert_positions(uri, convert);
+        self.edit.convert_positions(uri, convert);
+    }
+}
+
+impl ConvertPositions for CodeActionOrCommand {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        match self {
+            CodeActionOrCommand::Command(_) => {}
+            CodeActionOrCommand::CodeAction(code_action) => {
+                code_action.convert_positions(uri, convert)
+            }
+        }
+    }
+}
+
+impl ConvertPositions for DocumentSymbol {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.range.convert_positions(uri, convert);
+        self.selection_range.convert_positions(uri, convert);
+        self.children.convert_positions(uri, convert);
+    }
+}
+
+impl ConvertPositions for SymbolInformation {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.location.convert_positions(uri, convert)
+    }
+}
+
+impl ConvertPositions for DocumentSymbolResponse {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        match self {
+            DocumentSymbolResponse::Flat(symbols) => symbols.convert_positions(uri, convert),
+            DocumentSymbolResponse::Nested(symbols) => symbols.convert_positions(uri, convert),
+        }
+    }
+}
+
+impl ConvertPositions for WorkspaceSymbol {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.location.convert_positions(uri, convert)
+    }
+}
+
+impl ConvertPositions for WorkspaceSymbolResponse {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        match self {
+            WorkspaceSymbolResponse::Flat(symbols) => symbols.convert_positions(uri, convert),
+            WorkspaceSymbolResponse::Nested(symbols) => symbols.convert_positions(uri, convert),
+        }
+    }
+}
+
+impl ConvertPositions for PublishDiagnosticsParams {
+    fn convert_positions(&mut self, _: Option<&Url>, convert: &mut Convert) {
+        self.diagnostics.convert_positions(Some(&self.uri), convert)
+    }
+}
+
+impl ConvertPositions for ApplyWorkspaceEditParams {
+    fn convert_positions(&mut self, uri: Option<&Url>, convert: &mut Convert) {
+        self.edit.convert_positions(uri, convert)
+    }
+}
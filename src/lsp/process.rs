@@ -8,10 +8,12 @@ use lsp_types::request::{
 };
 use lsp_types::*;
 use my_proc_macros::NamedVariant;
+use ropey::Rope;
 use shared::canonicalized_path::CanonicalizedPath;
-use shared::language::Language;
+use shared::language::{Language, LspFileWatchers, PositionEncoding};
 use shared::process_command::SpawnCommandResult;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 use std::io::{BufRead, BufReader, Read, Write};
 
 use std::process::{self};
@@ -26,6 +28,7 @@ use super::code_action::CodeAction;
 use super::completion::{Completion, CompletionItem};
 use super::goto_definition_response::GotoDefinitionResponse;
 use super::hover::Hover;
+use super::position_encoding::ConvertPositions;
 use super::prepare_rename_response::PrepareRenameResponse;
 use super::signature_help::SignatureHelp;
 use super::symbols::Symbols;
@@ -41,9 +44,16 @@ struct LspServerProcess {
     stderr: Option<process::ChildStderr>,
 
     server_capabilities: Option<ServerCapabilities>,
+    /// The encoding picked by the server, which is `Utf16` until it is initialized.
+    position_encoding: PositionEncoding,
+    /// The content of the opened documents, for converting the positions of LSP messages.
+    documents: HashMap<CanonicalizedPath, Rope>,
+    file_watchers: LspFileWatchers,
     current_working_directory: CanonicalizedPath,
     next_request_id: RequestId,
     pending_response_requests: HashMap<RequestId, PendingResponseRequest>,
+    /// Requests held back by `lsp_max_concurrent_requests`, in the order they were made.
+    queued_requests: VecDeque<QueuedRequest>,
     app_message_sender: Sender<AppMessage>,
 
     sender: Sender<LspServerProcessMessage>,
@@ -58,6 +68,13 @@ struct PendingResponseRequest {
     path: Option<CanonicalizedPath>,
 }
 
+#[derive(Debug)]
+struct QueuedRequest {
+    id: RequestId,
+    request: serde_json::Value,
+    pending_response_request: PendingResponseRequest,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum LspNotification {
     Initialized(Box<Language>),
@@ -207,7 +224,7 @@ impl LspServerProcessChannel {
 
     pub fn document_did_open(&self, path: CanonicalizedPath) -> Result<(), anyhow::Error> {
         let content = path.read()?;
-        let Some(language_id) = self.language.id() else {
+        let Some(language_id) = self.language.lsp_language_id_for(path.as_ref()) else {
             return Ok(());
         };
         self.send(LspServerProcessMessage::FromEditor(
@@ -273,7 +290,11 @@ impl LspServerProcess {
             current_working_directory,
             next_request_id: 0,
             pending_response_requests: HashMap::new(),
+            queued_requests: VecDeque::new(),
             server_capabilities: None,
+            position_encoding: PositionEncoding::default(),
+            documents: HashMap::new(),
+            file_watchers: LspFileWatchers::default(),
             app_message_sender: app_message_sender.clone(),
             sender: sender.clone(),
         };
@@ -295,8 +316,20 @@ impl LspServerProcess {
             None,
             InitializeParams {
                 process_id: None,
-                initialization_options: self.language.initialization_options(),
+                initialization_options: self
+                    .language
+                    .initialization_options_for(self.current_working_directory.as_ref()),
                 capabilities: ClientCapabilities {
+                    general: Some(GeneralClientCapabilities {
+                        position_encodings: Some(
+                            self.language
+                                .lsp_position_encodings()
+                                .into_iter()
+                                .map(PositionEncoding::to_lsp)
+                                .collect(),
+                        ),
+                        ..GeneralClientCapabilities::default()
+                    }),
                     workspace: Some(WorkspaceClientCapabilities {
                         apply_edit: Some(true),
                         workspace_edit: Some(WorkspaceEditClientCapabilities {
@@ -320,6 +353,10 @@ impl LspServerProcess {
                         execute_command: Some(DynamicRegistrationClientCapabilities {
                             dynamic_registration: None,
                         }),
+                        did_change_watched_files: Some(DidChangeWatchedFilesClientCapabilities {
+                            dynamic_registration: Some(true),
+                            relative_pattern_support: Some(true),
+                        }),
                         symbol: Some(WorkspaceSymbolClientCapabilities {
                             ..Default::default()
                         }),
@@ -396,17 +433,29 @@ impl LspServerProcess {
                             dynamic_registration: Some(true),
                             link_support: None,
                         }),
+                        formatting: self
+                            .language
+                            .expected_lsp_capabilities()
+                            .formatting
+                            .then_some(DocumentFormattingClientCapabilities {
+                                dynamic_registration: None,
+                            }),
                         ..TextDocumentClientCapabilities::default()
                     }),
                     ..ClientCapabilities::default()
                 },
-                workspace_folders: Some(vec![WorkspaceFolder {
-                    uri: Url::parse(&format!(
-                        "file://{}",
-                        self.current_working_directory.display_absolute()
-                    ))?,
-                    name: "root".to_string(),
-                }]),
+                // `root_uri` is left as `None` in either case
+                workspace_folders: if self.language.lsp_single_file_support() {
+                    None
+                } else {
+                    Some(vec![WorkspaceFolder {
+                        uri: Url::parse(&format!(
+                            "file://{}",
+                            self.current_working_directory.display_absolute()
+                        ))?,
+                        name: "root".to_string(),
+                    }])
+                },
                 ..InitializeParams::default()
             },
         )?;
@@ -637,6 +686,7 @@ impl LspServerProcess {
                 // Get the method of the request
                 let pending_response_request =
                     self.pending_response_requests.remove(&request_id).unwrap();
+                self.dispatch_queued_requests()?;
 
                 // Parse the reply as a Response
                 let response = serde_json::from_value::<
@@ -681,6 +731,9 @@ impl LspServerProcess {
                         let payload: <lsp_request!("initialize") as Request>::Result =
                             serde_json::from_value(response)?;
 
+                        self.position_encoding =
+                            PositionEncoding::negotiated(&payload.capabilities);
+                        log::info!("Negotiated position encoding: {:?}", self.position_encoding);
                         // Get the capabilities
                         self.server_capabilities = Some(payload.capabilities);
 
@@ -695,8 +748,9 @@ impl LspServerProcess {
                             )))?;
                     }
                     "textDocument/completion" => {
-                        let payload: <lsp_request!("textDocument/completion") as Request>::Result =
+                        let mut payload: <lsp_request!("textDocument/completion") as Request>::Result =
                             serde_json::from_value(response)?;
+                        self.convert_position_characters(&mut payload, path.as_ref(), false);
 
                         if let Some(payload) = payload {
                             self.app_message_sender
@@ -720,8 +774,9 @@ impl LspServerProcess {
                         }
                     }
                     "textDocument/hover" => {
-                        let payload: <lsp_request!("textDocument/hover") as Request>::Result =
+                        let mut payload: <lsp_request!("textDocument/hover") as Request>::Result =
                             serde_json::from_value(response)?;
+                        self.convert_position_characters(&mut payload, path.as_ref(), false);
 
                         if let Some(payload) = payload {
                             self.app_message_sender
@@ -732,8 +787,9 @@ impl LspServerProcess {
                         }
                     }
                     "textDocument/definition" => {
-                        let payload: <lsp_request!("textDocument/definition") as Request>::Result =
+                        let mut payload: <lsp_request!("textDocument/definition") as Request>::Result =
                             serde_json::from_value(response)?;
+                        self.convert_position_characters(&mut payload, path.as_ref(), false);
 
                         if let Some(payload) = payload {
                             self.app_message_sender
@@ -747,8 +803,9 @@ impl LspServerProcess {
                         }
                     }
                     "textDocument/references" => {
-                        let payload: <lsp_request!("textDocument/references") as Request>::Result =
+                        let mut payload: <lsp_request!("textDocument/references") as Request>::Result =
                             serde_json::from_value(response)?;
+                        self.convert_position_characters(&mut payload, path.as_ref(), false);
 
                         if let Some(payload) = payload {
                             self.app_message_sender
@@ -765,8 +822,9 @@ impl LspServerProcess {
                         }
                     }
                     "textDocument/declaration" => {
-                        let payload: <lsp_request!("textDocument/declaration") as Request>::Result =
+                        let mut payload: <lsp_request!("textDocument/declaration") as Request>::Result =
                             serde_json::from_value(response)?;
+                        self.convert_position_characters(&mut payload, path.as_ref(), false);
 
                         if let Some(payload) = payload {
                             self.app_message_sender
@@ -780,8 +838,9 @@ impl LspServerProcess {
                         }
                     }
                     "textDocument/typeDefinition" => {
-                        let payload: <lsp_request!("textDocument/typeDefinition") as Request>::Result =
+                        let mut payload: <lsp_request!("textDocument/typeDefinition") as Request>::Result =
                             serde_json::from_value(response)?;
+                        self.convert_position_characters(&mut payload, path.as_ref(), false);
 
                         if let Some(payload) = payload {
                             self.app_message_sender
@@ -795,8 +854,9 @@ impl LspServerProcess {
                         }
                     }
                     "textDocument/implementation" => {
-                        let payload: <lsp_request!("textDocument/implementation") as Request>::Result =
+                        let mut payload: <lsp_request!("textDocument/implementation") as Request>::Result =
                             serde_json::from_value(response)?;
+                        self.convert_position_characters(&mut payload, path.as_ref(), false);
 
                         if let Some(payload) = payload {
                             self.app_message_sender
@@ -810,8 +870,9 @@ impl LspServerProcess {
                         }
                     }
                     "textDocument/prepareRename" => {
-                        let payload: <lsp_request!("textDocument/prepareRename") as Request>::Result =
+                        let mut payload: <lsp_request!("textDocument/prepareRename") as Request>::Result =
                             serde_json::from_value(response)?;
+                        self.convert_position_characters(&mut payload, path.as_ref(), false);
 
                         if let Some(payload) = payload {
                             self.app_message_sender
@@ -822,8 +883,9 @@ impl LspServerProcess {
                         }
                     }
                     "textDocument/rename" => {
-                        let payload: <lsp_request!("textDocument/rename") as Request>::Result =
+                        let mut payload: <lsp_request!("textDocument/rename") as Request>::Result =
                             serde_json::from_value(response)?;
+                        self.convert_position_characters(&mut payload, path.as_ref(), false);
 
                         if let Some(payload) = payload {
                             self.app_message_sender
@@ -834,8 +896,9 @@ impl LspServerProcess {
                         }
                     }
                     "textDocument/codeAction" => {
-                        let payload: <lsp_request!("textDocument/codeAction") as Request>::Result =
+                        let mut payload: <lsp_request!("textDocument/codeAction") as Request>::Result =
                             serde_json::from_value(response)?;
+                        self.convert_position_characters(&mut payload, path.as_ref(), false);
 
                         if let Some(payload) = payload {
                             self.app_message_sender
@@ -868,8 +931,9 @@ impl LspServerProcess {
                             .unwrap();
                     }
                     "textDocument/documentSymbol" => {
-                        let payload: <lsp_request!("textDocument/documentSymbol") as Request>::Result =
+                        let mut payload: <lsp_request!("textDocument/documentSymbol") as Request>::Result =
                             serde_json::from_value(response)?;
+                        self.convert_position_characters(&mut payload, path.as_ref(), false);
 
                         if let Some(payload) = payload {
                             if let Some(path) = path {
@@ -886,8 +950,9 @@ impl LspServerProcess {
                         }
                     }
                     "completionItem/resolve" => {
-                        let payload: <lsp_request!("completionItem/resolve") as Request>::Result =
+                        let mut payload: <lsp_request!("completionItem/resolve") as Request>::Result =
                             serde_json::from_value(response)?;
+                        self.convert_position_characters(&mut payload, path.as_ref(), false);
 
                         self.app_message_sender
                             .send(AppMessage::LspNotification(Box::new(
@@ -896,8 +961,9 @@ impl LspServerProcess {
                             .unwrap();
                     }
                     "workspace/symbol" => {
-                        let payload: <lsp_request!("workspace/symbol") as Request>::Result =
+                        let mut payload: <lsp_request!("workspace/symbol") as Request>::Result =
                             serde_json::from_value(response)?;
+                        self.convert_position_characters(&mut payload, path.as_ref(), false);
 
                         if let Some(workspace_symbol_response) = payload {
                             let symbols = Symbols::try_from_workspace_symbol_response(
@@ -936,8 +1002,9 @@ impl LspServerProcess {
                 log::info!("LspServerProcess::handle_notification: {}", method.as_str());
                 match method.as_str() {
                     "textDocument/publishDiagnostics" => {
-                        let params: <lsp_notification!("textDocument/publishDiagnostics") as Notification>::Params =
+                        let mut params: <lsp_notification!("textDocument/publishDiagnostics") as Notification>::Params =
                             serde_json::from_value(request.params.ok_or_else(|| anyhow::anyhow!("Missing params"))?)?;
+                        self.convert_position_characters(&mut params, None, false);
 
                         self.app_message_sender
                             .send(AppMessage::LspNotification(Box::new(
@@ -946,8 +1013,9 @@ impl LspServerProcess {
                             .unwrap();
                     }
                     "workspace/applyEdit" => {
-                        let params: <lsp_request!("workspace/applyEdit") as Request>::Params =
+                        let mut params: <lsp_request!("workspace/applyEdit") as Request>::Params =
                             serde_json::from_value(request.params.unwrap())?;
+                        self.convert_position_characters(&mut params, None, false);
 
                         self.app_message_sender
                             .send(AppMessage::LspNotification(Box::new(
@@ -955,6 +1023,26 @@ impl LspServerProcess {
                             )))
                             .unwrap();
                     }
+                    "client/registerCapability" => {
+                        let params: <lsp_request!("client/registerCapability") as Request>::Params =
+                            serde_json::from_value(
+                                request
+                                    .params
+                                    .ok_or_else(|| anyhow::anyhow!("Missing params"))?,
+                            )?;
+                        self.file_watchers.register(&params.registrations);
+                        log::info!(
+                            "LspServerProcess: watching {:?}",
+                            self.file_watchers.globs()
+                        );
+                        self.send_reply(request.id, serde_json::Value::Null)?;
+                    }
+                    "client/unregisterCapability" => {
+                        let params: <lsp_request!("client/unregisterCapability") as Request>::Params =
+                            serde_json::from_value(request.params.ok_or_else(|| anyhow::anyhow!("Missing params"))?)?;
+                        self.file_watchers.unregister(&params.unregisterations);
+                        self.send_reply(request.id, serde_json::Value::Null)?;
+                    }
                     "workspace/configuration" => {
                         // Just return null for now, since I don't know how how to handle this properly
                         // This reply is necessary for Graphql LSP to work
@@ -987,7 +1075,8 @@ impl LspServerProcess {
     }
 
     fn trigger_characters(&self) -> Vec<String> {
-        self.server_capabilities
+        let server_trigger_characters = self
+            .server_capabilities
             .as_ref()
             .and_then(|capabilities| {
                 capabilities
@@ -995,7 +1084,11 @@ impl LspServerProcess {
                     .as_ref()
                     .and_then(|provider| provider.trigger_characters.clone())
             })
-            .unwrap_or_default()
+            .unwrap_or_default();
+        merge_trigger_characters(
+            server_trigger_characters,
+            self.language.lsp_extra_trigger_characters(),
+        )
     }
 
     pub fn shutdown(&mut self) -> anyhow::Result<()> {
@@ -1060,11 +1153,12 @@ impl LspServerProcess {
         &mut self,
         context: ResponseContext,
         path: Option<CanonicalizedPath>,
-        params: R::Params,
+        mut params: R::Params,
     ) -> anyhow::Result<()>
     where
-        R::Params: serde::Serialize,
+        R::Params: serde::Serialize + ConvertPositions,
     {
+        self.convert_position_characters(&mut params, path.as_ref(), true);
         let id = {
             let result = self.next_request_id;
             self.next_request_id += 1;
@@ -1078,17 +1172,101 @@ impl LspServerProcess {
             id: Some(json_rpc_types::Id::Num(id)),
         };
 
-        self.send_json(&request)?;
-
-        self.pending_response_requests.insert(
+        let request = serde_json::to_value(&request)?;
+        let queued_request = QueuedRequest {
             id,
-            PendingResponseRequest {
+            request,
+            pending_response_request: PendingResponseRequest {
                 context,
                 method: R::METHOD.to_string(),
                 path,
             },
-        );
+        };
+
+        // Lifecycle requests are never held back by `lsp_max_concurrent_requests`,
+        // because, for example, the process loop exits right after sending `shutdown`.
+        if matches!(R::METHOD, "initialize" | "shutdown") {
+            return self.dispatch_request(queued_request);
+        }
+        self.queued_requests.push_back(queued_request);
+        self.dispatch_queued_requests()
+    }
+
+    /// Converts the `character` of every position in `value` between the char columns of Ki
+    /// and the `position_encoding` of the server, where `path` is the document of the positions
+    /// that are not in a document of their own, e.g. the text edits of a completion.
+    ///
+    /// Each document is read at most once per `value`, and not at all for positions at column 0,
+    /// which is the same in every encoding.
+    fn convert_position_characters(
+        &self,
+        value: &mut impl ConvertPositions,
+        path: Option<&CanonicalizedPath>,
+        to_server: bool,
+    ) {
+        let encoding = self.position_encoding;
+        // The char columns of Ki are code points
+        if encoding == PositionEncoding::Utf32 {
+            return;
+        }
+        let uri = path.and_then(|path| path_buf_to_url(path.clone()).ok());
+        let mut documents: HashMap<Url, Option<Rope>> = HashMap::new();
+        value.convert_positions(uri.as_ref(), &mut |uri, line, character| {
+            if character == 0 {
+                return 0;
+            }
+            let document = documents
+                .entry(uri.clone())
+                .or_insert_with(|| self.document(uri));
+            let Some(line) = document
+                .as_ref()
+                .and_then(|document| document.get_line(line as usize))
+            else {
+                return character;
+            };
+            let line = Cow::from(line);
+            if to_server {
+                encoding.char_column_to_character(&line, character as usize)
+            } else {
+                encoding.character_to_char_column(&line, character) as u32
+            }
+        });
+    }
+
+    /// The content of the document at `uri`, which is read from the disk if not opened.
+    fn document(&self, uri: &Url) -> Option<Rope> {
+        let path: CanonicalizedPath = uri.to_file_path().ok()?.try_into().ok()?;
+        self.documents
+            .get(&path)
+            .cloned()
+            .or_else(|| Some(Rope::from_str(&path.read().ok()?)))
+    }
 
+    fn dispatch_request(&mut self, queued_request: QueuedRequest) -> anyhow::Result<()> {
+        let QueuedRequest {
+            id,
+            request,
+            pending_response_request,
+        } = queued_request;
+        self.send_json(&request)?;
+        self.pending_response_requests
+            .insert(id, pending_response_request);
+        Ok(())
+    }
+
+    /// Send the queued requests while there are fewer than `lsp_max_concurrent_requests`
+    /// requests awaiting a response.
+    fn dispatch_queued_requests(&mut self) -> anyhow::Result<()> {
+        while self
+            .language
+            .lsp_max_concurrent_requests()
+            .is_none_or(|max| self.pending_response_requests.len() < max.max(1))
+        {
+            let Some(queued_request) = self.queued_requests.pop_front() else {
+                break;
+            };
+            self.dispatch_request(queued_request)?;
+        }
         Ok(())
     }
 
@@ -1099,6 +1277,8 @@ impl LspServerProcess {
         version: usize,
         content: String,
     ) -> Result<(), anyhow::Error> {
+        self.documents
+            .insert(file_path.clone(), Rope::from_str(&content));
         self.send_notification::<lsp_notification!("textDocument/didOpen")>(
             DidOpenTextDocumentParams {
                 text_document: TextDocumentItem {
@@ -1117,6 +1297,8 @@ impl LspServerProcess {
         version: i32,
         content: String,
     ) -> Result<(), anyhow::Error> {
+        self.documents
+            .insert(file_path.clone(), Rope::from_str(&content));
         self.send_notification::<lsp_notification!("textDocument/didChange")>(
             DidChangeTextDocumentParams {
                 text_document: VersionedTextDocumentIdentifier {
@@ -1136,14 +1318,30 @@ impl LspServerProcess {
         &mut self,
         file_path: CanonicalizedPath,
     ) -> Result<(), anyhow::Error> {
+        let include_text = self
+            .language
+            .lsp_did_save_include_text(self.server_did_save_include_text());
         self.send_notification::<lsp_notification!("textDocument/didSave")>(
-            DidSaveTextDocumentParams {
-                text_document: path_buf_to_text_document_identifier(file_path)?,
-                text: None,
-            },
+            did_save_text_document_params(file_path, include_text)?,
         )
     }
 
+    fn server_did_save_include_text(&self) -> bool {
+        self.has_capability(|capabilities| {
+            matches!(
+                &capabilities.text_document_sync,
+                Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                            include_text: Some(true),
+                        })),
+                        ..
+                    }
+                ))
+            )
+        })
+    }
+
     fn workspace_did_rename_files(
         &mut self,
         old: CanonicalizedPath,
@@ -1391,11 +1589,7 @@ impl LspServerProcess {
             params.context,
             Some(params.path.clone()),
             CodeActionParams {
-                context: CodeActionContext {
-                    diagnostics,
-                    trigger_kind: None,
-                    only: None,
-                },
+                context: code_action_context(diagnostics, self.language.lsp_code_action_kinds()),
                 partial_result_params: Default::default(),
                 range: Range {
                     start: params.position.into(),
@@ -1672,6 +1866,42 @@ impl ErrorTracker {
     }
 }
 
+/// The file has just been saved, so its content on disk is the text of the document.
+fn did_save_text_document_params(
+    file_path: CanonicalizedPath,
+    include_text: bool,
+) -> anyhow::Result<DidSaveTextDocumentParams> {
+    Ok(DidSaveTextDocumentParams {
+        text: if include_text {
+            Some(file_path.read()?)
+        } else {
+            None
+        },
+        text_document: path_buf_to_text_document_identifier(file_path)?,
+    })
+}
+
+/// The trigger characters advertised by the server, followed by the `extra` ones it lacks.
+fn merge_trigger_characters(server: Vec<String>, extra: &[String]) -> Vec<String> {
+    server
+        .into_iter()
+        .chain(extra.iter().cloned())
+        .unique()
+        .collect()
+}
+
+/// `kinds` limits the requested code actions, or all kinds are requested if it is `None`.
+fn code_action_context(
+    diagnostics: Vec<Diagnostic>,
+    kinds: Option<Vec<String>>,
+) -> CodeActionContext {
+    CodeActionContext {
+        diagnostics,
+        trigger_kind: None,
+        only: kinds.map(|kinds| kinds.into_iter().map(CodeActionKind::from).collect()),
+    }
+}
+
 #[cfg(test)]
 mod test_lsp_server_process {
     use super::*;
@@ -1701,9 +1931,13 @@ mod test_lsp_server_process {
             stdout: Some(stdout),
             stderr: Some(stderr),
             server_capabilities: None,
+            position_encoding: PositionEncoding::default(),
+            documents: HashMap::new(),
+            file_watchers: LspFileWatchers::default(),
             current_working_directory: std::env::current_dir()?.try_into()?,
             next_request_id: 0,
             pending_response_requests: HashMap::new(),
+            queued_requests: VecDeque::new(),
             app_message_sender: app_sender.clone(),
             sender,
         };
@@ -1735,4 +1969,232 @@ mod test_lsp_server_process {
         );
         Ok(())
     }
+
+    #[test]
+    fn did_save_includes_text_when_the_language_requests_it() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("main.rs");
+        std::fs::write(&path, "fn main() {}\n")?;
+        let path: CanonicalizedPath = path.try_into()?;
+        let language: Language = serde_json::from_value(serde_json::json!({
+            "extensions": ["rs"],
+            "file_names": [],
+            "lsp_command": {
+                "command": { "command": "rust-analyzer", "arguments": [] },
+                "lsp_save_options": { "include_text": true }
+            }
+        }))?;
+
+        // The language overrides the server, which did not ask for the text
+        let params =
+            did_save_text_document_params(path.clone(), language.lsp_did_save_include_text(false))?;
+        assert_eq!(params.text.as_deref(), Some("fn main() {}\n"));
+
+        // Otherwise the server decides
+        let language = Language::default();
+        let params =
+            did_save_text_document_params(path.clone(), language.lsp_did_save_include_text(false))?;
+        assert_eq!(params.text, None);
+        let params = did_save_text_document_params(path, language.lsp_did_save_include_text(true))?;
+        assert!(params.text.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn code_action_request_only_has_the_configured_kinds() -> anyhow::Result<()> {
+        let language: Language = serde_json::from_value(serde_json::json!({
+            "extensions": ["rs"],
+            "file_names": [],
+            "lsp_command": {
+                "command": { "command": "rust-analyzer", "arguments": [] },
+                "lsp_code_action_kinds": ["quickfix", "refactor.extract"]
+            }
+        }))?;
+        let context = code_action_context(Vec::new(), language.lsp_code_action_kinds());
+        assert_eq!(
+            context.only,
+            Some(vec![
+                CodeActionKind::QUICKFIX,
+                CodeActionKind::REFACTOR_EXTRACT
+            ])
+        );
+
+        let context = code_action_context(Vec::new(), Language::default().lsp_code_action_kinds());
+        assert_eq!(context.only, None);
+        Ok(())
+    }
+
+    #[test]
+    fn extra_trigger_characters_are_merged_with_those_of_the_server() -> anyhow::Result<()> {
+        let language: Language = serde_json::from_value(serde_json::json!({
+            "extensions": ["rs"],
+            "file_names": [],
+            "lsp_command": {
+                "command": { "command": "rust-analyzer", "arguments": [] },
+                "lsp_extra_trigger_characters": [":", "."]
+            }
+        }))?;
+        assert_eq!(
+            merge_trigger_characters(
+                vec![".".to_string(), "(".to_string()],
+                language.lsp_extra_trigger_characters()
+            ),
+            [".", "(", ":"]
+        );
+        assert_eq!(
+            merge_trigger_characters(
+                vec![".".to_string()],
+                Language::default().lsp_extra_trigger_characters()
+            ),
+            ["."]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn requests_beyond_lsp_max_concurrent_requests_are_queued() -> anyhow::Result<()> {
+        let (app_sender, _app_receiver) = mpsc::channel();
+        let (sender, _receiver) = mpsc::channel();
+
+        // A mock server that never responds
+        let mut process = Command::new("sh")
+            .args(["-c", "cat > /dev/null"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()?;
+
+        let language: Language = serde_json::from_value(serde_json::json!({
+            "extensions": ["rs"],
+            "file_names": [],
+            "lsp_command": {
+                "command": { "command": "rust-analyzer", "arguments": [] },
+                "lsp_max_concurrent_requests": 2
+            }
+        }))?;
+        let mut lsp_process = LspServerProcess {
+            language,
+            stdin: process.stdin.take().unwrap(),
+            stdout: process.stdout.take(),
+            stderr: process.stderr.take(),
+            server_capabilities: None,
+            position_encoding: PositionEncoding::default(),
+            documents: HashMap::new(),
+            file_watchers: LspFileWatchers::default(),
+            current_working_directory: std::env::current_dir()?.try_into()?,
+            next_request_id: 0,
+            pending_response_requests: HashMap::new(),
+            queued_requests: VecDeque::new(),
+            app_message_sender: app_sender,
+            sender,
+        };
+
+        for _ in 0..3 {
+            lsp_process.send_request::<lsp_request!("workspace/symbol")>(
+                ResponseContext::default(),
+                None,
+                WorkspaceSymbolParams {
+                    partial_result_params: Default::default(),
+                    work_done_progress_params: Default::default(),
+                    query: "foo".to_string(),
+                },
+            )?;
+        }
+        let in_flight = |lsp_process: &LspServerProcess| {
+            lsp_process
+                .pending_response_requests
+                .keys()
+                .copied()
+                .sorted()
+                .collect_vec()
+        };
+        assert_eq!(in_flight(&lsp_process), [0, 1]);
+        assert_eq!(lsp_process.queued_requests.len(), 1);
+
+        // A response to the request 0 frees up a slot for the queued request
+        lsp_process.pending_response_requests.remove(&0);
+        lsp_process.dispatch_queued_requests()?;
+        assert_eq!(in_flight(&lsp_process), [1, 2]);
+        assert!(lsp_process.queued_requests.is_empty());
+
+        // `shutdown` is sent even when the server is at capacity
+        lsp_process.shutdown()?;
+        assert_eq!(in_flight(&lsp_process), [1, 2, 3]);
+        assert!(lsp_process.queued_requests.is_empty());
+
+        process.kill()?;
+        process.wait()?;
+        Ok(())
+    }
+
+    #[test]
+    fn positions_are_converted_with_the_negotiated_position_encoding() -> anyhow::Result<()> {
+        let (app_sender, _app_receiver) = mpsc::channel();
+        let (sender, _receiver) = mpsc::channel();
+        let mut process = Command::new("sh")
+            .args(["-c", "cat > /dev/null"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()?;
+
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("main.rs");
+        std::fs::write(&path, "let 😀 = b;\n")?;
+        let path: CanonicalizedPath = path.try_into()?;
+        let uri = path_buf_to_url(path.clone())?;
+
+        let mut lsp_process = LspServerProcess {
+            language: Language::default(),
+            stdin: process.stdin.take().unwrap(),
+            stdout: process.stdout.take(),
+            stderr: process.stderr.take(),
+            server_capabilities: None,
+            position_encoding: PositionEncoding::Utf16,
+            documents: HashMap::new(),
+            file_watchers: LspFileWatchers::default(),
+            current_working_directory: std::env::current_dir()?.try_into()?,
+            next_request_id: 0,
+            pending_response_requests: HashMap::new(),
+            queued_requests: VecDeque::new(),
+            app_message_sender: app_sender,
+            sender,
+        };
+
+        // `b` is at the char column 8, after the emoji of 2 UTF-16 code units
+        let mut params = TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            position: Position::new(0, 8),
+        };
+        lsp_process.convert_position_characters(&mut params, None, true);
+        assert_eq!(params.position.character, 9);
+
+        // The edits of a workspace edit are converted with the document they are keyed by
+        let mut edit = lsp_types::WorkspaceEdit::new(HashMap::from([(
+            uri.clone(),
+            vec![TextEdit::new(
+                Range::new(Position::new(0, 9), Position::new(0, 10)),
+                "c".to_string(),
+            )],
+        )]));
+        lsp_process.convert_position_characters(&mut edit, None, false);
+        let range = edit.changes.unwrap()[&uri][0].range;
+        assert_eq!(range, Range::new(Position::new(0, 8), Position::new(0, 9)));
+
+        // The emoji is 4 bytes in UTF-8, and the opened content takes precedence over the disk
+        lsp_process.position_encoding = PositionEncoding::Utf8;
+        lsp_process.text_document_did_open(
+            path.clone(),
+            "rust".to_string(),
+            1,
+            "😀 = b;\n".to_string(),
+        )?;
+        let mut position = Position::new(0, 4);
+        lsp_process.convert_position_characters(&mut position, Some(&path), true);
+        assert_eq!(position.character, 7);
+
+        process.kill()?;
+        process.wait()?;
+        Ok(())
+    }
 }
@@ -90,10 +90,6 @@ fn is_word(char: char) -> bool {
     char.is_alphanumeric() || char == '_' || char == '-'
 }
 
-fn is_symbol(char: char) -> bool {
-    !is_word(char) && !char.is_whitespace()
-}
-
 fn get_current_word_by_cursor(
     skip_symbols: bool,
     buffer: &crate::buffer::Buffer,
@@ -104,6 +100,14 @@ fn get_current_word_by_cursor(
         return Ok(None);
     };
 
+    let language = buffer.language();
+    let word_chars = language
+        .as_ref()
+        .map(|language| language.word_chars())
+        .unwrap_or_default();
+    let is_word = |char: char| is_word(char) || word_chars.contains(char);
+    let is_symbol = |char: char| !is_word(char) && !char.is_whitespace();
+
     let is_target = |char: char| {
         if skip_symbols {
             is_word(char)
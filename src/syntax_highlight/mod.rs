@@ -2,7 +2,8 @@
 mod test;
 
 use std::{
-    collections::HashMap,
+    cell::Cell,
+    collections::{HashMap, HashSet},
     ops::Range,
     sync::{atomic::AtomicUsize, mpsc::Sender},
     time::Duration,
@@ -42,7 +43,7 @@ impl GetHighlightConfig for Language {
             tree_sitter_language,
             "highlight".to_string(),
             highlights_query,
-            self.injection_query().unwrap_or_default(),
+            &self.injection_query().unwrap_or_default(),
             self.locals_query().unwrap_or_default(),
         )?;
 
@@ -53,43 +54,86 @@ impl GetHighlightConfig for Language {
 }
 
 pub trait Highlight {
-    fn highlight(
-        &self,
+    fn highlight<'a>(
+        &'a self,
         source_code: &str,
         cancellation_flag: &AtomicUsize,
+        injection_callback: impl FnMut(&str) -> Option<&'a HighlightConfiguration>,
     ) -> anyhow::Result<HighlightedSpans>;
 }
 
 impl Highlight for HighlightConfiguration {
-    fn highlight(
-        &self,
+    fn highlight<'a>(
+        &'a self,
         source_code: &str,
         cancellation_flag: &AtomicUsize,
+        mut injection_callback: impl FnMut(&str) -> Option<&'a HighlightConfiguration>,
     ) -> anyhow::Result<HighlightedSpans> {
         let mut highlighter = Highlighter::new();
 
+        let injected = Cell::new(false);
         let highlights = highlighter.highlight(
             self,
             source_code.as_bytes(),
             Some(cancellation_flag),
-            |_| None,
+            |name| {
+                let config = injection_callback(name);
+                injected.set(injected.get() || config.is_some());
+                config
+            },
         )?;
 
-        let mut highlight = None;
+        // The highlights are offset by the layer tag of their configuration,
+        // see `tagged_highlight_names`.
+        let highlight_names_count = crate::themes::highlight_names().len();
+        let layer_tag =
+            |highlight: &tree_sitter_highlight::Highlight| highlight.0 / highlight_names_count;
+
+        let mut highlights_stack = Vec::new();
+
+        // The highlights started at the current offset after the latest injection,
+        // because the highlights started before it enclose the injected layer.
+        let mut run_start = 0;
+
+        // The highlight started by the previous event, if any,
+        // because an empty highlight ends right after it starts.
+        let mut last_started = None;
 
         let mut highlighted_spans = vec![];
 
         for event in highlights {
-            match event? {
+            let event = event?;
+            if injected.take() {
+                run_start = highlights_stack.len();
+            }
+            match event {
                 HighlightEvent::HighlightStart(s) => {
-                    highlight = Some(s);
+                    // At the same offset, tree-sitter-highlight starts the highlights of the
+                    // injected layers before those of the enclosing layer,
+                    // e.g. the YAML string that is the content of an injected Bash command,
+                    // so the highlights of the enclosing layer are nested beneath them,
+                    // unless the layers have the same language, which cannot be told apart.
+                    let index = highlights_stack[run_start..]
+                        .iter()
+                        .rposition(|highlight| layer_tag(highlight) == layer_tag(&s))
+                        .map_or(run_start, |index| run_start + index + 1);
+                    highlights_stack.insert(index, s);
+                    last_started = Some(index);
                 }
                 HighlightEvent::HighlightEnd => {
-                    highlight = None;
+                    match last_started.take() {
+                        Some(index) => Some(highlights_stack.remove(index)),
+                        None => highlights_stack.pop(),
+                    };
+                    run_start = highlights_stack.len();
                 }
                 HighlightEvent::Source { start, end } => {
-                    if let Some(highlight) = highlight {
-                        let style_key = StyleKey::Syntax(IndexedHighlightGroup::new(highlight.0));
+                    last_started = None;
+                    run_start = highlights_stack.len();
+                    if let Some(highlight) = highlights_stack.last() {
+                        let style_key = StyleKey::Syntax(IndexedHighlightGroup::new(
+                            highlight.0 % highlight_names_count,
+                        ));
                         highlighted_spans.push(HighlightedSpan {
                             byte_range: start..end,
                             style_key,
@@ -219,16 +263,78 @@ pub fn start_thread(callback: Sender<AppMessage>) -> Sender<SyntaxHighlightReque
 
     sender
 }
-type TreeSitterGrammarId = String;
+/// See `Language::highlight_config_id`.
+type HighlightConfigId = String;
+
+/// The grammar ID that a highlight config ID starts with, see `Language::highlight_config_id`.
+fn grammar_id_of(highlight_config_id: &str) -> &str {
+    highlight_config_id
+        .split(['+', ':'])
+        .next()
+        .unwrap_or(highlight_config_id)
+}
+
+/// The highlight names offset by `layer_tag` times the number of highlight names,
+/// so that the highlights of the configurations loaded together can be told apart
+/// when they are injected into one another, see `impl Highlight for HighlightConfiguration`.
+fn tagged_highlight_names(layer_tag: usize) -> Vec<&'static str> {
+    let highlight_names = crate::themes::highlight_names();
+    std::iter::repeat_n("", layer_tag * highlight_names.len())
+        .chain(highlight_names.iter().copied())
+        .collect()
+}
+
 /// We have to cache the highlight configurations because they load slowly.
+///
+/// Languages without highlight configuration are cached as `None`,
+/// so that we do not attempt to load them repeatedly.
+///
+/// The configurations of the grammars invalidated by `config::reload_languages`
+/// or `config::invalidate_grammar_caches` are evicted.
 #[derive(Default)]
 pub struct HighlightConfigs(
-    HashMap<TreeSitterGrammarId, tree_sitter_highlight::HighlightConfiguration>,
+    HashMap<HighlightConfigId, Option<(usize, tree_sitter_highlight::HighlightConfiguration)>>,
+    /// The `config::languages_generation` of the cached configurations.
+    usize,
 );
 
 impl HighlightConfigs {
     pub fn new() -> Self {
-        Self::default()
+        Self(Default::default(), crate::config::languages_generation())
+    }
+
+    fn invalidate_if_languages_reloaded(&mut self) {
+        let languages_generation = crate::config::languages_generation();
+        if self.1 != languages_generation {
+            let invalidated_grammar_ids = crate::config::invalidated_grammar_ids_since(self.1);
+            self.0.retain(|highlight_config_id, _| {
+                !invalidated_grammar_ids.contains(grammar_id_of(highlight_config_id))
+            });
+            self.1 = languages_generation;
+        }
+    }
+
+    fn load(
+        &mut self,
+        highlight_config_id: &HighlightConfigId,
+        language: &Language,
+    ) -> anyhow::Result<()> {
+        if !self.0.contains_key(highlight_config_id) {
+            let layer_tag = (0..)
+                .find(|layer_tag| {
+                    !self
+                        .0
+                        .values()
+                        .any(|config| matches!(config, Some((tag, _)) if tag == layer_tag))
+                })
+                .unwrap_or_default();
+            let config = language.get_highlight_config()?.map(|mut config| {
+                config.configure(&tagged_highlight_names(layer_tag));
+                (layer_tag, config)
+            });
+            self.0.insert(highlight_config_id.clone(), config);
+        }
+        Ok(())
     }
 
     pub fn highlight(
@@ -237,23 +343,72 @@ impl HighlightConfigs {
         source_code: &str,
         cancellation_flag: &AtomicUsize,
     ) -> Result<HighlightedSpans, anyhow::Error> {
-        let Some(grammar_id) = language.tree_sitter_grammar_id() else {
+        self.highlight_with_max_injection_depth(
+            language,
+            source_code,
+            cancellation_flag,
+            crate::config::AppConfig::singleton().max_injection_depth(),
+        )
+    }
+
+    /// Injected languages that are only reachable through more than `max_injection_depth`
+    /// levels of injections are not highlighted, so that the cost of highlighting is bounded.
+    ///
+    /// The injection callback is only given the name of the injected language,
+    /// so the depth of a language is the level at which it is first injected,
+    /// and it is also highlighted where it is injected again more deeply,
+    /// e.g. Rust code blocks in the Markdown of Rust doc comments in Markdown.
+    fn highlight_with_max_injection_depth(
+        &mut self,
+        language: Language,
+        source_code: &str,
+        cancellation_flag: &AtomicUsize,
+        max_injection_depth: usize,
+    ) -> Result<HighlightedSpans, anyhow::Error> {
+        let language = language.for_content(source_code);
+        let Some(highlight_config_id) = language.highlight_config_id() else {
             return Ok(Default::default());
         };
-        let config = match self.0.get(&grammar_id) {
-            Some(config) => config,
-            None => {
-                if let Some(highlight_config) = language.get_highlight_config()? {
-                    self.0.insert(grammar_id.clone(), highlight_config);
-                    let get_error = || {
-                        anyhow::anyhow!("Unreachable: should be able to obtain a value that is inserted to the HashMap")
-                    };
-                    self.0.get(&grammar_id).ok_or_else(get_error)?
+        self.invalidate_if_languages_reloaded();
+        self.load(&highlight_config_id, &language)?;
+
+        // The languages that can be injected in this highlight.
+        // It is tracked separately from the cache, because the cache
+        // might contain languages loaded by other highlights.
+        let mut injectable_config_ids = HashSet::new();
+        let mut depth = 0;
+        loop {
+            let Some(Some((_, config))) = self.0.get(&highlight_config_id) else {
+                return Ok(Default::default());
+            };
+
+            // The injected languages are discovered one level deeper after each pass,
+            // so we collect the injected languages that are not injectable yet,
+            // and highlight again after loading them, unless they are too deep,
+            // in which case no config is returned, and they are left to the enclosing layer.
+            let mut new_injections = Vec::new();
+            let highlighted_spans = config.highlight(source_code, cancellation_flag, |name| {
+                let language = crate::config::from_injection_language(name)?;
+                let highlight_config_id = language.highlight_config_id()?;
+                if injectable_config_ids.contains(&highlight_config_id) {
+                    self.0
+                        .get(&highlight_config_id)?
+                        .as_ref()
+                        .map(|(_, config)| config)
                 } else {
-                    return Ok(Default::default());
+                    new_injections.push((highlight_config_id, language));
+                    None
                 }
+            })?;
+
+            if new_injections.is_empty() || depth >= max_injection_depth {
+                return Ok(highlighted_spans);
             }
-        };
-        config.highlight(source_code, cancellation_flag)
+            for (highlight_config_id, language) in new_injections {
+                self.load(&highlight_config_id, &language)?;
+                injectable_config_ids.insert(highlight_config_id);
+            }
+            depth += 1;
+        }
     }
 }
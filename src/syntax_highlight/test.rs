@@ -1,5 +1,6 @@
 use lazy_regex::regex;
 use my_proc_macros::key;
+use shared::language::Language;
 
 use crate::{
     app::{Dimension, Dispatch::*},
@@ -49,3 +50,441 @@ fn syntax_highlight_json() -> anyhow::Result<()> {
         ])
     })
 }
+
+/// Returns the highlight name of the span containing the first occurrence of `text`.
+fn highlight_name_at(
+    extension: &str,
+    source_code: &str,
+    text: &str,
+) -> anyhow::Result<Option<&'static str>> {
+    highlight_name_at_with_max_injection_depth(extension, source_code, text, 4)
+}
+
+fn highlight_name_at_with_max_injection_depth(
+    extension: &str,
+    source_code: &str,
+    text: &str,
+    max_injection_depth: usize,
+) -> anyhow::Result<Option<&'static str>> {
+    highlight_name_in(
+        crate::config::from_extension(extension).unwrap(),
+        source_code,
+        text,
+        max_injection_depth,
+    )
+}
+
+fn highlight_name_in(
+    language: Language,
+    source_code: &str,
+    text: &str,
+    max_injection_depth: usize,
+) -> anyhow::Result<Option<&'static str>> {
+    let highlighted_spans = super::HighlightConfigs::new().highlight_with_max_injection_depth(
+        language,
+        source_code,
+        &std::sync::atomic::AtomicUsize::new(0),
+        max_injection_depth,
+    )?;
+    let start = source_code.find(text).unwrap();
+    Ok(highlighted_spans
+        .0
+        .into_iter()
+        .find(|span| span.byte_range.contains(&start))
+        .and_then(|span| match span.style_key {
+            StyleKey::Syntax(group) => group.to_highlight_name().map(|name| name.into()),
+            _ => None,
+        }))
+}
+
+#[test]
+fn yaml_run_key_injects_bash() -> anyhow::Result<()> {
+    let source_code = "steps:\n  - run: echo hi\n  - name: echo hi\n";
+    let highlight_name = highlight_name_at("yaml", source_code, "echo")?.unwrap();
+    assert!(highlight_name.starts_with("function"), "{highlight_name}");
+
+    // Values of other keys are not injected
+    let name_value = &source_code[source_code.find("name").unwrap()..];
+    assert_eq!(
+        highlight_name_at("yaml", name_value, "echo")?,
+        Some("string")
+    );
+    Ok(())
+}
+
+#[test]
+fn markdown_lua_fence_injects_lua() -> anyhow::Result<()> {
+    let source_code = "# Config\n\n```lua\nlocal x = 1\n```\n";
+    let highlight_name = highlight_name_at("md", source_code, "local")?.unwrap();
+    assert!(highlight_name.starts_with("keyword"), "{highlight_name}");
+    Ok(())
+}
+
+#[test]
+fn dockerfile_run_injects_bash() -> anyhow::Result<()> {
+    let dockerfile = crate::config::languages()
+        .get("dockerfile")
+        .unwrap()
+        .clone();
+    // The grammar is `FromSource`, which is only available after `ki @ grammar build`
+    if dockerfile.tree_sitter_language().is_none() {
+        return Ok(());
+    }
+    let source_code = "FROM alpine\nRUN echo hi\n";
+    let highlight_name = highlight_name_in(dockerfile, source_code, "echo", 4)?.unwrap();
+    assert!(highlight_name.starts_with("function"), "{highlight_name}");
+    Ok(())
+}
+
+#[test]
+fn vue_script_with_ts_lang_injects_typescript() -> anyhow::Result<()> {
+    // The grammar is `FromSource`, which is only available after `ki @ grammar build`
+    if crate::config::from_extension("vue")
+        .and_then(|language| language.tree_sitter_language())
+        .is_none()
+    {
+        return Ok(());
+    }
+    // `number` is only a type in TypeScript
+    let source_code = "<script lang=\"ts\">\nlet count: number = 0;\n</script>\n";
+    let highlight_name = highlight_name_at("vue", source_code, "number")?.unwrap();
+    assert!(highlight_name.starts_with("type"), "{highlight_name}");
+    Ok(())
+}
+
+#[test]
+fn erb_injects_ruby_into_code_and_html_into_content() -> anyhow::Result<()> {
+    let source_code =
+        "<ul>\n<% items.each do |item| %>\n  <li><%= item %></li>\n<% end %>\n</ul>\n";
+    let highlight_name = highlight_name_at("erb", source_code, "do")?.unwrap();
+    assert!(highlight_name.starts_with("keyword"), "{highlight_name}");
+    let highlight_name = highlight_name_at("erb", source_code, "li")?.unwrap();
+    assert!(highlight_name.starts_with("tag"), "{highlight_name}");
+    Ok(())
+}
+
+#[test]
+fn ejs_reuses_the_erb_grammar_with_javascript() -> anyhow::Result<()> {
+    let source_code =
+        "<ul>\n<% for (const item of items) { %>\n  <li><%= item %></li>\n<% } %>\n</ul>\n";
+    let highlight_name = highlight_name_at("ejs", source_code, "const")?.unwrap();
+    assert!(highlight_name.starts_with("keyword"), "{highlight_name}");
+    let highlight_name = highlight_name_at("ejs", source_code, "li")?.unwrap();
+    assert!(highlight_name.starts_with("tag"), "{highlight_name}");
+    Ok(())
+}
+
+#[test]
+fn jinja_injects_html_around_template_tags() -> anyhow::Result<()> {
+    // The grammar is `FromSource`, which is only available after `ki @ grammar build`
+    if crate::config::from_extension("j2")
+        .and_then(|language| language.tree_sitter_language())
+        .is_none()
+    {
+        return Ok(());
+    }
+    let source_code =
+        "<ul>\n{% for item in items %}\n  <li>{{ item.name }}</li>\n{% endfor %}\n</ul>\n";
+    let highlight_name = highlight_name_at("j2", source_code, "li")?.unwrap();
+    assert!(highlight_name.starts_with("tag"), "{highlight_name}");
+    let highlight_name = highlight_name_at("j2", source_code, "{{")?.unwrap();
+    assert!(
+        highlight_name.starts_with("punctuation"),
+        "{highlight_name}"
+    );
+    let highlight_name = highlight_name_at("j2", source_code, "for")?.unwrap();
+    assert!(highlight_name.starts_with("keyword"), "{highlight_name}");
+    Ok(())
+}
+
+#[test]
+fn styled_components_template_injects_css() -> anyhow::Result<()> {
+    let source_code = "const Button = styled.div`\n  color: red;\n`;\n";
+    assert_eq!(
+        highlight_name_at("js", source_code, "color")?,
+        Some("property")
+    );
+    let source_code = "const Button = styled(Link)`\n  color: red;\n`;\n";
+    assert_eq!(
+        highlight_name_at("tsx", source_code, "color")?,
+        Some("property")
+    );
+
+    // Template literals of other tags are not CSS
+    let source_code = "const query = sql`\n  color: red;\n`;\n";
+    assert_eq!(
+        highlight_name_at("js", source_code, "color")?,
+        Some("string")
+    );
+    Ok(())
+}
+
+#[test]
+fn ruby_sql_heredoc_injects_sql() -> anyhow::Result<()> {
+    let source_code = "script = <<~BASH\n  if true; then echo hi; fi\nBASH\n";
+    let highlight_name = highlight_name_at("rb", source_code, "if")?.unwrap();
+    assert!(highlight_name.starts_with("keyword"), "{highlight_name}");
+
+    // The SQL grammar is `FromSource`, which is only available after `ki @ grammar build`
+    if crate::config::from_extension("sql")
+        .and_then(|language| language.tree_sitter_language())
+        .is_none()
+    {
+        return Ok(());
+    }
+    let source_code = "query = <<~SQL\n  SELECT * FROM users\nSQL\n";
+    let highlight_name = highlight_name_at("rb", source_code, "SELECT")?.unwrap();
+    assert!(highlight_name.starts_with("keyword"), "{highlight_name}");
+    Ok(())
+}
+
+#[test]
+fn c_inline_assembly_injects_asm() -> anyhow::Result<()> {
+    let source_code = "void f(int x, int y) {\n  asm(\"mov %0, %1\" : \"=r\"(x) : \"r\"(y));\n}\n";
+    for extension in ["c", "cpp"] {
+        let highlight_name = highlight_name_at(extension, source_code, "mov")?.unwrap();
+        assert!(highlight_name.starts_with("function"), "{highlight_name}");
+
+        // Constraints are not assembly
+        assert_eq!(
+            highlight_name_at(extension, source_code, "=r")?,
+            Some("string")
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn nested_rules_are_highlighted() -> anyhow::Result<()> {
+    let source_code = ".card {\n  .title {\n    color: red;\n  }\n}\n";
+    assert_eq!(
+        highlight_name_at("css", source_code, "color")?,
+        Some("property")
+    );
+
+    // The SCSS grammar is `FromSource`, which is only available after `ki @ grammar build`
+    if crate::config::from_extension("scss")
+        .and_then(|language| language.tree_sitter_language())
+        .is_none()
+    {
+        return Ok(());
+    }
+    // The pinned revision names its comments `js_comment`, which the nvim-treesitter query does not know,
+    // so SCSS falls back to the query of the grammar, which highlights the syntax that SCSS adds to CSS
+    let source_code =
+        "$gap: 4px;\n.card {\n  &:hover {\n    @include rounded;\n    margin: $gap;\n  }\n}\n";
+    assert_eq!(
+        highlight_name_at("scss", source_code, "rounded")?,
+        Some("function")
+    );
+    assert_eq!(
+        highlight_name_at("scss", source_code, "@include")?,
+        Some("keyword.import")
+    );
+    Ok(())
+}
+
+#[test]
+fn regex_literals_inject_regex() -> anyhow::Result<()> {
+    let source_code = "const r = /[a-z]+/;\nconst s = \"(a-z)\";\n";
+    let highlight_name = highlight_name_at("js", source_code, "[")?.unwrap();
+    assert!(
+        highlight_name.starts_with("punctuation"),
+        "{highlight_name}"
+    );
+
+    // Other strings are not regexes
+    assert_eq!(
+        highlight_name_at("js", source_code, "(a-z)")?,
+        Some("string")
+    );
+
+    let source_code =
+        "fn main() {\n    Regex::new(r\"[a-z]+\");\n    String::from(\"(a-z)\");\n}\n";
+    let highlight_name = highlight_name_at("rs", source_code, "[")?.unwrap();
+    assert!(
+        highlight_name.starts_with("punctuation"),
+        "{highlight_name}"
+    );
+    assert_eq!(
+        highlight_name_at("rs", source_code, "(a-z)")?,
+        Some("string")
+    );
+    Ok(())
+}
+
+#[test]
+fn markdown_inline_content_is_highlighted() -> anyhow::Result<()> {
+    let source_code = "# Title\n\nA **bold** word and a [link](https://example.com).\n";
+    assert_eq!(
+        highlight_name_at("md", source_code, "bold")?,
+        Some("markup.strong")
+    );
+    let highlight_name = highlight_name_at("md", source_code, "link")?.unwrap();
+    assert!(
+        highlight_name.starts_with("markup.link"),
+        "{highlight_name}"
+    );
+    Ok(())
+}
+
+#[test]
+fn zig_c_import_injects_c() -> anyhow::Result<()> {
+    let source_code = "const c = @cImport({\n    @cInclude(\"stdio.h\");\n    @cDefine(\"MAX_LEN\", \"sizeof(int) * 4\");\n});\n";
+    let highlight_name = highlight_name_at("zig", source_code, "sizeof")?.unwrap();
+    assert!(highlight_name.starts_with("keyword"), "{highlight_name}");
+
+    // Header names and macro names are not C
+    assert_eq!(
+        highlight_name_at("zig", source_code, "stdio")?,
+        Some("string")
+    );
+    assert_eq!(
+        highlight_name_at("zig", source_code, "MAX_LEN")?,
+        Some("string")
+    );
+    Ok(())
+}
+
+#[test]
+fn rustdoc_fence_injects_rust() -> anyhow::Result<()> {
+    let source_code = "/// Adds one.\n///\n/// ```rust\n/// let two = add_one(1);\n/// ```\nfn add_one(x: i32) -> i32 {\n    x + 1\n}\n\n// ```rust\n// let ordinary = 1;\n// ```\n";
+    let highlight_name = highlight_name_at("rs", source_code, "let two")?.unwrap();
+    assert!(highlight_name.starts_with("keyword"), "{highlight_name}");
+
+    // Ordinary comments are not injected
+    let highlight_name = highlight_name_at("rs", source_code, "let ordinary")?.unwrap();
+    assert!(highlight_name.starts_with("comment"), "{highlight_name}");
+    Ok(())
+}
+
+#[test]
+fn injections_beyond_max_injection_depth_are_not_highlighted() -> anyhow::Result<()> {
+    let source_code = "steps:\n  - run: echo hi\n";
+    let highlight_name =
+        highlight_name_at_with_max_injection_depth("yaml", source_code, "echo", 1)?.unwrap();
+    assert!(highlight_name.starts_with("function"), "{highlight_name}");
+
+    // The injected Bash is one level deep, so it falls back to the YAML highlight
+    assert_eq!(
+        highlight_name_at_with_max_injection_depth("yaml", source_code, "echo", 0)?,
+        Some("string")
+    );
+    Ok(())
+}
+
+#[test]
+fn cyclic_injections_are_bounded_by_max_injection_depth() -> anyhow::Result<()> {
+    // Markdown → Rust → Markdown (doc comment) → Rust, which could go on forever
+    // The outer fence is made of tildes, so that it is not closed by the fence of the doc comment
+    let source_code = "~~~rust\n/// ```rust\n/// let deepest = 1;\n/// ```\nfn f() {}\n~~~\n";
+    let highlight_name =
+        highlight_name_at_with_max_injection_depth("md", source_code, "let deepest", 3)?.unwrap();
+    assert!(highlight_name.starts_with("keyword"), "{highlight_name}");
+
+    // The outer Rust is still highlighted, but the Markdown of its doc comment is two levels deep,
+    // so the innermost Rust is not injected
+    let highlight_name =
+        highlight_name_at_with_max_injection_depth("md", source_code, "fn f", 1)?.unwrap();
+    assert!(highlight_name.starts_with("keyword"), "{highlight_name}");
+    let highlight_name =
+        highlight_name_at_with_max_injection_depth("md", source_code, "let deepest", 1)?;
+    assert!(
+        !highlight_name.is_some_and(|name| name.starts_with("keyword")),
+        "{highlight_name:?}"
+    );
+    Ok(())
+}
+
+#[test]
+fn haskell_parameter_shadowing_top_level_declaration_is_local() -> anyhow::Result<()> {
+    let source_code = "x = 1\n\nf x = x + 1\n";
+    // The `x` in the body of `f` refers to the parameter instead of the top-level `x`
+    assert_eq!(
+        highlight_name_at("hs", source_code, "x + 1")?,
+        Some("variable.parameter")
+    );
+    Ok(())
+}
+
+#[test]
+fn nix_write_shell_script_injects_bash() -> anyhow::Result<()> {
+    let source_code = r#"{ pkgs }:
+{
+  meta.description = ''
+    then
+  '';
+  greet = pkgs.writeShellScript "then" ''
+    if [ -n "$1" ]; then
+      echo ${pkgs.hello}/bin/hello
+    fi
+  '';
+  build = writeShellScriptBin "build" "while true; do cargo build; done";
+}
+"#;
+    // The description is not a script
+    assert_eq!(
+        highlight_name_at("nix", source_code, "then")?,
+        Some("string")
+    );
+    // The name of the script is not a part of the script
+    assert_eq!(
+        highlight_name_at("nix", source_code, "\"then\"")?,
+        Some("string")
+    );
+    for keyword in ["if [", "fi\n", "while"] {
+        let highlight_name = highlight_name_at("nix", source_code, keyword)?.unwrap();
+        assert!(
+            highlight_name.starts_with("keyword"),
+            "{keyword}: {highlight_name}"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn only_the_configs_of_invalidated_grammars_are_evicted() -> anyhow::Result<()> {
+    let mut highlight_configs = super::HighlightConfigs::new();
+    for extension in ["rs", "json"] {
+        let language = crate::config::from_extension(extension).unwrap();
+        highlight_configs.load(&language.highlight_config_id().unwrap(), &language)?;
+    }
+    highlight_configs
+        .0
+        .insert("rust:build.rs".to_string(), None);
+
+    crate::config::invalidate_grammar_caches(["rust".to_string()].into());
+    highlight_configs.invalidate_if_languages_reloaded();
+
+    assert!(!highlight_configs.0.contains_key("rust"));
+    assert!(!highlight_configs.0.contains_key("rust:build.rs"));
+    assert!(highlight_configs.0.contains_key("json"));
+    Ok(())
+}
+
+#[test]
+fn c_header_with_template_is_highlighted_as_cpp() -> anyhow::Result<()> {
+    let source_code = "template<typename T>\nclass Box {\n  T value;\n};\n";
+    let c = crate::config::languages().get("c").unwrap().clone();
+    let mut highlight_configs = super::HighlightConfigs::new();
+    let highlighted_spans = highlight_configs.highlight_with_max_injection_depth(
+        c,
+        source_code,
+        &std::sync::atomic::AtomicUsize::new(0),
+        4,
+    )?;
+    assert!(highlight_configs.0.contains_key("cpp"));
+    assert!(!highlight_configs.0.contains_key("c"));
+
+    let highlight_name: &str = highlighted_spans
+        .0
+        .into_iter()
+        .find(|span| span.byte_range.contains(&0))
+        .and_then(|span| match span.style_key {
+            StyleKey::Syntax(group) => group.to_highlight_name().map(|name| name.into()),
+            _ => None,
+        })
+        .unwrap();
+    assert!(highlight_name.starts_with("keyword"), "{highlight_name}");
+    Ok(())
+}
@@ -1,6 +1,6 @@
 use convert_case::Casing;
 use itertools::Itertools;
-use shared::process_command::ProcessCommand;
+use shared::{language::Language, process_command::ProcessCommand};
 
 use crate::{
     clipboard::CopiedTexts, selection_mode::NamingConventionAgnostic, soft_wrap::soft_wrap,
@@ -15,8 +15,8 @@ pub enum Transformation {
     ReplaceWithCopiedText { copied_texts: CopiedTexts },
     RegexReplace { regex: MyRegex, replacement: String },
     NamingConventionAgnosticReplace { search: String, replacement: String },
-    ToggleLineComment { prefix: String },
-    ToggleBlockComment { open: String, close: String },
+    ToggleLineComment { language: Box<Language> },
+    ToggleBlockComment { language: Box<Language> },
 }
 
 impl std::fmt::Display for Transformation {
@@ -47,10 +47,13 @@ impl std::fmt::Display for Transformation {
                 f,
                 "Naming convention-Agnostic: Replace `{search}` with `{replacement}`",
             ),
-            Transformation::ToggleLineComment { prefix } => {
-                write!(f, "Toggle Line Comment `{prefix}`")
-            }
-            Transformation::ToggleBlockComment { open, close } => {
+            Transformation::ToggleLineComment { language } => write!(
+                f,
+                "Toggle Line Comment `{}`",
+                language.line_comment_prefix().unwrap_or_default()
+            ),
+            Transformation::ToggleBlockComment { language } => {
+                let (open, close) = language.block_comment_affixes().unwrap_or_default();
                 write!(f, "Toggle Block Comment `{open} {close}`")
             }
         }
@@ -105,21 +108,11 @@ impl Transformation {
                 search,
                 replacement,
             } => NamingConventionAgnostic::replace(&string, search, replacement),
-            Transformation::ToggleLineComment { prefix } => Ok(if string.starts_with(prefix) {
-                string.trim_start_matches(prefix).trim_start().to_string()
-            } else {
-                format!("{prefix} {string}")
-            }),
-            Transformation::ToggleBlockComment { open, close } => {
-                Ok(if string.starts_with(open) && string.ends_with(close) {
-                    string
-                        .trim_start_matches(open)
-                        .trim_end_matches(close)
-                        .trim()
-                        .to_string()
-                } else {
-                    format!("{open} {string} {close}")
-                })
+            Transformation::ToggleLineComment { language } => Ok(language
+                .toggle_line_comment(&string.split('\n').collect_vec())
+                .join("\n")),
+            Transformation::ToggleBlockComment { language } => {
+                Ok(language.toggle_block_comment(&string))
             }
         }
     }
@@ -162,7 +155,7 @@ who lives in a pineapple under the sea? Spongebob Squarepants! absorbent and yel
     #[test]
     fn toggle_line_comment() {
         let transformation = Transformation::ToggleLineComment {
-            prefix: "//".to_string(),
+            language: Box::new(crate::config::from_extension("rs").unwrap()),
         };
         assert_eq!(
             transformation.apply(0, "hello".to_string()).unwrap(),
@@ -176,8 +169,7 @@ who lives in a pineapple under the sea? Spongebob Squarepants! absorbent and yel
     #[test]
     fn toggle_block_comment() {
         let transformation = Transformation::ToggleBlockComment {
-            open: "/*".to_string(),
-            close: "*/".to_string(),
+            language: Box::new(crate::config::from_extension("rs").unwrap()),
         };
         assert_eq!(
             transformation.apply(0, "hello".to_string()).unwrap(),